@@ -0,0 +1,134 @@
+use bytes::Bytes;
+
+use super::{ok, parse_integer, Session};
+use crate::error::{CommandError, CommandResult};
+use crate::resp::{Protocol, Value};
+
+pub fn ping(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    if session.in_subscriber_mode() {
+        // Subscribed clients expect every reply to be an array.
+        let message = match args.first() {
+            Some(message) => message.as_bytes()?,
+            None => Bytes::new(),
+        };
+        return Ok(Value::array(vec![
+            Value::bulk(Bytes::from_static(b"pong")),
+            Value::bulk(message),
+        ]));
+    }
+
+    match args.first() {
+        Some(message) => Ok(Value::bulk(message.as_bytes()?)),
+        None => Ok(Value::String("PONG".to_string())),
+    }
+}
+
+pub fn echo(args: &[Value], _session: &mut Session) -> CommandResult<Value> {
+    Ok(Value::bulk(args[0].as_bytes()?))
+}
+
+/// Replies OK, after which the connection is closed.
+pub fn quit(_args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    session.is_quitting = true;
+    Ok(ok())
+}
+
+pub fn reset(_args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    session.reset();
+    Ok(Value::String("RESET".to_string()))
+}
+
+pub fn select(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    match parse_integer(&args[0])? {
+        index if index >= 0 && (index as usize) < session.server.dbs.len() => {
+            session.db_index = index as usize;
+            Ok(ok())
+        }
+        _ => Err(CommandError::err("DB index is out of range")),
+    }
+}
+
+pub fn hello(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let protocol = match args.first().map(parse_integer) {
+        None => session.protocol,
+        Some(Ok(2)) => Protocol::Resp2,
+        Some(Ok(3)) => Protocol::Resp3,
+        Some(Ok(_)) => {
+            return Err(CommandError::Other(
+                "NOPROTO unsupported protocol version".to_string(),
+            ))
+        }
+        Some(Err(_)) => {
+            return Err(CommandError::err(
+                "Protocol version is not an integer or out of range",
+            ))
+        }
+    };
+    session.protocol = protocol;
+
+    let field = |name: &str| Value::bulk(Bytes::from(name.to_string()));
+    let role = match session.server.config.read().unwrap().replicaof {
+        Some(_) => "slave",
+        None => "master",
+    };
+    Ok(Value::map(
+        vec![
+            (field("server"), field("redis")),
+            (field("version"), field(env!("CARGO_PKG_VERSION"))),
+            (
+                field("proto"),
+                Value::Number(match protocol {
+                    Protocol::Resp2 => 2,
+                    Protocol::Resp3 => 3,
+                }),
+            ),
+            (field("id"), Value::Number(session.id as i64)),
+            (field("mode"), field("standalone")),
+            (field("role"), field(role)),
+            (field("modules"), Value::array(vec![])),
+        ],
+        protocol,
+    ))
+}
+
+pub fn client(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let subcommand = args[0].as_str()?.to_ascii_uppercase();
+    match (subcommand.as_str(), args.len()) {
+        ("ID", 1) => Ok(Value::Number(session.id as i64)),
+        ("GETNAME", 1) => Ok(Value::bulk(Bytes::from(session.name.clone()))),
+        ("SETNAME", 2) => {
+            let name = args[1].as_string()?;
+            // A name has to read as a single word wherever it is listed.
+            if name.chars().any(|c| !c.is_ascii_graphic()) {
+                return Err(CommandError::err(
+                    "Client names cannot contain spaces, newlines or special characters.",
+                ));
+            }
+            session.set_name(name);
+            Ok(ok())
+        }
+        ("LIST", 1) => {
+            let clients = session.server.clients.lock().unwrap();
+            let list = clients
+                .iter()
+                .map(|(id, client)| {
+                    format!(
+                        "id={} addr={} name={} age={}\n",
+                        id,
+                        client.addr,
+                        client.name,
+                        client.connected_at.elapsed().as_secs()
+                    )
+                })
+                .collect::<String>();
+            Ok(Value::bulk(Bytes::from(list)))
+        }
+        ("ID", _) | ("GETNAME", _) | ("SETNAME", _) | ("LIST", _) => Err(
+            CommandError::WrongArgCount(format!("client|{}", subcommand.to_ascii_lowercase())),
+        ),
+        _ => Err(CommandError::Other(format!(
+            "ERR unknown subcommand '{}'. Try CLIENT HELP.",
+            args[0].as_str()?
+        ))),
+    }
+}
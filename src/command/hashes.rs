@@ -0,0 +1,97 @@
+use super::Session;
+use crate::error::{CommandError, CommandResult};
+use crate::resp::Value;
+
+pub fn hset(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    // Fields come in pairs with their values.
+    if args[1..].len() % 2 == 1 {
+        return Err(CommandError::WrongArgCount("hset".to_string()));
+    }
+
+    let fields = args[1..]
+        .chunks(2)
+        .map(|pair| Ok((pair[0].as_bytes()?, pair[1].as_bytes()?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let added = session.db().hash_set(args[0].as_str()?, fields)?;
+    Ok(Value::Number(added as i64))
+}
+
+pub fn hget(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    match session
+        .db()
+        .hash_get(args[0].as_str()?, &args[1].as_bytes()?)?
+    {
+        Some(value) => Ok(Value::bulk(value)),
+        None => Ok(Value::Null),
+    }
+}
+
+pub fn hmget(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let fields = args[1..]
+        .iter()
+        .map(|field| field.as_bytes())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let values = session
+        .db()
+        .hash_get_many(args[0].as_str()?, &fields)?
+        .into_iter()
+        .map(|value| match value {
+            Some(value) => Value::bulk(value),
+            None => Value::Null,
+        })
+        .collect();
+    Ok(Value::array(values))
+}
+
+pub fn hexists(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let exists = session
+        .db()
+        .hash_get(args[0].as_str()?, &args[1].as_bytes()?)?
+        .is_some();
+    Ok(Value::Number(exists as i64))
+}
+
+pub fn hlen(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let len = session.db().hash_len(args[0].as_str()?)?;
+    Ok(Value::Number(len as i64))
+}
+
+pub fn hkeys(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let pairs = session.db().hash_get_all(args[0].as_str()?)?;
+    Ok(Value::array(
+        pairs
+            .into_iter()
+            .map(|(field, _)| Value::bulk(field))
+            .collect(),
+    ))
+}
+
+pub fn hvals(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let pairs = session.db().hash_get_all(args[0].as_str()?)?;
+    Ok(Value::array(
+        pairs
+            .into_iter()
+            .map(|(_, value)| Value::bulk(value))
+            .collect(),
+    ))
+}
+
+pub fn hdel(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let fields = args[1..]
+        .iter()
+        .map(|field| field.as_bytes())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let removed = session.db().hash_delete(args[0].as_str()?, &fields)?;
+    Ok(Value::Number(removed as i64))
+}
+
+pub fn hgetall(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let pairs = session.db().hash_get_all(args[0].as_str()?)?;
+    Ok(Value::map(
+        pairs
+            .into_iter()
+            .map(|(field, value)| (Value::bulk(field), Value::bulk(value)))
+            .collect(),
+        session.protocol,
+    ))
+}
@@ -0,0 +1,232 @@
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+
+use super::{parse_integer, Session};
+use crate::db::{instant_from_unix, Ttl};
+use crate::error::{CommandError, CommandResult};
+use crate::resp::Value;
+
+/// Options accepted by SCAN.
+struct ScanOptions {
+    pattern: Option<String>,
+    count: usize,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            pattern: None,
+            count: 10,
+        }
+    }
+}
+
+fn parse_scan_options(options: &[Value]) -> CommandResult<ScanOptions> {
+    let mut scan_options = ScanOptions::default();
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        let argument = options.next().ok_or(CommandError::SyntaxError)?;
+        match option.as_str()?.to_ascii_uppercase().as_str() {
+            "MATCH" => scan_options.pattern = Some(argument.as_string()?),
+            "COUNT" => match parse_integer(argument)? {
+                count if count < 1 => return Err(CommandError::SyntaxError),
+                count => scan_options.count = count as usize,
+            },
+            _ => return Err(CommandError::SyntaxError),
+        }
+    }
+
+    Ok(scan_options)
+}
+
+pub fn del(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let mut deleted = 0;
+    for key in args {
+        if session.db().remove(key.as_str()?) {
+            deleted += 1;
+        }
+    }
+    Ok(Value::Number(deleted))
+}
+
+pub fn exists(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let mut found = 0;
+    for key in args {
+        if session.db().contains(key.as_str()?) {
+            found += 1;
+        }
+    }
+    Ok(Value::Number(found))
+}
+
+pub fn type_(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let name = session.db().type_of(args[0].as_str()?).unwrap_or("none");
+    Ok(Value::String(name.to_string()))
+}
+
+pub fn copy(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let source = args[0].as_str()?;
+    let destination = args[1].as_string()?;
+
+    let mut db_index = session.db_index;
+    let mut replace = false;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        match option.as_str()?.to_ascii_uppercase().as_str() {
+            "DB" => {
+                let index = parse_integer(options.next().ok_or(CommandError::SyntaxError)?)?;
+                if index < 0 || index as usize >= session.server.dbs.len() {
+                    return Err(CommandError::err("DB index is out of range"));
+                }
+                db_index = index as usize;
+            }
+            "REPLACE" => replace = true,
+            _ => return Err(CommandError::SyntaxError),
+        }
+    }
+    if db_index == session.db_index && source == destination {
+        return Err(CommandError::err(
+            "source and destination objects are the same",
+        ));
+    }
+
+    let copied = match session.db().dump(source) {
+        Some((value, expires_at)) => {
+            session.server.dbs[db_index].restore(destination, value, expires_at, replace)
+        }
+        None => false,
+    };
+    Ok(Value::Number(copied as i64))
+}
+
+pub fn object(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let subcommand = args[0].as_str()?.to_ascii_uppercase();
+    match subcommand.as_str() {
+        "ENCODING" => {
+            if args.len() != 2 {
+                return Err(CommandError::WrongArgCount("object|encoding".to_string()));
+            }
+
+            match session.db().encoding_of(args[1].as_str()?) {
+                Some(encoding) => Ok(Value::bulk(Bytes::from(encoding))),
+                None => Err(CommandError::err("no such key")),
+            }
+        }
+        _ => Err(CommandError::Other(format!(
+            "ERR unknown subcommand '{}'. Try OBJECT HELP.",
+            args[0].as_str()?
+        ))),
+    }
+}
+
+pub fn keys(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let keys = session
+        .db()
+        .keys(args[0].as_str()?)
+        .into_iter()
+        .map(|key| Value::bulk(Bytes::from(key)))
+        .collect();
+    Ok(Value::array(keys))
+}
+
+pub fn randomkey(_args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    match session.db().random_key() {
+        Some(key) => Ok(Value::bulk(Bytes::from(key))),
+        None => Ok(Value::Null),
+    }
+}
+
+pub fn scan(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let cursor = args[0]
+        .as_str()?
+        .parse::<usize>()
+        .map_err(|_| CommandError::err("invalid cursor"))?;
+    let options = parse_scan_options(&args[1..])?;
+    let (next, keys) = session
+        .db()
+        .scan(cursor, options.pattern.as_deref(), options.count);
+    Ok(Value::array(vec![
+        Value::bulk(Bytes::from(next.to_string())),
+        Value::array(
+            keys.into_iter()
+                .map(|key| Value::bulk(Bytes::from(key)))
+                .collect(),
+        ),
+    ]))
+}
+
+/// Replies with the time to live of a key, rounded to `unit`.
+fn ttl_in(args: &[Value], session: &mut Session, unit: Duration) -> CommandResult<Value> {
+    let ttl = match session.db().ttl(args[0].as_str()?) {
+        Ttl::Missing => -2,
+        Ttl::Persistent => -1,
+        Ttl::Remaining(remaining) => {
+            let unit = unit.as_millis();
+            ((remaining.as_millis() + unit / 2) / unit) as i64
+        }
+    };
+    Ok(Value::Number(ttl))
+}
+
+pub fn ttl(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    ttl_in(args, session, Duration::from_secs(1))
+}
+
+pub fn pttl(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    ttl_in(args, session, Duration::from_millis(1))
+}
+
+/// Sets a timeout on a key, with the amount converted by `timeout`. A
+/// non-positive timeout expires the key right away.
+fn expire_after(
+    name: &str,
+    args: &[Value],
+    session: &mut Session,
+    timeout: fn(u64) -> Duration,
+) -> CommandResult<Value> {
+    let amount = parse_integer(&args[1])?.max(0) as u64;
+    let expires_at = Instant::now().checked_add(timeout(amount)).ok_or_else(|| {
+        CommandError::Other(format!("ERR invalid expire time in '{}' command", name))
+    })?;
+    let expired = session.db().expire(args[0].as_str()?, expires_at);
+    Ok(Value::Number(expired as i64))
+}
+
+pub fn expire(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    expire_after("expire", args, session, Duration::from_secs)
+}
+
+pub fn pexpire(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    expire_after("pexpire", args, session, Duration::from_millis)
+}
+
+/// Sets a key to expire at a Unix time, with the amount converted by
+/// `timestamp`. A time in the past expires the key right away.
+fn expire_at(
+    name: &str,
+    args: &[Value],
+    session: &mut Session,
+    timestamp: fn(u64) -> Duration,
+) -> CommandResult<Value> {
+    let amount = parse_integer(&args[1])?.max(0) as u64;
+    let expires_at = instant_from_unix(timestamp(amount)).ok_or_else(|| {
+        CommandError::Other(format!("ERR invalid expire time in '{}' command", name))
+    })?;
+    let expired = session.db().expire(args[0].as_str()?, expires_at);
+    Ok(Value::Number(expired as i64))
+}
+
+pub fn expireat(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    expire_at("expireat", args, session, Duration::from_secs)
+}
+
+pub fn pexpireat(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    expire_at("pexpireat", args, session, Duration::from_millis)
+}
+
+pub fn persist(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let persisted = session.db().persist(args[0].as_str()?);
+    Ok(Value::Number(persisted as i64))
+}
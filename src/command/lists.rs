@@ -0,0 +1,247 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use super::{ok, parse_count, parse_integer, Command, CommandFuture, Session};
+use crate::db::ListEnd;
+use crate::error::{CommandError, CommandResult};
+use crate::resp::Value;
+
+/// Parses the timeout of a blocking command, in seconds, where zero means
+/// waiting forever.
+fn parse_timeout(value: &Value) -> CommandResult<Option<Duration>> {
+    let timeout = match value.as_str()?.parse::<f64>() {
+        Ok(timeout) if timeout.is_finite() => timeout,
+        _ => return Err(CommandError::err("timeout is not a float or out of range")),
+    };
+    if timeout < 0.0 {
+        return Err(CommandError::err("timeout is negative"));
+    }
+
+    if timeout == 0.0 {
+        Ok(None)
+    } else {
+        Ok(Some(Duration::from_secs_f64(timeout)))
+    }
+}
+
+fn push(args: &[Value], session: &mut Session, end: ListEnd) -> CommandResult<Value> {
+    let values = args[1..]
+        .iter()
+        .map(|value| value.as_bytes())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let len = session.db().push(args[0].as_str()?, values, end)?;
+    Ok(Value::Number(len as i64))
+}
+
+pub fn lpush(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    push(args, session, ListEnd::Left)
+}
+
+pub fn rpush(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    push(args, session, ListEnd::Right)
+}
+
+/// Pops a single element, or an array of up to the given count of them.
+fn pop(args: &[Value], session: &mut Session, end: ListEnd) -> CommandResult<Value> {
+    let key = args[0].as_str()?;
+    match args.get(1) {
+        None => match session.db().pop(key, end, 1)? {
+            Some(mut popped) => Ok(Value::bulk(popped.remove(0))),
+            None => Ok(Value::Null),
+        },
+        Some(count) => match session.db().pop(key, end, parse_count(count)?)? {
+            Some(popped) => Ok(Value::array(popped.into_iter().map(Value::bulk).collect())),
+            None => Ok(Value::NullArray),
+        },
+    }
+}
+
+pub fn lpop(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    pop(args, session, ListEnd::Left)
+}
+
+pub fn rpop(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    pop(args, session, ListEnd::Right)
+}
+
+/// BLPOP and BRPOP, which wait for an element to be pushed when every list
+/// is empty.
+pub struct BlockingPop {
+    end: ListEnd,
+}
+
+impl BlockingPop {
+    pub fn left() -> Self {
+        BlockingPop { end: ListEnd::Left }
+    }
+
+    pub fn right() -> Self {
+        BlockingPop {
+            end: ListEnd::Right,
+        }
+    }
+}
+
+impl Command for BlockingPop {
+    fn execute<'a>(&'a self, args: &'a [Value], session: &'a mut Session) -> CommandFuture<'a> {
+        Box::pin(async move {
+            let (timeout, keys) = args.split_last().unwrap();
+            let keys = keys
+                .iter()
+                .map(|key| key.as_string())
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let timeout = parse_timeout(timeout)?;
+            match session.db().blocking_pop(&keys, self.end, timeout).await? {
+                Some((key, element)) => {
+                    // Replicas must not block, so they pop from the list
+                    // that was served.
+                    let pop = match self.end {
+                        ListEnd::Left => "LPOP",
+                        ListEnd::Right => "RPOP",
+                    };
+                    let command = vec![
+                        Value::bulk(Bytes::from_static(pop.as_bytes())),
+                        Value::bulk(Bytes::from(key.clone())),
+                    ];
+                    session
+                        .server
+                        .replication
+                        .write()
+                        .unwrap()
+                        .propagate(session.db_index, Value::array(command));
+
+                    Ok(Value::array(vec![
+                        Value::bulk(Bytes::from(key)),
+                        Value::bulk(element),
+                    ]))
+                }
+                None => Ok(Value::NullArray),
+            }
+        })
+    }
+
+    fn is_blocking(&self) -> bool {
+        true
+    }
+}
+
+fn parse_list_end(value: &Value) -> CommandResult<ListEnd> {
+    match value.as_str()?.to_ascii_uppercase().as_str() {
+        "LEFT" => Ok(ListEnd::Left),
+        "RIGHT" => Ok(ListEnd::Right),
+        _ => Err(CommandError::SyntaxError),
+    }
+}
+
+fn move_element(
+    args: &[Value],
+    session: &mut Session,
+    from: ListEnd,
+    to: ListEnd,
+) -> CommandResult<Value> {
+    let moved = session
+        .db()
+        .list_move(args[0].as_str()?, args[1].as_str()?, from, to)?;
+    Ok(moved.map_or(Value::Null, Value::bulk))
+}
+
+pub fn rpoplpush(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    move_element(args, session, ListEnd::Right, ListEnd::Left)
+}
+
+/// LMOVE source destination LEFT|RIGHT LEFT|RIGHT
+pub fn lmove(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let from = parse_list_end(&args[2])?;
+    let to = parse_list_end(&args[3])?;
+    move_element(args, session, from, to)
+}
+
+pub fn llen(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let len = session.db().list_len(args[0].as_str()?)?;
+    Ok(Value::Number(len as i64))
+}
+
+pub fn lrange(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let start = parse_integer(&args[1])?;
+    let stop = parse_integer(&args[2])?;
+    let elements = session.db().list_range(args[0].as_str()?, start, stop)?;
+    Ok(Value::array(
+        elements.into_iter().map(Value::bulk).collect(),
+    ))
+}
+
+pub fn lindex(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let index = parse_integer(&args[1])?;
+    match session.db().list_index(args[0].as_str()?, index)? {
+        Some(element) => Ok(Value::bulk(element)),
+        None => Ok(Value::Null),
+    }
+}
+
+/// LPOS key element [RANK rank] [COUNT num] [MAXLEN len], replying with a
+/// single index, or an array of them when given a count.
+pub fn lpos(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let mut rank = 1;
+    let mut count = None;
+    let mut maxlen = 0;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        let value = match options.next() {
+            Some(value) => value,
+            None => return Err(CommandError::SyntaxError),
+        };
+        match option.as_str()?.to_ascii_uppercase().as_str() {
+            "RANK" => {
+                rank = match parse_integer(value)? {
+                    0 => return Err(CommandError::err(
+                        "RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list",
+                    )),
+                    i64::MIN => return Err(CommandError::err(
+                        "value is out of range, value must between -9223372036854775807 and 9223372036854775807",
+                    )),
+                    rank => rank,
+                }
+            }
+            "COUNT" => match parse_integer(value)? {
+                num if num < 0 => return Err(CommandError::err("COUNT can't be negative")),
+                num => count = Some(num as usize),
+            },
+            "MAXLEN" => match parse_integer(value)? {
+                len if len < 0 => return Err(CommandError::err("MAXLEN can't be negative")),
+                len => maxlen = len as usize,
+            },
+            _ => return Err(CommandError::SyntaxError),
+        }
+    }
+
+    let positions = session.db().list_positions(
+        args[0].as_str()?,
+        &args[1].as_bytes()?,
+        rank,
+        count.unwrap_or(1),
+        maxlen,
+    )?;
+    let mut positions = positions
+        .into_iter()
+        .map(|position| Value::Number(position as i64));
+    match count {
+        Some(_) => Ok(Value::array(positions.collect())),
+        None => Ok(positions.next().unwrap_or(Value::Null)),
+    }
+}
+
+pub fn lset(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let index = parse_integer(&args[1])?;
+    session
+        .db()
+        .list_set(args[0].as_str()?, index, args[2].as_bytes()?)?;
+    Ok(ok())
+}
+
+pub fn ltrim(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let start = parse_integer(&args[1])?;
+    let stop = parse_integer(&args[2])?;
+    session.db().list_trim(args[0].as_str()?, start, stop)?;
+    Ok(ok())
+}
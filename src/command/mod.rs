@@ -0,0 +1,831 @@
+mod connection;
+mod hashes;
+mod keys;
+mod lists;
+mod pubsub;
+mod replication;
+mod server;
+mod sets;
+mod sorted_sets;
+mod strings;
+mod transactions;
+
+use std::collections::HashMap;
+use std::future::{self, Future};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use bytes::Bytes;
+use tokio::stream::StreamMap;
+use tokio::sync::broadcast;
+
+use crate::db::{unix_from_instant, Db, Ttl};
+use crate::error::{CommandError, CommandResult};
+use crate::pubsub::PatternMessage;
+use crate::resp::{Protocol, Value};
+use crate::server::{ClientInfo, Server};
+
+pub use pubsub::{message, pattern_message};
+use transactions::Transaction;
+
+/// Commands a client can still run in subscriber mode.
+const SUBSCRIBED_COMMANDS: &[&str] = &[
+    "SUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "PING",
+    "QUIT",
+    "RESET",
+];
+
+/// Commands that modify the dataset, which are forwarded to replicas.
+/// Blocking pops are left out, as they forward the plain pop they ended
+/// with, and so is SPOP, which forwards the members it removed.
+const WRITE_COMMANDS: &[&str] = &[
+    "SET",
+    "SETNX",
+    "SETEX",
+    "PSETEX",
+    "GETDEL",
+    "GETSET",
+    "MSET",
+    "APPEND",
+    "SETRANGE",
+    "INCR",
+    "DECR",
+    "INCRBY",
+    "DECRBY",
+    "INCRBYFLOAT",
+    "DEL",
+    "COPY",
+    "EXPIRE",
+    "PEXPIRE",
+    "EXPIREAT",
+    "PEXPIREAT",
+    "PERSIST",
+    "LPUSH",
+    "RPUSH",
+    "LPOP",
+    "RPOP",
+    "RPOPLPUSH",
+    "LMOVE",
+    "LSET",
+    "LTRIM",
+    "HSET",
+    "HDEL",
+    "SADD",
+    "SREM",
+    "SMOVE",
+    "ZADD",
+    "ZINCRBY",
+    "ZREM",
+    "FLUSHDB",
+    "FLUSHALL",
+];
+
+/// Writes that reply nil when they changed nothing, such as a SET whose
+/// condition did not hold, and so are not forwarded then.
+const NIL_WHEN_UNCHANGED: &[&str] = &["SET", "GETDEL", "LPOP", "RPOP", "RPOPLPUSH", "LMOVE"];
+
+/// Writes that reply zero when they changed nothing, such as a DEL of a
+/// missing key, and so are not forwarded then.
+const ZERO_WHEN_UNCHANGED: &[&str] = &[
+    "SETNX",
+    "DEL",
+    "COPY",
+    "EXPIRE",
+    "PEXPIRE",
+    "EXPIREAT",
+    "PEXPIREAT",
+    "PERSIST",
+    "HDEL",
+    "SADD",
+    "SREM",
+    "SMOVE",
+    "ZREM",
+];
+
+/// Commands that run straight away rather than being queued inside a
+/// transaction.
+const TRANSACTION_COMMANDS: &[&str] = &[
+    "MULTI", "EXEC", "DISCARD", "WATCH", "UNWATCH", "QUIT", "RESET",
+];
+
+pub type CommandFuture<'a> = Pin<Box<dyn Future<Output = CommandResult<Value>> + Send + 'a>>;
+
+/// A command a client can run, producing the value replied to it.
+///
+/// Plain functions taking the arguments and the session implement it, which
+/// is how most commands are written. Only commands that need to wait
+/// implement it by hand.
+pub trait Command: Send + Sync {
+    fn execute<'a>(&'a self, args: &'a [Value], session: &'a mut Session) -> CommandFuture<'a>;
+
+    /// Whether the command may wait before replying, in which case the
+    /// replies queued ahead of it should be sent first.
+    fn is_blocking(&self) -> bool {
+        false
+    }
+}
+
+impl<F> Command for F
+where
+    F: Fn(&[Value], &mut Session) -> CommandResult<Value> + Send + Sync,
+{
+    fn execute<'a>(&'a self, args: &'a [Value], session: &'a mut Session) -> CommandFuture<'a> {
+        Box::pin(future::ready(self(args, session)))
+    }
+}
+
+/// The state of one client, along with the server it is connected to.
+pub struct Session {
+    pub server: Server,
+    /// Identifies the client for as long as the server runs.
+    pub id: u64,
+    /// Name the client gave itself with CLIENT SETNAME, empty if none.
+    pub name: String,
+    /// Index of the logical database the client has selected.
+    pub db_index: usize,
+    pub protocol: Protocol,
+    /// Port the client serves on, if it announced itself as a replica.
+    pub replica_listening_port: Option<u16>,
+    /// Set once the client asked for the replication stream with PSYNC.
+    pub is_replica: bool,
+    /// Set once the client asked to be disconnected with QUIT.
+    pub is_quitting: bool,
+    /// Messages from the channels the client subscribed to, by channel.
+    pub subscriptions: StreamMap<String, broadcast::Receiver<Bytes>>,
+    /// Messages from the channels matching the patterns the client
+    /// subscribed to, by pattern.
+    pub pattern_subscriptions: StreamMap<String, broadcast::Receiver<PatternMessage>>,
+    /// Commands queued since MULTI, if the client started a transaction.
+    pub transaction: Option<Transaction>,
+    /// Keys watched by the client, along with the index of their database.
+    watched: Vec<(usize, String)>,
+    /// Set once one of the watched keys is modified.
+    dirty: Arc<AtomicBool>,
+    replies: Vec<Value>,
+}
+
+impl Session {
+    /// Starts the session of a newly connected client, which CLIENT LIST
+    /// lists until the session is dropped.
+    pub fn new(server: Server) -> Self {
+        let id = server.next_client_id();
+        server.clients.lock().unwrap().insert(
+            id,
+            ClientInfo {
+                addr: String::new(),
+                name: String::new(),
+                connected_at: Instant::now(),
+            },
+        );
+
+        Session {
+            id,
+            name: String::new(),
+            server,
+            db_index: 0,
+            protocol: Protocol::default(),
+            replica_listening_port: None,
+            is_replica: false,
+            is_quitting: false,
+            subscriptions: StreamMap::new(),
+            pattern_subscriptions: StreamMap::new(),
+            transaction: None,
+            watched: Vec::new(),
+            dirty: Arc::default(),
+            replies: Vec::new(),
+        }
+    }
+
+    /// Records the address the client connected from.
+    pub fn set_addr(&mut self, addr: SocketAddr) {
+        if let Some(client) = self.server.clients.lock().unwrap().get_mut(&self.id) {
+            client.addr = addr.to_string();
+        }
+    }
+
+    /// Changes the name the client is listed under.
+    pub fn set_name(&mut self, name: String) {
+        if let Some(client) = self.server.clients.lock().unwrap().get_mut(&self.id) {
+            client.name = name.clone();
+        }
+        self.name = name;
+    }
+
+    /// Whether the client subscribed to any channel or pattern, which
+    /// limits the commands it can run.
+    pub fn is_subscribed(&self) -> bool {
+        self.subscription_count() > 0
+    }
+
+    /// Whether the client is in subscriber mode, where it may only run
+    /// SUBSCRIBED_COMMANDS. Only RESP2 clients enter it, as RESP3 ones can
+    /// tell published messages from replies.
+    pub fn in_subscriber_mode(&self) -> bool {
+        self.protocol == Protocol::Resp2 && self.is_subscribed()
+    }
+
+    /// How many channels and patterns the client subscribed to.
+    pub fn subscription_count(&self) -> usize {
+        self.subscriptions.len() + self.pattern_subscriptions.len()
+    }
+
+    /// Watches `key` in the selected database, so that the next EXEC aborts
+    /// if it is modified in the meantime.
+    pub fn watch(&mut self, key: String) {
+        let watched = (self.db_index, key);
+        if !self.watched.contains(&watched) {
+            self.db().watch(&watched.1, &self.dirty);
+            self.watched.push(watched);
+        }
+    }
+
+    /// Forgets every watched key.
+    pub fn unwatch(&mut self) {
+        for (index, key) in self.watched.drain(..) {
+            self.server.dbs[index].unwatch(&key, &self.dirty);
+        }
+        self.dirty.store(false, Ordering::SeqCst);
+    }
+
+    /// Puts the client back in the state it connected in, apart from its
+    /// id and name.
+    pub fn reset(&mut self) {
+        self.transaction = None;
+        self.unwatch();
+        self.subscriptions = StreamMap::new();
+        self.pattern_subscriptions = StreamMap::new();
+        self.db_index = 0;
+        self.protocol = Protocol::default();
+    }
+
+    /// Queues a reply to send ahead of the one the command returns, for
+    /// commands that reply more than once.
+    pub fn reply(&mut self, value: Value) {
+        self.replies.push(value);
+    }
+
+    /// Takes the replies queued by the last command.
+    pub fn take_replies(&mut self) -> Vec<Value> {
+        std::mem::take(&mut self.replies)
+    }
+
+    /// The database the client has selected.
+    pub fn db(&self) -> &Db {
+        &self.server.dbs[self.db_index]
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.unwatch();
+        self.server.clients.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// How many arguments a command takes, not counting its name.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    Between(usize, usize),
+}
+
+impl Arity {
+    fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Exactly(expected) => count == expected,
+            Arity::AtLeast(min) => count >= min,
+            Arity::Between(min, max) => (min..=max).contains(&count),
+        }
+    }
+}
+
+/// A registered command, along with what is checked before running it.
+pub struct CommandSpec {
+    name: &'static str,
+    arity: Arity,
+    command: Box<dyn Command>,
+}
+
+impl CommandSpec {
+    /// Runs the command, unless it was given the wrong number of arguments
+    /// or cannot run while the client is subscribed to channels. Successful
+    /// writes that changed something are forwarded to replicas.
+    pub async fn execute(&self, args: &[Value], session: &mut Session) -> CommandResult<Value> {
+        if !self.arity.accepts(args.len()) {
+            return Err(CommandError::WrongArgCount(self.name.to_ascii_lowercase()));
+        }
+        if session.in_subscriber_mode() && !SUBSCRIBED_COMMANDS.contains(&self.name) {
+            return Err(CommandError::Other(format!(
+                "ERR Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                self.name.to_ascii_lowercase()
+            )));
+        }
+
+        let reply = self.command.execute(args, session).await?;
+        if WRITE_COMMANDS.contains(&self.name) && !self.changed_nothing(&reply) {
+            let command = self.replicated(args, session)?;
+            session
+                .server
+                .replication
+                .write()
+                .unwrap()
+                .propagate(session.db_index, Value::array(command));
+        }
+
+        Ok(reply)
+    }
+
+    /// Whether `reply` shows the write left the dataset as it was, leaving
+    /// replicas nothing to apply.
+    fn changed_nothing(&self, reply: &Value) -> bool {
+        match reply {
+            Value::Null | Value::NullArray => NIL_WHEN_UNCHANGED.contains(&self.name),
+            Value::Number(0) => ZERO_WHEN_UNCHANGED.contains(&self.name),
+            _ => false,
+        }
+    }
+
+    /// The write as replicas are sent it. Relative expiries become the Unix
+    /// time the key now expires at, as replicas apply them later and their
+    /// clock would start over.
+    fn replicated(&self, args: &[Value], session: &Session) -> CommandResult<Vec<Value>> {
+        let bulk = |arg: &'static str| Value::bulk(Bytes::from_static(arg.as_bytes()));
+        let mut command = match self.name {
+            "EXPIRE" | "PEXPIRE" => vec![bulk("PEXPIREAT"), args[0].clone()],
+            "SETEX" | "PSETEX" => vec![bulk("SET"), args[0].clone(), args[2].clone(), bulk("PXAT")],
+            "SET" if args[2..].iter().any(is_relative_expiry) => {
+                vec![bulk("SET"), args[0].clone(), args[1].clone()]
+            }
+            _ => {
+                let mut command = vec![bulk(self.name)];
+                command.extend_from_slice(args);
+                return Ok(command);
+            }
+        };
+
+        // The key is gone when its expiry had already passed.
+        let key = &args[0];
+        let expires_at = match session.db().ttl(key.as_str()?) {
+            Ttl::Remaining(remaining) => unix_from_instant(Instant::now() + remaining),
+            _ => return Ok(vec![bulk("DEL"), key.clone()]),
+        };
+        let expires_at = Value::bulk(Bytes::from(expires_at.as_millis().to_string()));
+
+        if self.name == "SET" {
+            // SET keeps its other options, with PXAT in place of EX or PX.
+            let mut options = args[2..].iter();
+            while let Some(option) = options.next() {
+                if is_relative_expiry(option) {
+                    options.next();
+                    command.extend([bulk("PXAT"), expires_at.clone()]);
+                } else {
+                    command.push(option.clone());
+                }
+            }
+        } else {
+            command.push(expires_at);
+        }
+        Ok(command)
+    }
+
+    pub fn is_blocking(&self) -> bool {
+        self.command.is_blocking()
+    }
+}
+
+/// Whether `option` is SET's EX or PX, which take a time to live.
+fn is_relative_expiry(option: &Value) -> bool {
+    matches!(
+        option.as_str().map(str::to_ascii_uppercase).as_deref(),
+        Ok("EX" | "PX")
+    )
+}
+
+/// Every command the server knows, by upper-cased name.
+pub struct Registry {
+    commands: HashMap<&'static str, CommandSpec>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        let mut registry = Registry {
+            commands: HashMap::new(),
+        };
+
+        registry.register("PING", Arity::Between(0, 1), connection::ping);
+        registry.register("ECHO", Arity::Exactly(1), connection::echo);
+        registry.register("SELECT", Arity::Exactly(1), connection::select);
+        registry.register("HELLO", Arity::AtLeast(0), connection::hello);
+        registry.register("CLIENT", Arity::AtLeast(1), connection::client);
+        registry.register("QUIT", Arity::AtLeast(0), connection::quit);
+        registry.register("RESET", Arity::Exactly(0), connection::reset);
+
+        registry.register("SET", Arity::AtLeast(2), strings::set);
+        registry.register("SETNX", Arity::Exactly(2), strings::setnx);
+        registry.register("SETEX", Arity::Exactly(3), strings::setex);
+        registry.register("PSETEX", Arity::Exactly(3), strings::psetex);
+        registry.register("GET", Arity::Exactly(1), strings::get);
+        registry.register("GETDEL", Arity::Exactly(1), strings::getdel);
+        registry.register("GETSET", Arity::Exactly(2), strings::getset);
+        registry.register("MGET", Arity::AtLeast(1), strings::mget);
+        registry.register("MSET", Arity::AtLeast(2), strings::mset);
+        registry.register("APPEND", Arity::Exactly(2), strings::append);
+        registry.register("STRLEN", Arity::Exactly(1), strings::strlen);
+        registry.register("GETRANGE", Arity::Exactly(3), strings::getrange);
+        registry.register("SETRANGE", Arity::Exactly(3), strings::setrange);
+        registry.register("INCR", Arity::Exactly(1), strings::incr);
+        registry.register("DECR", Arity::Exactly(1), strings::decr);
+        registry.register("INCRBY", Arity::Exactly(2), strings::incrby);
+        registry.register("DECRBY", Arity::Exactly(2), strings::decrby);
+        registry.register("INCRBYFLOAT", Arity::Exactly(2), strings::incrbyfloat);
+
+        registry.register("DEL", Arity::AtLeast(1), keys::del);
+        registry.register("EXISTS", Arity::AtLeast(1), keys::exists);
+        registry.register("TYPE", Arity::Exactly(1), keys::type_);
+        registry.register("OBJECT", Arity::AtLeast(1), keys::object);
+        registry.register("COPY", Arity::AtLeast(2), keys::copy);
+        registry.register("KEYS", Arity::Exactly(1), keys::keys);
+        registry.register("SCAN", Arity::AtLeast(1), keys::scan);
+        registry.register("RANDOMKEY", Arity::Exactly(0), keys::randomkey);
+        registry.register("TTL", Arity::Exactly(1), keys::ttl);
+        registry.register("PTTL", Arity::Exactly(1), keys::pttl);
+        registry.register("EXPIRE", Arity::Exactly(2), keys::expire);
+        registry.register("PEXPIRE", Arity::Exactly(2), keys::pexpire);
+        registry.register("EXPIREAT", Arity::Exactly(2), keys::expireat);
+        registry.register("PEXPIREAT", Arity::Exactly(2), keys::pexpireat);
+        registry.register("PERSIST", Arity::Exactly(1), keys::persist);
+
+        registry.register("LPUSH", Arity::AtLeast(2), lists::lpush);
+        registry.register("RPUSH", Arity::AtLeast(2), lists::rpush);
+        registry.register("LPOP", Arity::Between(1, 2), lists::lpop);
+        registry.register("RPOP", Arity::Between(1, 2), lists::rpop);
+        registry.register("BLPOP", Arity::AtLeast(2), lists::BlockingPop::left());
+        registry.register("BRPOP", Arity::AtLeast(2), lists::BlockingPop::right());
+        registry.register("RPOPLPUSH", Arity::Exactly(2), lists::rpoplpush);
+        registry.register("LMOVE", Arity::Exactly(4), lists::lmove);
+        registry.register("LLEN", Arity::Exactly(1), lists::llen);
+        registry.register("LRANGE", Arity::Exactly(3), lists::lrange);
+        registry.register("LINDEX", Arity::Exactly(2), lists::lindex);
+        registry.register("LPOS", Arity::AtLeast(2), lists::lpos);
+        registry.register("LSET", Arity::Exactly(3), lists::lset);
+        registry.register("LTRIM", Arity::Exactly(3), lists::ltrim);
+
+        registry.register("HSET", Arity::AtLeast(3), hashes::hset);
+        registry.register("HGET", Arity::Exactly(2), hashes::hget);
+        registry.register("HMGET", Arity::AtLeast(2), hashes::hmget);
+        registry.register("HEXISTS", Arity::Exactly(2), hashes::hexists);
+        registry.register("HLEN", Arity::Exactly(1), hashes::hlen);
+        registry.register("HKEYS", Arity::Exactly(1), hashes::hkeys);
+        registry.register("HVALS", Arity::Exactly(1), hashes::hvals);
+        registry.register("HDEL", Arity::AtLeast(2), hashes::hdel);
+        registry.register("HGETALL", Arity::Exactly(1), hashes::hgetall);
+
+        registry.register("SADD", Arity::AtLeast(2), sets::sadd);
+        registry.register("SREM", Arity::AtLeast(2), sets::srem);
+        registry.register("SMOVE", Arity::Exactly(3), sets::smove);
+        registry.register("SPOP", Arity::Between(1, 2), sets::spop);
+        registry.register("SRANDMEMBER", Arity::Between(1, 2), sets::srandmember);
+        registry.register("SISMEMBER", Arity::Exactly(2), sets::sismember);
+        registry.register("SMEMBERS", Arity::Exactly(1), sets::smembers);
+        registry.register("SCARD", Arity::Exactly(1), sets::scard);
+        registry.register("SUNION", Arity::AtLeast(1), sets::sunion);
+        registry.register("SINTER", Arity::AtLeast(1), sets::sinter);
+        registry.register("SINTERCARD", Arity::AtLeast(2), sets::sintercard);
+        registry.register("SDIFF", Arity::AtLeast(1), sets::sdiff);
+
+        registry.register("ZADD", Arity::AtLeast(3), sorted_sets::zadd);
+        registry.register("ZSCORE", Arity::Exactly(2), sorted_sets::zscore);
+        registry.register("ZINCRBY", Arity::Exactly(3), sorted_sets::zincrby);
+        registry.register("ZRANK", Arity::Exactly(2), sorted_sets::zrank);
+        registry.register("ZREVRANK", Arity::Exactly(2), sorted_sets::zrevrank);
+        registry.register("ZCARD", Arity::Exactly(1), sorted_sets::zcard);
+        registry.register("ZRANGE", Arity::AtLeast(3), sorted_sets::zrange);
+        registry.register(
+            "ZRANGEBYSCORE",
+            Arity::AtLeast(3),
+            sorted_sets::zrangebyscore,
+        );
+        registry.register("ZRANGEBYLEX", Arity::Exactly(3), sorted_sets::zrangebylex);
+        registry.register("ZREM", Arity::AtLeast(2), sorted_sets::zrem);
+
+        registry.register("SUBSCRIBE", Arity::AtLeast(1), pubsub::subscribe);
+        registry.register("UNSUBSCRIBE", Arity::AtLeast(0), pubsub::unsubscribe);
+        registry.register("PSUBSCRIBE", Arity::AtLeast(1), pubsub::psubscribe);
+        registry.register("PUNSUBSCRIBE", Arity::AtLeast(0), pubsub::punsubscribe);
+        registry.register("PUBLISH", Arity::Exactly(2), pubsub::publish);
+
+        registry.register("MULTI", Arity::Exactly(0), transactions::multi);
+        registry.register("EXEC", Arity::Exactly(0), transactions::exec);
+        registry.register("DISCARD", Arity::Exactly(0), transactions::discard);
+        registry.register("WATCH", Arity::AtLeast(1), transactions::watch);
+        registry.register("UNWATCH", Arity::Exactly(0), transactions::unwatch);
+
+        registry.register("REPLCONF", Arity::AtLeast(2), replication::replconf);
+        registry.register("PSYNC", Arity::Exactly(2), replication::psync);
+        registry.register("WAIT", Arity::Exactly(2), replication::Wait);
+
+        registry.register("DBSIZE", Arity::Exactly(0), server::dbsize);
+        registry.register("FLUSHDB", Arity::Between(0, 1), server::flushdb);
+        registry.register("FLUSHALL", Arity::Between(0, 1), server::flushall);
+        registry.register("SAVE", Arity::Exactly(0), server::save);
+        registry.register("TIME", Arity::Exactly(0), server::time);
+        registry.register("INFO", Arity::AtLeast(0), server::info);
+        registry.register("DEBUG", Arity::AtLeast(1), server::DebugCommand);
+        registry.register("COMMAND", Arity::AtLeast(0), server::command);
+        registry.register("CONFIG", Arity::AtLeast(1), server::config);
+
+        registry
+    }
+
+    fn register(&mut self, name: &'static str, arity: Arity, command: impl Command + 'static) {
+        let spec = CommandSpec {
+            name,
+            arity,
+            command: Box::new(command),
+        };
+        self.commands.insert(name, spec);
+    }
+
+    /// Looks up a command by its upper-cased name.
+    pub fn get(&self, name: &str) -> Option<&CommandSpec> {
+        self.commands.get(name)
+    }
+
+    /// Runs the command `name`, or queues it if the client started a
+    /// transaction.
+    pub async fn execute(
+        &self,
+        name: &str,
+        args: &[Value],
+        session: &mut Session,
+    ) -> CommandResult<Value> {
+        let spec = self.get(name);
+        if let Some(transaction) = &mut session.transaction {
+            match spec {
+                Some(spec) if spec.name == "EXEC" => return self.exec(session).await,
+                Some(spec) if TRANSACTION_COMMANDS.contains(&spec.name) => {}
+                Some(spec) if spec.arity.accepts(args.len()) => {
+                    transaction.commands.push((spec.name, args.to_vec()));
+                    return Ok(Value::String("QUEUED".to_string()));
+                }
+                // The error is replied straight away, and EXEC will refuse to
+                // run the rest.
+                _ => transaction.failed = true,
+            }
+        }
+
+        match spec {
+            Some(spec) => spec.execute(args, session).await,
+            None => Err(CommandError::UnknownCommand(name.to_ascii_lowercase())),
+        }
+    }
+
+    /// Runs the commands queued since MULTI, unless one of them failed to
+    /// queue or a watched key was modified.
+    async fn exec(&self, session: &mut Session) -> CommandResult<Value> {
+        let transaction = session.transaction.take().unwrap_or_default();
+        let dirty = session.dirty.load(Ordering::SeqCst);
+        session.unwatch();
+
+        if transaction.failed {
+            return Err(CommandError::Other(
+                "EXECABORT Transaction discarded because of previous errors.".to_string(),
+            ));
+        }
+        if dirty {
+            return Ok(Value::NullArray);
+        }
+
+        let mut replies = Vec::with_capacity(transaction.commands.len());
+        for (name, args) in transaction.commands {
+            let reply = match self.get(name) {
+                Some(spec) => spec.execute(&args, session).await,
+                None => Err(CommandError::UnknownCommand(name.to_ascii_lowercase())),
+            };
+            replies.push(reply.unwrap_or_else(|err| err.to_value()));
+        }
+        Ok(Value::array(replies))
+    }
+}
+
+fn parse_integer(value: &Value) -> CommandResult<i64> {
+    value.as_i64().map_err(|_| CommandError::NotInteger)
+}
+
+fn parse_float(value: &Value) -> CommandResult<f64> {
+    match value.as_str()?.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(CommandError::NotFloat),
+    }
+}
+
+/// Parses a non-negative element count such as the one taken by LPOP.
+fn parse_count(value: &Value) -> CommandResult<usize> {
+    match parse_integer(value)? {
+        count if count < 0 => Err(CommandError::err("value is out of range, must be positive")),
+        count => Ok(count as usize),
+    }
+}
+
+fn ok() -> Value {
+    Value::String("OK".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Arity, Registry, Session};
+    use crate::config::Config;
+    use crate::db;
+    use crate::error::CommandError;
+    use crate::resp::{Protocol, Value};
+    use crate::server::Server;
+
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    pub fn session() -> Session {
+        Session::new(Server::new(db::databases(), Config::default()))
+    }
+
+    /// Turns the arguments of a command into the bulk strings a client sends.
+    pub fn args(args: &[&str]) -> Vec<Value> {
+        args.iter()
+            .map(|arg| Value::bulk(Bytes::from(arg.to_string())))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn it_executes_commands_through_the_registry() -> Result<()> {
+        let registry = Registry::new();
+        let mut session = session();
+
+        let ping = registry.get("PING").unwrap();
+        assert_eq!(
+            ping.execute(&[], &mut session).await,
+            Ok(Value::String("PONG".to_string()))
+        );
+
+        let echo = registry.get("ECHO").unwrap();
+        assert_eq!(
+            echo.execute(&[Value::bulk(Bytes::from("hey"))], &mut session)
+                .await,
+            Ok(Value::bulk(Bytes::from("hey")))
+        );
+        assert_eq!(
+            echo.execute(&[], &mut session).await,
+            Err(CommandError::WrongArgCount("echo".to_string()))
+        );
+
+        assert!(registry.get("NOPE").is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_rejects_the_wrong_number_of_arguments_before_running_a_command() -> Result<()> {
+        let registry = Registry::new();
+        let mut session = session();
+        let echo = registry.get("ECHO").unwrap();
+
+        for argv in [vec![], args(&["a", "b"])] {
+            let error = echo.execute(&argv, &mut session).await.unwrap_err();
+            assert_eq!(
+                error.to_value(),
+                Value::Error("ERR wrong number of arguments for 'echo' command".to_string())
+            );
+        }
+
+        let del = registry.get("DEL").unwrap();
+        assert_eq!(
+            del.execute(&[], &mut session).await,
+            Err(CommandError::WrongArgCount("del".to_string()))
+        );
+        assert_eq!(
+            del.execute(&args(&["a", "b"]), &mut session).await,
+            Ok(Value::Number(0))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_checks_each_kind_of_arity() {
+        assert!(Arity::Exactly(1).accepts(1));
+        assert!(!Arity::Exactly(1).accepts(2));
+        assert!(Arity::AtLeast(2).accepts(5));
+        assert!(!Arity::AtLeast(2).accepts(1));
+        assert!(Arity::Between(1, 2).accepts(2));
+        assert!(!Arity::Between(1, 2).accepts(0));
+        assert!(!Arity::Between(1, 2).accepts(3));
+    }
+
+    #[tokio::test]
+    async fn it_only_allows_subscription_commands_while_subscribed() -> Result<()> {
+        let registry = Registry::new();
+        let mut session = session();
+
+        let subscribe = registry.get("SUBSCRIBE").unwrap();
+        subscribe.execute(&args(&["news"]), &mut session).await?;
+
+        assert_eq!(
+            registry
+                .get("GET")
+                .unwrap()
+                .execute(&args(&["key"]), &mut session)
+                .await,
+            Err(CommandError::Other(
+                "ERR Can't execute 'get': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context"
+                    .to_string()
+            ))
+        );
+        assert!(registry
+            .get("PING")
+            .unwrap()
+            .execute(&[], &mut session)
+            .await
+            .is_ok());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_allows_any_command_to_subscribed_resp3_clients() -> Result<()> {
+        let registry = Registry::new();
+        let mut session = session();
+        session.protocol = Protocol::Resp3;
+
+        let subscribe = registry.get("SUBSCRIBE").unwrap();
+        subscribe.execute(&args(&["news"]), &mut session).await?;
+
+        assert_eq!(
+            registry
+                .get("GET")
+                .unwrap()
+                .execute(&args(&["key"]), &mut session)
+                .await,
+            Ok(Value::Null)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_keeps_the_selected_database_in_the_session() -> Result<()> {
+        let registry = Registry::new();
+        let mut session = session();
+
+        let select = registry.get("SELECT").unwrap();
+        select.execute(&args(&["3"]), &mut session).await?;
+        registry
+            .get("SET")
+            .unwrap()
+            .execute(&args(&["key", "value"]), &mut session)
+            .await?;
+
+        assert_eq!(session.db_index, 3);
+        assert!(session.server.dbs[3].contains("key"));
+        assert!(!session.server.dbs[0].contains("key"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_resets_the_session_to_its_initial_state() -> Result<()> {
+        let registry = Registry::new();
+        let mut session = session();
+
+        registry
+            .execute("HELLO", &args(&["3"]), &mut session)
+            .await?;
+        registry
+            .execute("SELECT", &args(&["2"]), &mut session)
+            .await?;
+        registry
+            .execute("SUBSCRIBE", &args(&["news"]), &mut session)
+            .await?;
+        registry
+            .execute("WATCH", &args(&["key"]), &mut session)
+            .await?;
+        registry.execute("MULTI", &[], &mut session).await?;
+
+        assert_eq!(
+            registry.execute("RESET", &[], &mut session).await,
+            Ok(Value::String("RESET".to_string()))
+        );
+        assert!(session.transaction.is_none());
+        assert!(!session.is_subscribed());
+        assert_eq!(session.db_index, 0);
+        assert_eq!(session.protocol, Protocol::Resp2);
+        assert!(session.watched.is_empty());
+
+        Ok(())
+    }
+}
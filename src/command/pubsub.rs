@@ -0,0 +1,218 @@
+use bytes::Bytes;
+
+use super::Session;
+use crate::error::CommandResult;
+use crate::resp::Value;
+
+/// The reply confirming a subscription change, carrying the number of
+/// channels and patterns the client is left subscribed to.
+fn confirmation(kind: &str, channel: Option<&str>, session: &Session) -> Value {
+    Value::array(vec![
+        Value::bulk(Bytes::from(kind.to_string())),
+        match channel {
+            Some(channel) => Value::bulk(Bytes::from(channel.to_string())),
+            None => Value::Null,
+        },
+        Value::Number(session.subscription_count() as i64),
+    ])
+}
+
+/// A message published to a channel the client subscribed to.
+pub fn message(channel: &str, message: Bytes) -> Value {
+    Value::array(vec![
+        Value::bulk(Bytes::from_static(b"message")),
+        Value::bulk(Bytes::from(channel.to_string())),
+        Value::bulk(message),
+    ])
+}
+
+/// A message published to a channel matching a pattern the client
+/// subscribed to.
+pub fn pattern_message(pattern: &str, channel: &str, message: Bytes) -> Value {
+    Value::array(vec![
+        Value::bulk(Bytes::from_static(b"pmessage")),
+        Value::bulk(Bytes::from(pattern.to_string())),
+        Value::bulk(Bytes::from(channel.to_string())),
+        Value::bulk(message),
+    ])
+}
+
+/// The channels or patterns to leave: the ones given, or every one the
+/// client subscribed to when none are.
+fn names_to_leave<'a>(
+    args: &[Value],
+    subscribed: impl Iterator<Item = &'a String>,
+) -> anyhow::Result<Vec<String>> {
+    if args.is_empty() {
+        Ok(subscribed.cloned().collect())
+    } else {
+        args.iter().map(|arg| arg.as_string()).collect()
+    }
+}
+
+/// Replies once per channel: the last confirmation is returned and the
+/// others are queued ahead of it.
+fn confirm_each(session: &mut Session, mut confirmations: Vec<Value>) -> Value {
+    let last = confirmations.pop().expect("at least one confirmation");
+    for confirmation in confirmations {
+        session.reply(confirmation);
+    }
+    last
+}
+
+pub fn subscribe(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let mut confirmations = Vec::with_capacity(args.len());
+    for arg in args {
+        let channel = arg.as_string()?;
+        if !session.subscriptions.contains_key(&channel) {
+            let receiver = session.server.pubsub.subscribe(&channel);
+            session.subscriptions.insert(channel.clone(), receiver);
+        }
+        confirmations.push(confirmation("subscribe", Some(&channel), session));
+    }
+
+    Ok(confirm_each(session, confirmations))
+}
+
+pub fn unsubscribe(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let channels = names_to_leave(args, session.subscriptions.keys())?;
+    if channels.is_empty() {
+        return Ok(confirmation("unsubscribe", None, session));
+    }
+
+    let mut confirmations = Vec::with_capacity(channels.len());
+    for channel in channels {
+        session.subscriptions.remove(&channel);
+        confirmations.push(confirmation("unsubscribe", Some(&channel), session));
+    }
+
+    Ok(confirm_each(session, confirmations))
+}
+
+pub fn psubscribe(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let mut confirmations = Vec::with_capacity(args.len());
+    for arg in args {
+        let pattern = arg.as_string()?;
+        if !session.pattern_subscriptions.contains_key(&pattern) {
+            let receiver = session.server.pubsub.psubscribe(&pattern);
+            session
+                .pattern_subscriptions
+                .insert(pattern.clone(), receiver);
+        }
+        confirmations.push(confirmation("psubscribe", Some(&pattern), session));
+    }
+
+    Ok(confirm_each(session, confirmations))
+}
+
+pub fn punsubscribe(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let patterns = names_to_leave(args, session.pattern_subscriptions.keys())?;
+    if patterns.is_empty() {
+        return Ok(confirmation("punsubscribe", None, session));
+    }
+
+    let mut confirmations = Vec::with_capacity(patterns.len());
+    for pattern in patterns {
+        session.pattern_subscriptions.remove(&pattern);
+        confirmations.push(confirmation("punsubscribe", Some(&pattern), session));
+    }
+
+    Ok(confirm_each(session, confirmations))
+}
+
+pub fn publish(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let received = session
+        .server
+        .pubsub
+        .publish(args[0].as_str()?, args[1].as_bytes()?);
+    Ok(Value::Number(received as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{psubscribe, publish, punsubscribe, subscribe, unsubscribe};
+    use crate::command::tests::{args, session};
+    use crate::resp::Value;
+
+    fn confirmation(kind: &str, channel: &str, count: i64) -> Value {
+        Value::array(vec![
+            Value::bulk(Bytes::from(kind.to_string())),
+            Value::bulk(Bytes::from(channel.to_string())),
+            Value::Number(count),
+        ])
+    }
+
+    #[test]
+    fn it_confirms_each_subscription() {
+        let mut session = session();
+
+        let reply = subscribe(&args(&["a", "b"]), &mut session).unwrap();
+
+        assert_eq!(
+            session.take_replies(),
+            vec![confirmation("subscribe", "a", 1)]
+        );
+        assert_eq!(reply, confirmation("subscribe", "b", 2));
+        assert!(session.is_subscribed());
+    }
+
+    #[test]
+    fn it_unsubscribes_from_every_channel_without_arguments() {
+        let mut session = session();
+        subscribe(&args(&["a", "b"]), &mut session).unwrap();
+        session.take_replies();
+
+        unsubscribe(&[], &mut session).unwrap();
+
+        assert!(!session.is_subscribed());
+        assert_eq!(
+            unsubscribe(&[], &mut session).unwrap(),
+            Value::array(vec![
+                Value::bulk(Bytes::from("unsubscribe")),
+                Value::Null,
+                Value::Number(0)
+            ])
+        );
+    }
+
+    #[test]
+    fn it_counts_the_subscribers_reached_by_publish() {
+        let mut subscriber = session();
+        let mut publisher = session();
+        publisher.server = subscriber.server.clone();
+
+        subscribe(&args(&["news"]), &mut subscriber).unwrap();
+
+        assert_eq!(
+            publish(&args(&["news", "hello"]), &mut publisher).unwrap(),
+            Value::Number(1)
+        );
+        assert_eq!(
+            publish(&args(&["other", "hello"]), &mut publisher).unwrap(),
+            Value::Number(0)
+        );
+    }
+
+    #[test]
+    fn it_counts_channels_and_patterns_together() {
+        let mut session = session();
+        subscribe(&args(&["news"]), &mut session).unwrap();
+
+        assert_eq!(
+            psubscribe(&args(&["news.*"]), &mut session).unwrap(),
+            confirmation("psubscribe", "news.*", 2)
+        );
+        assert_eq!(
+            unsubscribe(&[], &mut session).unwrap(),
+            confirmation("unsubscribe", "news", 1)
+        );
+        assert!(session.is_subscribed());
+        assert_eq!(
+            punsubscribe(&args(&["news.*"]), &mut session).unwrap(),
+            confirmation("punsubscribe", "news.*", 0)
+        );
+        assert!(!session.is_subscribed());
+    }
+}
@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use super::{ok, parse_count, parse_integer, Command, CommandFuture, Session};
+use crate::error::{CommandError, CommandResult};
+use crate::replication;
+use crate::resp::Value;
+
+/// WAIT, which blocks until enough replicas acknowledged the writes made so
+/// far.
+pub struct Wait;
+
+impl Command for Wait {
+    fn execute<'a>(&'a self, args: &'a [Value], session: &'a mut Session) -> CommandFuture<'a> {
+        Box::pin(async move {
+            let needed = parse_count(&args[0])?;
+            let timeout = match parse_integer(&args[1])? {
+                timeout if timeout < 0 => {
+                    return Err(CommandError::err("timeout is negative"));
+                }
+                0 => None,
+                timeout => Some(Duration::from_millis(timeout as u64)),
+            };
+
+            let acknowledged =
+                replication::wait_for_replicas(&session.server, needed, timeout).await;
+            Ok(Value::Number(acknowledged as i64))
+        })
+    }
+
+    fn is_blocking(&self) -> bool {
+        true
+    }
+}
+
+pub fn psync(_args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    // Partial resynchronization is not supported, so every replica gets a
+    // full copy of the dataset, which is sent once this reply is.
+    let reply = {
+        let replication = session.server.replication.read().unwrap();
+        format!("FULLRESYNC {} {}", replication.replid, replication.offset)
+    };
+    session.is_replica = true;
+    Ok(Value::String(reply))
+}
+
+pub fn replconf(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    // Options come in pairs with their values.
+    if args.len() % 2 == 1 {
+        return Err(CommandError::SyntaxError);
+    }
+
+    for pair in args.chunks(2) {
+        let option = pair[0].as_str()?.to_ascii_lowercase();
+        match option.as_str() {
+            "listening-port" => {
+                let port = pair[1]
+                    .as_str()?
+                    .parse::<u16>()
+                    .map_err(|_| CommandError::NotInteger)?;
+                session.replica_listening_port = Some(port);
+            }
+            // Every capability a replica announces is one we can live
+            // without, so there is nothing to record.
+            "capa" => {}
+            _ => {
+                return Err(CommandError::err(&format!(
+                    "Unrecognized REPLCONF option: {}",
+                    option
+                )))
+            }
+        }
+    }
+
+    Ok(ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{psync, replconf, Wait};
+    use crate::command::tests::{args, session};
+    use crate::command::Command;
+    use crate::error::CommandError;
+    use crate::resp::Value;
+
+    #[test]
+    fn it_records_the_listening_port_of_a_replica() {
+        let mut session = session();
+
+        assert_eq!(
+            replconf(&args(&["listening-port", "6380"]), &mut session),
+            Ok(Value::String("OK".to_string()))
+        );
+        assert_eq!(session.replica_listening_port, Some(6380));
+    }
+
+    #[test]
+    fn it_accepts_capabilities() {
+        let mut session = session();
+
+        assert_eq!(
+            replconf(&args(&["capa", "eof", "capa", "psync2"]), &mut session),
+            Ok(Value::String("OK".to_string()))
+        );
+    }
+
+    #[test]
+    fn it_rejects_unknown_or_unpaired_options() {
+        let mut session = session();
+
+        assert_eq!(
+            replconf(&args(&["nope", "1"]), &mut session),
+            Err(CommandError::err("Unrecognized REPLCONF option: nope"))
+        );
+        assert_eq!(
+            replconf(&args(&["capa"]), &mut session),
+            Err(CommandError::SyntaxError)
+        );
+        assert_eq!(
+            replconf(&args(&["listening-port", "port"]), &mut session),
+            Err(CommandError::NotInteger)
+        );
+    }
+
+    #[test]
+    fn it_fully_resynchronizes_replicas() {
+        let mut session = session();
+        let replid = session.server.replication.read().unwrap().replid.clone();
+
+        assert_eq!(
+            psync(&args(&["?", "-1"]), &mut session),
+            Ok(Value::String(format!("FULLRESYNC {} 0", replid)))
+        );
+        assert!(session.is_replica);
+    }
+
+    #[tokio::test]
+    async fn it_counts_the_replicas_acknowledging_writes() {
+        let mut session = session();
+
+        assert_eq!(
+            Wait.execute(&args(&["0", "0"]), &mut session).await,
+            Ok(Value::Number(0))
+        );
+        assert_eq!(
+            Wait.execute(&args(&["1", "10"]), &mut session).await,
+            Ok(Value::Number(0))
+        );
+        assert_eq!(
+            Wait.execute(&args(&["1", "-1"]), &mut session).await,
+            Err(CommandError::err("timeout is negative"))
+        );
+    }
+}
@@ -0,0 +1,477 @@
+use std::process;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+use super::{ok, parse_float, parse_integer, Command, CommandFuture, Session};
+use crate::db::Db;
+use crate::error::{CommandError, CommandResult};
+use crate::rdb;
+use crate::resp::Value;
+use crate::server::Server;
+
+/// Gathers the `name:value` fields of an INFO section.
+type InfoFields = fn(&Server) -> Vec<(String, String)>;
+
+/// The sections INFO can report, in the order they are listed.
+const INFO_SECTIONS: &[(&str, InfoFields)] =
+    &[("Server", server_info), ("Replication", replication_info)];
+
+/// DEBUG, of which only the subcommands test suites rely on do anything.
+/// The others reply OK rather than failing the suite.
+pub struct DebugCommand;
+
+impl Command for DebugCommand {
+    fn execute<'a>(&'a self, args: &'a [Value], session: &'a mut Session) -> CommandFuture<'a> {
+        Box::pin(async move {
+            let subcommand = args[0].as_str()?.to_ascii_uppercase();
+            match subcommand.as_str() {
+                "SLEEP" => {
+                    let seconds =
+                        parse_float(args.get(1).ok_or_else(|| {
+                            CommandError::WrongArgCount("debug|sleep".to_string())
+                        })?)?;
+                    // Clamped so that the duration cannot overflow.
+                    let seconds = seconds.clamp(0.0, u32::MAX as f64);
+                    tokio::time::delay_for(Duration::from_secs_f64(seconds)).await;
+                }
+                "SET-ACTIVE-EXPIRE" => {
+                    let enabled = parse_integer(args.get(1).ok_or_else(|| {
+                        CommandError::WrongArgCount("debug|set-active-expire".to_string())
+                    })?)?;
+                    session
+                        .server
+                        .active_expire
+                        .store(enabled != 0, Ordering::SeqCst);
+                }
+                _ => {}
+            }
+            Ok(ok())
+        })
+    }
+
+    fn is_blocking(&self) -> bool {
+        true
+    }
+}
+
+pub fn dbsize(_args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    Ok(Value::Number(session.db().len() as i64))
+}
+
+pub fn time(_args: &[Value], _session: &mut Session) -> CommandResult<Value> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(anyhow::Error::from)?;
+    Ok(Value::array(vec![
+        Value::bulk(Bytes::from(now.as_secs().to_string())),
+        Value::bulk(Bytes::from(now.subsec_micros().to_string())),
+    ]))
+}
+
+pub fn info(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let requested = args
+        .iter()
+        .map(|arg| Ok(arg.as_str()?.to_ascii_lowercase()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let everything = requested.is_empty()
+        || requested
+            .iter()
+            .any(|name| matches!(name.as_str(), "all" | "default" | "everything"));
+
+    let sections = INFO_SECTIONS
+        .iter()
+        .filter(|(title, _)| {
+            everything
+                || requested
+                    .iter()
+                    .any(|name| title.eq_ignore_ascii_case(name))
+        })
+        .map(|(title, fields)| {
+            let mut section = format!("# {}\r\n", title);
+            for (name, value) in fields(&session.server) {
+                section.push_str(&format!("{}:{}\r\n", name, value));
+            }
+            section
+        })
+        .collect::<Vec<_>>();
+    Ok(Value::bulk(Bytes::from(sections.join("\r\n"))))
+}
+
+fn server_info(server: &Server) -> Vec<(String, String)> {
+    let uptime = server.started_at.elapsed().as_secs();
+    let fields = vec![
+        ("redis_version", env!("CARGO_PKG_VERSION").to_string()),
+        ("redis_mode", "standalone".to_string()),
+        ("process_id", process::id().to_string()),
+        ("tcp_port", server.config.read().unwrap().port.to_string()),
+        ("uptime_in_seconds", uptime.to_string()),
+        ("uptime_in_days", (uptime / (24 * 60 * 60)).to_string()),
+    ];
+    fields
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+}
+
+fn replication_info(server: &Server) -> Vec<(String, String)> {
+    let mut fields = match &server.config.read().unwrap().replicaof {
+        Some((host, port)) => vec![
+            ("role", "slave".to_string()),
+            ("master_host", host.clone()),
+            ("master_port", port.to_string()),
+        ],
+        None => vec![("role", "master".to_string())],
+    };
+
+    let replication = server.replication.read().unwrap();
+    fields.push(("connected_slaves", replication.replicas.len().to_string()));
+    let mut fields = fields
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect::<Vec<_>>();
+    for (index, replica) in replication.replicas.iter().enumerate() {
+        fields.push((
+            format!("slave{}", index),
+            format!("state=online,offset={}", replica.acked()),
+        ));
+    }
+    fields.push(("master_replid".to_string(), replication.replid.clone()));
+    fields.push((
+        "master_repl_offset".to_string(),
+        replication.offset.to_string(),
+    ));
+    fields
+}
+
+/// Checks the optional mode of FLUSHDB and FLUSHALL. Flushing is always
+/// synchronous, but clients may still ask for either mode explicitly.
+fn parse_flush_mode(args: &[Value]) -> CommandResult<()> {
+    if let Some(mode) = args.first() {
+        let mode = mode.as_str()?.to_ascii_uppercase();
+        if mode != "ASYNC" && mode != "SYNC" {
+            return Err(CommandError::SyntaxError);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn flushdb(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    parse_flush_mode(args)?;
+    session.db().clear();
+    Ok(ok())
+}
+
+pub fn flushall(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    parse_flush_mode(args)?;
+    session.server.dbs.iter().for_each(Db::clear);
+    Ok(ok())
+}
+
+/// Saves the databases to the RDB file, blocking until it is written.
+pub fn save(_args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let path = session.server.config.read().unwrap().rdb_path();
+    rdb::save(&path, &session.server.dbs)?;
+    Ok(ok())
+}
+
+pub fn command(args: &[Value], _session: &mut Session) -> CommandResult<Value> {
+    // This only answers enough for clients such as redis-cli to start a
+    // session cleanly.
+    let subcommand = match args.first() {
+        Some(subcommand) => subcommand.as_str()?.to_ascii_uppercase(),
+        None => String::new(),
+    };
+    match subcommand.as_str() {
+        "" | "DOCS" => Ok(Value::Array {
+            len: 0,
+            elements: vec![],
+        }),
+        "COUNT" => Ok(Value::Number(0)),
+        "INFO" => Ok(Value::Array {
+            len: args.len() as i64 - 1,
+            elements: args[1..].iter().map(|_| Value::Null).collect(),
+        }),
+        _ => Err(CommandError::Other(format!(
+            "ERR unknown subcommand '{}'. Try COMMAND HELP.",
+            args[0].as_str()?
+        ))),
+    }
+}
+
+pub fn config(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let subcommand = args[0].as_str()?.to_ascii_uppercase();
+    match subcommand.as_str() {
+        "GET" => {
+            if args.len() < 2 {
+                return Err(CommandError::WrongArgCount("config|get".to_string()));
+            }
+
+            // A parameter matched by several patterns is only listed once.
+            let config = session.server.config.read().unwrap();
+            let mut matched = vec![];
+            for pattern in &args[1..] {
+                for (name, value) in config.matching(pattern.as_str()?) {
+                    if !matched.iter().any(|(existing, _)| *existing == name) {
+                        matched.push((name, value));
+                    }
+                }
+            }
+            let pairs = matched
+                .into_iter()
+                .map(|(name, value)| {
+                    (
+                        Value::bulk(Bytes::from(name)),
+                        Value::bulk(Bytes::from(value)),
+                    )
+                })
+                .collect();
+            Ok(Value::map(pairs, session.protocol))
+        }
+        "SET" => {
+            if args.len() < 3 || args[1..].len() % 2 == 1 {
+                return Err(CommandError::WrongArgCount("config|set".to_string()));
+            }
+
+            // Apply every change to a copy first, so that a failing one
+            // leaves the config untouched.
+            let mut config = session.server.config.write().unwrap();
+            let mut updated = config.clone();
+            for pair in args[1..].chunks(2) {
+                updated.set(pair[0].as_str()?, pair[1].as_str()?)?;
+            }
+            *config = updated;
+            Ok(ok())
+        }
+        _ => Err(CommandError::Other(format!(
+            "ERR unknown subcommand '{}'. Try CONFIG HELP.",
+            args[0].as_str()?
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{config, info, save, time, DebugCommand};
+    use crate::command::tests::{args, session};
+    use crate::command::Command;
+    use crate::db;
+    use crate::rdb;
+    use crate::resp::Value;
+
+    use std::fs;
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    #[test]
+    fn it_replies_with_the_current_time() -> Result<()> {
+        let before = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let reply = time(&[], &mut session()).unwrap();
+        let after = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        let parts = match reply {
+            Value::Array { elements, .. } => elements
+                .iter()
+                .map(|part| part.as_str()?.parse::<u64>().map_err(anyhow::Error::from))
+                .collect::<Result<Vec<_>>>()?,
+            other => panic!("expected an array, got {:?}", other),
+        };
+        assert_eq!(parts.len(), 2);
+        assert!((before..=after).contains(&parts[0]));
+        assert!(parts[1] < 1_000_000);
+
+        Ok(())
+    }
+
+    fn info_lines(args: &[Value]) -> Vec<String> {
+        match info(args, &mut session()).unwrap() {
+            Value::Bulk { data, .. } => String::from_utf8(data.to_vec())
+                .unwrap()
+                .split("\r\n")
+                .map(|line| line.to_string())
+                .collect(),
+            other => panic!("expected a bulk string, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_reports_every_info_section_by_default() {
+        let lines = info_lines(&[]);
+
+        assert!(lines.contains(&"# Server".to_string()));
+        assert!(lines.contains(&"tcp_port:6379".to_string()));
+        assert!(lines.contains(&"# Replication".to_string()));
+        assert!(lines.contains(&"role:master".to_string()));
+    }
+
+    #[test]
+    fn it_reports_the_master_of_a_replica() {
+        let mut session = session();
+        session.server.config.write().unwrap().replicaof = Some(("localhost".to_string(), 6379));
+
+        let reply = info(&args(&["replication"]), &mut session).unwrap();
+
+        let info = String::from_utf8(reply.as_bytes().unwrap().to_vec()).unwrap();
+        assert!(info.contains("role:slave\r\nmaster_host:localhost\r\nmaster_port:6379\r\n"));
+    }
+
+    #[test]
+    fn it_reports_the_requested_info_section() {
+        let lines = info_lines(&args(&["REPLICATION"]));
+
+        assert_eq!(lines[0], "# Replication");
+        assert!(!lines.contains(&"# Server".to_string()));
+        let fields = lines[1..]
+            .iter()
+            .filter_map(|line| line.split_once(':'))
+            .collect::<Vec<_>>();
+        assert!(fields.contains(&("role", "master")));
+        assert!(fields.contains(&("master_repl_offset", "0")));
+        assert!(fields
+            .iter()
+            .any(|(name, value)| *name == "master_replid" && value.len() == 40));
+
+        assert_eq!(info_lines(&args(&["nope"])), vec![""]);
+    }
+
+    #[test]
+    fn it_gets_a_single_config_parameter() {
+        let mut session = session();
+
+        assert_eq!(
+            config(&args(&["GET", "dir"]), &mut session),
+            Ok(Value::array(args(&["dir", "."])))
+        );
+        assert_eq!(
+            config(&args(&["get", "DBFILENAME"]), &mut session),
+            Ok(Value::array(args(&["dbfilename", "dump.rdb"])))
+        );
+    }
+
+    #[test]
+    fn it_gets_config_parameters_matching_a_glob() {
+        let mut session = session();
+
+        assert_eq!(
+            config(&args(&["GET", "d*"]), &mut session),
+            Ok(Value::array(args(&["dbfilename", "dump.rdb", "dir", "."])))
+        );
+        assert_eq!(
+            config(&args(&["GET", "dir", "di?"]), &mut session),
+            Ok(Value::array(args(&["dir", "."])))
+        );
+    }
+
+    #[test]
+    fn it_sets_a_parameter_then_gets_it_back() {
+        let mut session = session();
+
+        assert_eq!(
+            config(&args(&["SET", "maxmemory", "100mb"]), &mut session),
+            Ok(Value::String("OK".to_string()))
+        );
+        assert_eq!(
+            config(&args(&["GET", "maxmemory"]), &mut session),
+            Ok(Value::array(args(&["maxmemory", "104857600"])))
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_config_when_setting_an_unknown_parameter() {
+        let mut session = session();
+
+        let reply = config(&args(&["SET", "dir", "/data", "nope", "1"]), &mut session);
+
+        assert_eq!(
+            reply.unwrap_err().to_value(),
+            Value::Error(
+                "ERR Unknown option or number of arguments for CONFIG SET - 'nope'".to_string()
+            )
+        );
+        assert_eq!(session.server.config.read().unwrap().dir, ".");
+    }
+
+    #[test]
+    fn it_saves_to_the_configured_rdb_file() -> Result<()> {
+        let mut session = session();
+        let dir = std::env::temp_dir();
+        let dbfilename = format!("redis-save-{}.rdb", std::process::id());
+        config(
+            &args(&[
+                "SET",
+                "dir",
+                dir.to_str().unwrap(),
+                "dbfilename",
+                &dbfilename,
+            ]),
+            &mut session,
+        )?;
+        session
+            .db()
+            .set_many(vec![("key".to_string(), Bytes::from("value"))]);
+
+        assert_eq!(save(&[], &mut session), Ok(Value::String("OK".to_string())));
+
+        let path = dir.join(&dbfilename);
+        let loaded = db::databases();
+        rdb::load(&path, &loaded)?;
+        fs::remove_file(&path)?;
+        assert_eq!(loaded[0].get("key")?, Some(Bytes::from("value")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_replies_with_an_empty_array_for_unknown_parameters() {
+        let mut session = session();
+
+        assert_eq!(
+            config(&args(&["GET", "nope"]), &mut session),
+            Ok(Value::array(vec![]))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_sleeps_for_debug_sleep() -> Result<()> {
+        let mut session = session();
+
+        let reply = tokio::time::timeout(
+            Duration::from_secs(1),
+            DebugCommand.execute(&args(&["SLEEP", "0"]), &mut session),
+        )
+        .await?;
+
+        assert_eq!(reply, Ok(Value::String("OK".to_string())));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_toggles_active_expiry() {
+        let mut session = session();
+
+        let reply = DebugCommand
+            .execute(&args(&["SET-ACTIVE-EXPIRE", "0"]), &mut session)
+            .await;
+        assert_eq!(reply, Ok(Value::String("OK".to_string())));
+        assert!(!session.server.active_expire.load(Ordering::SeqCst));
+
+        DebugCommand
+            .execute(&args(&["set-active-expire", "1"]), &mut session)
+            .await
+            .unwrap();
+        assert!(session.server.active_expire.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn it_accepts_unknown_debug_subcommands() {
+        assert_eq!(
+            DebugCommand.execute(&args(&["JMAP"]), &mut session()).await,
+            Ok(Value::String("OK".to_string()))
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use bytes::Bytes;
+
+use super::{parse_count, parse_integer, Session};
+use crate::db::SetOperation;
+use crate::error::{CommandError, CommandResult};
+use crate::resp::Value;
+
+fn members(args: &[Value]) -> CommandResult<Vec<Bytes>> {
+    let members = args[1..]
+        .iter()
+        .map(|member| member.as_bytes())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(members)
+}
+
+pub fn sadd(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let added = session.db().set_add(args[0].as_str()?, members(args)?)?;
+    Ok(Value::Number(added as i64))
+}
+
+pub fn srem(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let removed = session
+        .db()
+        .set_remove(args[0].as_str()?, &members(args)?)?;
+    Ok(Value::Number(removed as i64))
+}
+
+pub fn sismember(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let contains = session
+        .db()
+        .set_contains(args[0].as_str()?, &args[1].as_bytes()?)?;
+    Ok(Value::Number(contains as i64))
+}
+
+pub fn smove(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let moved = session
+        .db()
+        .set_move(args[0].as_str()?, args[1].as_str()?, args[2].as_bytes()?)?;
+    Ok(Value::Number(moved as i64))
+}
+
+/// SPOP key [count], replying with a single member, or an array of them
+/// when given a count.
+pub fn spop(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let key = args[0].as_str()?;
+    let count = match args.get(1) {
+        Some(count) => Some(parse_count(count)?),
+        None => None,
+    };
+
+    let popped = session.db().set_pop(key, count.unwrap_or(1))?;
+    if !popped.is_empty() {
+        // Replicas would pick other members, so they are told which went.
+        let mut command = vec![Value::bulk(Bytes::from("SREM")), args[0].clone()];
+        command.extend(popped.iter().cloned().map(Value::bulk));
+        session
+            .server
+            .replication
+            .write()
+            .unwrap()
+            .propagate(session.db_index, Value::array(command));
+    }
+
+    match count {
+        Some(_) => Ok(Value::array(popped.into_iter().map(Value::bulk).collect())),
+        None => Ok(popped.into_iter().next().map_or(Value::Null, Value::bulk)),
+    }
+}
+
+/// SRANDMEMBER key [count], where a positive count asks for distinct
+/// members and a negative one allows repeats.
+pub fn srandmember(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let key = args[0].as_str()?;
+    let count = match args.get(1) {
+        Some(count) => parse_integer(count)?,
+        None => {
+            let member = session.db().set_random_members(key, 1, true)?;
+            return Ok(member.into_iter().next().map_or(Value::Null, Value::bulk));
+        }
+    };
+
+    let members = match count.checked_abs() {
+        Some(abs) => session
+            .db()
+            .set_random_members(key, abs as usize, count > 0)?,
+        None => return Err(CommandError::err("value is out of range")),
+    };
+    Ok(Value::array(members.into_iter().map(Value::bulk).collect()))
+}
+
+pub fn smembers(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let members = session.db().set_members(args[0].as_str()?)?;
+    Ok(Value::array(members.into_iter().map(Value::bulk).collect()))
+}
+
+pub fn scard(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let len = session.db().set_len(args[0].as_str()?)?;
+    Ok(Value::Number(len as i64))
+}
+
+fn combine(args: &[Value], session: &mut Session, operation: SetOperation) -> CommandResult<Value> {
+    let keys = args
+        .iter()
+        .map(|key| key.as_string())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let members = session.db().set_combine(&keys, operation)?;
+    Ok(Value::array(members.into_iter().map(Value::bulk).collect()))
+}
+
+pub fn sunion(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    combine(args, session, SetOperation::Union)
+}
+
+pub fn sinter(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    combine(args, session, SetOperation::Intersection)
+}
+
+pub fn sdiff(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    combine(args, session, SetOperation::Difference)
+}
+
+/// SINTERCARD numkeys key [key ...] [LIMIT limit], where a limit of 0 means
+/// no limit.
+pub fn sintercard(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let numkeys = match parse_integer(&args[0]) {
+        Ok(numkeys) if numkeys > 0 => numkeys as usize,
+        _ => return Err(CommandError::err("numkeys should be greater than 0")),
+    };
+    if numkeys > args.len() - 1 {
+        return Err(CommandError::err(
+            "Number of keys can't be greater than number of args",
+        ));
+    }
+    let keys = args[1..=numkeys]
+        .iter()
+        .map(|key| key.as_string())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut limit = None;
+    match &args[numkeys + 1..] {
+        [] => {}
+        [option, value] if option.as_str()?.eq_ignore_ascii_case("LIMIT") => {
+            limit = match parse_integer(value)? {
+                limit if limit < 0 => return Err(CommandError::err("LIMIT can't be negative")),
+                0 => None,
+                limit => Some(limit as usize),
+            };
+        }
+        _ => return Err(CommandError::SyntaxError),
+    }
+
+    let len = session.db().set_intersection_len(&keys, limit)?;
+    Ok(Value::Number(len as i64))
+}
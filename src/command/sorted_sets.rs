@@ -0,0 +1,165 @@
+use bytes::Bytes;
+
+use super::{parse_integer, Session};
+use crate::error::{CommandError, CommandResult};
+use crate::resp::Value;
+use crate::sorted_set::{LexBound, ScoreBound};
+
+/// Parses a sorted set score, which unlike other floats may be infinite.
+fn parse_score(value: &Value) -> CommandResult<f64> {
+    match value.as_str()?.parse::<f64>() {
+        Ok(score) if !score.is_nan() => Ok(score),
+        _ => Err(CommandError::NotFloat),
+    }
+}
+
+/// Formats a sorted set score the way it is sent back to clients.
+fn format_score(score: f64) -> Bytes {
+    Bytes::from(score.to_string())
+}
+
+/// Parses one end of a score range, where a leading `(` makes it exclusive.
+fn parse_score_bound(value: &Value) -> CommandResult<ScoreBound> {
+    let value = value.as_str()?;
+    let (exclusive, score) = match value.strip_prefix('(') {
+        Some(score) => (true, score),
+        None => (false, value),
+    };
+    let score = match score.parse::<f64>() {
+        Ok(score) if !score.is_nan() => score,
+        _ => return Err(CommandError::err("min or max is not a float")),
+    };
+
+    if exclusive {
+        Ok(ScoreBound::Exclusive(score))
+    } else {
+        Ok(ScoreBound::Inclusive(score))
+    }
+}
+
+/// Parses one end of a lexicographic range: `-` or `+` for either end of the
+/// set, or a member preceded by `[` to include it or `(` to exclude it.
+fn parse_lex_bound(value: &Value) -> CommandResult<LexBound> {
+    let value = value.as_bytes()?;
+    match value.first() {
+        Some(b'-') if value.len() == 1 => Ok(LexBound::Min),
+        Some(b'+') if value.len() == 1 => Ok(LexBound::Max),
+        Some(b'[') => Ok(LexBound::Inclusive(value.slice(1..))),
+        Some(b'(') => Ok(LexBound::Exclusive(value.slice(1..))),
+        _ => Err(CommandError::err("min or max not valid string range item")),
+    }
+}
+
+/// Parses the trailing options of ZRANGE and ZRANGEBYSCORE, returning
+/// whether scores should be included in the reply.
+fn parse_with_scores(options: &[Value]) -> CommandResult<bool> {
+    match options {
+        [] => Ok(false),
+        [option] if option.as_str()?.eq_ignore_ascii_case("WITHSCORES") => Ok(true),
+        _ => Err(CommandError::SyntaxError),
+    }
+}
+
+/// Builds the reply to a sorted set range, interleaving the scores with the
+/// members when asked to.
+fn range_reply(range: Vec<(Bytes, f64)>, with_scores: bool) -> Value {
+    let mut elements = Vec::with_capacity(range.len() * 2);
+    for (member, score) in range {
+        elements.push(Value::bulk(member));
+        if with_scores {
+            elements.push(Value::bulk(format_score(score)));
+        }
+    }
+
+    Value::array(elements)
+}
+
+pub fn zadd(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    if args[1..].len() % 2 == 1 {
+        return Err(CommandError::SyntaxError);
+    }
+
+    let members = args[1..]
+        .chunks(2)
+        .map(|pair| Ok((parse_score(&pair[0])?, pair[1].as_bytes()?)))
+        .collect::<CommandResult<Vec<_>>>()?;
+    let added = session.db().zset_add(args[0].as_str()?, members)?;
+    Ok(Value::Number(added as i64))
+}
+
+pub fn zscore(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    match session
+        .db()
+        .zset_score(args[0].as_str()?, &args[1].as_bytes()?)?
+    {
+        Some(score) => Ok(Value::bulk(format_score(score))),
+        None => Ok(Value::Null),
+    }
+}
+
+pub fn zincrby(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let delta = parse_score(&args[1])?;
+    let score = session
+        .db()
+        .zset_incr_by(args[0].as_str()?, delta, args[2].as_bytes()?)?;
+    Ok(Value::bulk(format_score(score)))
+}
+
+pub fn zrank(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    rank_reply(args, session, false)
+}
+
+pub fn zrevrank(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    rank_reply(args, session, true)
+}
+
+fn rank_reply(args: &[Value], session: &mut Session, reverse: bool) -> CommandResult<Value> {
+    match session
+        .db()
+        .zset_rank(args[0].as_str()?, &args[1].as_bytes()?, reverse)?
+    {
+        Some(rank) => Ok(Value::Number(rank as i64)),
+        None => Ok(Value::Null),
+    }
+}
+
+pub fn zcard(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let len = session.db().zset_len(args[0].as_str()?)?;
+    Ok(Value::Number(len as i64))
+}
+
+pub fn zrange(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let with_scores = parse_with_scores(&args[3..])?;
+    let start = parse_integer(&args[1])?;
+    let stop = parse_integer(&args[2])?;
+    let range = session.db().zset_range(args[0].as_str()?, start, stop)?;
+    Ok(range_reply(range, with_scores))
+}
+
+pub fn zrangebyscore(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let with_scores = parse_with_scores(&args[3..])?;
+    let min = parse_score_bound(&args[1])?;
+    let max = parse_score_bound(&args[2])?;
+    let range = session
+        .db()
+        .zset_range_by_score(args[0].as_str()?, min, max)?;
+    Ok(range_reply(range, with_scores))
+}
+
+pub fn zrangebylex(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let min = parse_lex_bound(&args[1])?;
+    let max = parse_lex_bound(&args[2])?;
+    let members = session
+        .db()
+        .zset_range_by_lex(args[0].as_str()?, &min, &max)?;
+    Ok(Value::array(members.into_iter().map(Value::bulk).collect()))
+}
+
+pub fn zrem(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let members = args[1..]
+        .iter()
+        .map(|member| member.as_bytes())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let removed = session.db().zset_remove(args[0].as_str()?, &members)?;
+    Ok(Value::Number(removed as i64))
+}
@@ -0,0 +1,229 @@
+use std::time::{Duration, Instant};
+
+use super::{ok, parse_float, parse_integer, Session};
+use crate::db::{instant_from_unix, SetCondition};
+use crate::error::{CommandError, CommandResult};
+use crate::resp::Value;
+
+#[derive(Default)]
+struct SetOptions {
+    expires_at: Option<Instant>,
+    condition: SetCondition,
+}
+
+/// Parses the `amount` given to an expiry option of `command`, which is
+/// relative to now for EX and PX, and a unix time for EXAT and PXAT.
+fn parse_expiry(option: &str, amount: &Value, command: &str) -> CommandResult<Instant> {
+    let invalid = || CommandError::err(&format!("invalid expire time in '{}' command", command));
+
+    let amount = parse_integer(amount)?;
+    if amount <= 0 {
+        return Err(invalid());
+    }
+
+    let amount = amount as u64;
+    let expires_at = match option {
+        "EX" => Instant::now().checked_add(Duration::from_secs(amount)),
+        "PX" => Instant::now().checked_add(Duration::from_millis(amount)),
+        "EXAT" => instant_from_unix(Duration::from_secs(amount)),
+        _ => instant_from_unix(Duration::from_millis(amount)),
+    };
+    expires_at.ok_or_else(invalid)
+}
+
+fn parse_set_options(options: &[Value]) -> CommandResult<SetOptions> {
+    let mut set_options = SetOptions::default();
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        let option = option.as_str()?.to_ascii_uppercase();
+        match option.as_str() {
+            "EX" | "PX" | "EXAT" | "PXAT" => {
+                if set_options.expires_at.is_some() {
+                    return Err(CommandError::SyntaxError);
+                }
+
+                let amount = options.next().ok_or(CommandError::SyntaxError)?;
+                set_options.expires_at = Some(parse_expiry(&option, amount, "set")?);
+            }
+            "NX" | "XX" => {
+                if set_options.condition != SetCondition::Always {
+                    return Err(CommandError::SyntaxError);
+                }
+
+                set_options.condition = if option == "NX" {
+                    SetCondition::IfNotExists
+                } else {
+                    SetCondition::IfExists
+                };
+            }
+            _ => return Err(CommandError::SyntaxError),
+        }
+    }
+
+    Ok(set_options)
+}
+
+pub fn set(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let key = args[0].as_string()?;
+    let value = args[1].as_bytes()?;
+    let options = parse_set_options(&args[2..])?;
+    if session
+        .db()
+        .set_if(key, value, options.expires_at, options.condition)
+    {
+        Ok(ok())
+    } else {
+        Ok(Value::Null)
+    }
+}
+
+pub fn setnx(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let set = session.db().set_if(
+        args[0].as_string()?,
+        args[1].as_bytes()?,
+        None,
+        SetCondition::IfNotExists,
+    );
+    Ok(Value::Number(set as i64))
+}
+
+pub fn setex(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    set_expiring(args, "EX", "setex", session)
+}
+
+pub fn psetex(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    set_expiring(args, "PX", "psetex", session)
+}
+
+/// Runs SETEX and PSETEX, which take the key, its time to live in the unit
+/// of `option`, then its value.
+fn set_expiring(
+    args: &[Value],
+    option: &str,
+    command: &str,
+    session: &mut Session,
+) -> CommandResult<Value> {
+    let expires_at = parse_expiry(option, &args[1], command)?;
+    session.db().set_if(
+        args[0].as_string()?,
+        args[2].as_bytes()?,
+        Some(expires_at),
+        SetCondition::Always,
+    );
+    Ok(ok())
+}
+
+pub fn get(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    match session.db().get(args[0].as_str()?)? {
+        Some(data) => Ok(Value::bulk(data)),
+        None => Ok(Value::Null),
+    }
+}
+
+pub fn getdel(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    match session.db().get_delete(args[0].as_str()?)? {
+        Some(data) => Ok(Value::bulk(data)),
+        None => Ok(Value::Null),
+    }
+}
+
+pub fn getset(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    match session
+        .db()
+        .get_set(args[0].as_str()?, args[1].as_bytes()?)?
+    {
+        Some(data) => Ok(Value::bulk(data)),
+        None => Ok(Value::Null),
+    }
+}
+
+pub fn mget(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let keys = args
+        .iter()
+        .map(|key| key.as_str())
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let values = session
+        .db()
+        .get_many(&keys)
+        .into_iter()
+        .map(|value| match value {
+            Some(data) => Value::bulk(data),
+            None => Value::Null,
+        })
+        .collect();
+    Ok(Value::array(values))
+}
+
+pub fn mset(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    if args.len() % 2 == 1 {
+        return Err(CommandError::SyntaxError);
+    }
+
+    let pairs = args
+        .chunks(2)
+        .map(|pair| Ok((pair[0].as_string()?, pair[1].as_bytes()?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    session.db().set_many(pairs);
+    Ok(ok())
+}
+
+pub fn append(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let len = session
+        .db()
+        .append(args[0].as_str()?, &args[1].as_bytes()?)?;
+    Ok(Value::Number(len as i64))
+}
+
+pub fn strlen(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let value = session.db().get(args[0].as_str()?)?;
+    Ok(Value::Number(value.map_or(0, |value| value.len()) as i64))
+}
+
+pub fn getrange(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let start = parse_integer(&args[1])?;
+    let end = parse_integer(&args[2])?;
+    let data = session.db().get_range(args[0].as_str()?, start, end)?;
+    Ok(Value::bulk(data))
+}
+
+pub fn setrange(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let offset = parse_integer(&args[1])?;
+    if offset < 0 {
+        return Err(CommandError::err("offset is out of range"));
+    }
+    let len = session
+        .db()
+        .set_range(args[0].as_str()?, offset as usize, &args[2].as_bytes()?)?;
+    Ok(Value::Number(len as i64))
+}
+
+pub fn incr(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    Ok(Value::Number(session.db().incr_by(args[0].as_str()?, 1)?))
+}
+
+pub fn decr(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    Ok(Value::Number(session.db().incr_by(args[0].as_str()?, -1)?))
+}
+
+pub fn incrby(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let delta = parse_integer(&args[1])?;
+    Ok(Value::Number(
+        session.db().incr_by(args[0].as_str()?, delta)?,
+    ))
+}
+
+pub fn decrby(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let delta = parse_integer(&args[1])?
+        .checked_neg()
+        .ok_or_else(|| CommandError::err("decrement would overflow"))?;
+    Ok(Value::Number(
+        session.db().incr_by(args[0].as_str()?, delta)?,
+    ))
+}
+
+pub fn incrbyfloat(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    let delta = parse_float(&args[1])?;
+    let value = session.db().incr_by_float(args[0].as_str()?, delta)?;
+    Ok(Value::bulk(value))
+}
@@ -0,0 +1,196 @@
+use super::{ok, Session};
+use crate::error::{CommandError, CommandResult};
+use crate::resp::Value;
+
+/// Commands queued between MULTI and EXEC.
+#[derive(Default)]
+pub struct Transaction {
+    pub commands: Vec<(&'static str, Vec<Value>)>,
+    /// Whether a command could not be queued, in which case EXEC discards
+    /// the whole transaction.
+    pub failed: bool,
+}
+
+pub fn multi(_args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    if session.transaction.is_some() {
+        return Err(CommandError::err("MULTI calls can not be nested"));
+    }
+
+    session.transaction = Some(Transaction::default());
+    Ok(ok())
+}
+
+/// Only reached outside of a transaction, since the registry runs EXEC
+/// itself once MULTI was called.
+pub fn exec(_args: &[Value], _session: &mut Session) -> CommandResult<Value> {
+    Err(CommandError::err("EXEC without MULTI"))
+}
+
+pub fn discard(_args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    match session.transaction.take() {
+        Some(_) => {
+            session.unwatch();
+            Ok(ok())
+        }
+        None => Err(CommandError::err("DISCARD without MULTI")),
+    }
+}
+
+pub fn watch(args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    if session.transaction.is_some() {
+        return Err(CommandError::err("WATCH inside MULTI is not allowed"));
+    }
+
+    for arg in args {
+        session.watch(arg.as_string()?);
+    }
+    Ok(ok())
+}
+
+pub fn unwatch(_args: &[Value], session: &mut Session) -> CommandResult<Value> {
+    session.unwatch();
+    Ok(ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    use crate::command::tests::{args, session};
+    use crate::command::Registry;
+    use crate::error::CommandError;
+    use crate::resp::Value;
+
+    #[tokio::test]
+    async fn it_queues_commands_until_exec() -> Result<()> {
+        let registry = Registry::new();
+        let mut session = session();
+
+        registry.execute("MULTI", &[], &mut session).await?;
+        assert_eq!(
+            registry
+                .execute("SET", &args(&["key", "1"]), &mut session)
+                .await?,
+            Value::String("QUEUED".to_string())
+        );
+        registry
+            .execute("INCR", &args(&["key"]), &mut session)
+            .await?;
+        assert!(!session.db().contains("key"));
+
+        assert_eq!(
+            registry.execute("EXEC", &[], &mut session).await?,
+            Value::array(vec![Value::String("OK".to_string()), Value::Number(2)])
+        );
+        assert_eq!(session.db().get("key")?, Some(Bytes::from("2")));
+        assert!(session.transaction.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_discards_a_transaction_with_a_command_that_failed_to_queue() -> Result<()> {
+        let registry = Registry::new();
+        let mut session = session();
+
+        registry.execute("MULTI", &[], &mut session).await?;
+        registry
+            .execute("SET", &args(&["key", "1"]), &mut session)
+            .await?;
+        assert_eq!(
+            registry.execute("GET", &[], &mut session).await,
+            Err(CommandError::WrongArgCount("get".to_string()))
+        );
+
+        assert_eq!(
+            registry.execute("EXEC", &[], &mut session).await,
+            Err(CommandError::Other(
+                "EXECABORT Transaction discarded because of previous errors.".to_string()
+            ))
+        );
+        assert!(!session.db().contains("key"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_aborts_exec_once_a_watched_key_changed() -> Result<()> {
+        let registry = Registry::new();
+        let mut session = session();
+
+        registry
+            .execute("WATCH", &args(&["key"]), &mut session)
+            .await?;
+        registry.execute("MULTI", &[], &mut session).await?;
+        registry
+            .execute("SET", &args(&["key", "mine"]), &mut session)
+            .await?;
+        session.db().remove("key");
+        session
+            .db()
+            .set_many(vec![("key".to_string(), Bytes::from("theirs"))]);
+
+        assert_eq!(
+            registry.execute("EXEC", &[], &mut session).await?,
+            Value::NullArray
+        );
+        assert_eq!(session.db().get("key")?, Some(Bytes::from("theirs")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_forgets_watched_keys_on_unwatch() -> Result<()> {
+        let registry = Registry::new();
+        let mut session = session();
+
+        registry
+            .execute("WATCH", &args(&["key"]), &mut session)
+            .await?;
+        registry.execute("UNWATCH", &[], &mut session).await?;
+        session
+            .db()
+            .set_many(vec![("key".to_string(), Bytes::from("theirs"))]);
+        registry.execute("MULTI", &[], &mut session).await?;
+        registry
+            .execute("GET", &args(&["key"]), &mut session)
+            .await?;
+
+        assert_eq!(
+            registry.execute("EXEC", &[], &mut session).await?,
+            Value::array(vec![Value::bulk(Bytes::from("theirs"))])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_rejects_transaction_commands_out_of_place() -> Result<()> {
+        let registry = Registry::new();
+        let mut session = session();
+
+        assert_eq!(
+            registry.execute("EXEC", &[], &mut session).await,
+            Err(CommandError::err("EXEC without MULTI"))
+        );
+        assert_eq!(
+            registry.execute("DISCARD", &[], &mut session).await,
+            Err(CommandError::err("DISCARD without MULTI"))
+        );
+
+        registry.execute("MULTI", &[], &mut session).await?;
+        assert_eq!(
+            registry.execute("MULTI", &[], &mut session).await,
+            Err(CommandError::err("MULTI calls can not be nested"))
+        );
+        assert_eq!(
+            registry
+                .execute("WATCH", &args(&["key"]), &mut session)
+                .await,
+            Err(CommandError::err("WATCH inside MULTI is not allowed"))
+        );
+
+        Ok(())
+    }
+}
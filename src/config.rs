@@ -0,0 +1,350 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Result};
+
+use crate::error::{CommandError, CommandResult};
+use crate::glob;
+
+/// Names of the parameters CONFIG GET can read.
+const PARAMETERS: &[&str] = &["bind", "dbfilename", "dir", "maxmemory", "port", "timeout"];
+
+/// Server settings, read from the command line. Some of them can be changed
+/// at runtime with CONFIG SET.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Host the listener binds to.
+    pub bind: String,
+    pub port: u16,
+    /// Directory the RDB file is kept in.
+    pub dir: String,
+    pub dbfilename: String,
+    /// Memory limit in bytes, where zero means no limit.
+    pub maxmemory: u64,
+    /// Host and port of the master this server replicates, if it is a
+    /// replica.
+    pub replicaof: Option<(String, u16)>,
+    /// Seconds a client may stay idle before it is disconnected, where zero
+    /// means never.
+    pub timeout: u64,
+    /// Whether the dataset is saved to the RDB file when the server is
+    /// asked to stop.
+    pub save_on_shutdown: bool,
+    /// Most clients served at once. Clients connecting past it are turned
+    /// away.
+    pub maxclients: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: "127.0.0.1".to_string(),
+            port: 6379,
+            dir: ".".to_string(),
+            dbfilename: "dump.rdb".to_string(),
+            maxmemory: 0,
+            replicaof: None,
+            timeout: 0,
+            save_on_shutdown: false,
+            maxclients: 10000,
+        }
+    }
+}
+
+impl Config {
+    /// Builds the config from `--name value` flags, such as `--port 6380`,
+    /// keeping the defaults for the settings that are not given.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format_err!("missing value for {}", flag))?;
+            match flag.as_str() {
+                "--bind" => config.bind = value,
+                "--dir" => config.dir = value,
+                "--dbfilename" => config.dbfilename = value,
+                "--port" => config.port = parse_port(&value)?,
+                "--timeout" => {
+                    config.timeout = value
+                        .parse()
+                        .map_err(|_| format_err!("invalid timeout '{}'", value))?
+                }
+                "--save-on-shutdown" => config.save_on_shutdown = parse_yes_no(&value)?,
+                "--maxclients" => {
+                    config.maxclients = match value.parse() {
+                        Ok(maxclients) if maxclients > 0 => maxclients,
+                        _ => bail!("invalid maxclients '{}'", value),
+                    }
+                }
+                "--replicaof" => {
+                    // The master is given as a single "host port" argument.
+                    let (host, port) = value
+                        .split_once(' ')
+                        .ok_or_else(|| format_err!("invalid master address '{}'", value))?;
+                    config.replicaof = Some((host.to_string(), parse_port(port)?));
+                }
+                _ => bail!("unknown option {}", flag),
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// The address the listener binds to.
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.bind, self.port)
+    }
+
+    /// How long a client may stay idle before it is disconnected, if at all.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        match self.timeout {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        }
+    }
+
+    /// Where the RDB file is read from and saved to.
+    pub fn rdb_path(&self) -> PathBuf {
+        PathBuf::from(&self.dir).join(&self.dbfilename)
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        match name {
+            "bind" => Some(self.bind.clone()),
+            "dbfilename" => Some(self.dbfilename.clone()),
+            "dir" => Some(self.dir.clone()),
+            "maxmemory" => Some(self.maxmemory.to_string()),
+            "port" => Some(self.port.to_string()),
+            "timeout" => Some(self.timeout.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Returns the parameters whose name matches the glob `pattern`, along
+    /// with their values.
+    pub fn matching(&self, pattern: &str) -> Vec<(&'static str, String)> {
+        let pattern = pattern.to_ascii_lowercase();
+        PARAMETERS
+            .iter()
+            .filter(|name| glob::matches(pattern.as_bytes(), name.as_bytes()))
+            .filter_map(|name| Some((*name, self.get(name)?)))
+            .collect()
+    }
+
+    /// Changes the parameter `name` to `value`.
+    pub fn set(&mut self, name: &str, value: &str) -> CommandResult<()> {
+        let name = name.to_ascii_lowercase();
+        match name.as_str() {
+            "dir" => self.dir = value.to_string(),
+            "dbfilename" => self.dbfilename = value.to_string(),
+            "maxmemory" => {
+                self.maxmemory = parse_memory(value).ok_or_else(|| {
+                    CommandError::Other(format!(
+                        "ERR CONFIG SET failed (possibly related to argument '{}') - argument must be a memory value",
+                        name
+                    ))
+                })?
+            }
+            "timeout" => {
+                self.timeout = value.parse().map_err(|_| {
+                    CommandError::Other(format!(
+                        "ERR CONFIG SET failed (possibly related to argument '{}') - argument couldn't be parsed into an integer",
+                        name
+                    ))
+                })?
+            }
+            "bind" | "port" => {
+                return Err(CommandError::Other(format!(
+                    "ERR CONFIG SET failed (possibly related to argument '{}') - can't set immutable config",
+                    name
+                )))
+            }
+            _ => {
+                return Err(CommandError::Other(format!(
+                    "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                    name
+                )))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_port(value: &str) -> Result<u16> {
+    value
+        .parse()
+        .map_err(|_| format_err!("invalid port '{}'", value))
+}
+
+fn parse_yes_no(value: &str) -> Result<bool> {
+    match value {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => bail!("expected yes or no, got '{}'", value),
+    }
+}
+
+/// Parses a memory amount such as `100mb`, where `k`, `m` and `g` count in
+/// thousands and `kb`, `mb` and `gb` in multiples of 1024.
+fn parse_memory(value: &str) -> Option<u64> {
+    let value = value.to_ascii_lowercase();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(digits);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+
+    amount.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_memory, Config};
+    use crate::error::CommandError;
+
+    use std::time::Duration;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn it_defaults_to_the_standard_address() {
+        let config = Config::from_args(vec![]).unwrap();
+
+        assert_eq!(config, Config::default());
+        assert_eq!(config.address(), "127.0.0.1:6379");
+    }
+
+    #[test]
+    fn it_reads_the_port_and_bind_address() {
+        let config = Config::from_args(args(&["--port", "6380", "--bind", "0.0.0.0"])).unwrap();
+
+        assert_eq!(config.port, 6380);
+        assert_eq!(config.address(), "0.0.0.0:6380");
+    }
+
+    #[test]
+    fn it_reads_the_rdb_location() {
+        let config = Config::from_args(args(&[
+            "--dir",
+            "/tmp/redis-files",
+            "--dbfilename",
+            "data.rdb",
+        ]))
+        .unwrap();
+
+        assert_eq!(config.dir, "/tmp/redis-files");
+        assert_eq!(config.dbfilename, "data.rdb");
+    }
+
+    #[test]
+    fn it_reads_the_master_to_replicate() {
+        let config = Config::from_args(args(&["--replicaof", "localhost 6379"])).unwrap();
+
+        assert_eq!(config.replicaof, Some(("localhost".to_string(), 6379)));
+        assert!(Config::from_args(args(&["--replicaof", "localhost"])).is_err());
+        assert!(Config::from_args(args(&["--replicaof", "localhost port"])).is_err());
+    }
+
+    #[test]
+    fn it_reads_the_idle_timeout() {
+        let config = Config::from_args(args(&["--timeout", "30"])).unwrap();
+
+        assert_eq!(config.idle_timeout(), Some(Duration::from_secs(30)));
+        assert_eq!(Config::default().idle_timeout(), None);
+        assert!(Config::from_args(args(&["--timeout", "-1"])).is_err());
+    }
+
+    #[test]
+    fn it_reads_the_client_limit() {
+        let config = Config::from_args(args(&["--maxclients", "2"])).unwrap();
+
+        assert_eq!(config.maxclients, 2);
+        assert_eq!(Config::default().maxclients, 10000);
+        assert!(Config::from_args(args(&["--maxclients", "0"])).is_err());
+        assert!(Config::from_args(args(&["--maxclients", "many"])).is_err());
+    }
+
+    #[test]
+    fn it_reads_whether_to_save_on_shutdown() {
+        let config = Config::from_args(args(&["--save-on-shutdown", "yes"])).unwrap();
+
+        assert!(config.save_on_shutdown);
+        assert!(!Config::default().save_on_shutdown);
+        assert!(Config::from_args(args(&["--save-on-shutdown", "maybe"])).is_err());
+    }
+
+    #[test]
+    fn it_matches_parameters_by_glob() {
+        let config = Config::default();
+
+        assert_eq!(config.matching("dir"), vec![("dir", ".".to_string())]);
+        assert_eq!(
+            config.matching("D*"),
+            vec![
+                ("dbfilename", "dump.rdb".to_string()),
+                ("dir", ".".to_string())
+            ]
+        );
+        assert_eq!(config.matching("*").len(), 6);
+        assert!(config.matching("nope").is_empty());
+    }
+
+    #[test]
+    fn it_sets_mutable_parameters() {
+        let mut config = Config::default();
+
+        config.set("maxmemory", "100mb").unwrap();
+        config.set("DIR", "/data").unwrap();
+
+        assert_eq!(config.maxmemory, 100 * 1024 * 1024);
+        assert_eq!(config.dir, "/data");
+    }
+
+    #[test]
+    fn it_refuses_to_set_unknown_or_immutable_parameters() {
+        let mut config = Config::default();
+
+        assert_eq!(
+            config.set("nope", "1"),
+            Err(CommandError::Other(
+                "ERR Unknown option or number of arguments for CONFIG SET - 'nope'".to_string()
+            ))
+        );
+        assert!(config.set("port", "6380").is_err());
+        assert!(config.set("maxmemory", "lots").is_err());
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn it_parses_memory_units() {
+        assert_eq!(parse_memory("512"), Some(512));
+        assert_eq!(parse_memory("1k"), Some(1000));
+        assert_eq!(parse_memory("1KB"), Some(1024));
+        assert_eq!(parse_memory("2gb"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_memory("mb"), None);
+        assert_eq!(parse_memory("1tb"), None);
+        assert_eq!(parse_memory("-1"), None);
+    }
+
+    #[test]
+    fn it_rejects_invalid_arguments() {
+        assert!(Config::from_args(args(&["--port", "not-a-port"])).is_err());
+        assert!(Config::from_args(args(&["--port"])).is_err());
+        assert!(Config::from_args(args(&["--verbose", "yes"])).is_err());
+    }
+}
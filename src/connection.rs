@@ -1,6 +1,7 @@
-use bytes::BytesMut;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use std::time::Duration;
+
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 use anyhow::{bail, Result};
 
@@ -8,30 +9,97 @@ use crate::resp;
 
 pub type Arguments = Vec<resp::Value>;
 
-pub struct Connection {
-    stream: TcpStream,
+/// Raised when the peer closes its end of the connection.
+#[derive(Debug, thiserror::Error)]
+#[error("client closed connection")]
+pub struct ConnectionClosed;
+
+/// Raised when the peer sends nothing for longer than the read timeout.
+#[derive(Debug, thiserror::Error)]
+#[error("client idle for too long")]
+pub struct IdleTimeout;
+
+/// Reads commands from, and writes replies to, a client over any byte
+/// stream: a socket when serving clients, an in-memory pipe in tests.
+pub struct Connection<S> {
+    stream: S,
+    buffer: BytesMut,
+    write_buffer: BytesMut,
+    limits: resp::Limits,
+    /// Bytes of the values parsed so far.
+    consumed: u64,
+    /// How long to wait for the peer to send more of a value, if not
+    /// forever.
+    read_timeout: Option<Duration>,
 }
 
-impl Connection {
-    pub fn new(stream: TcpStream) -> Self {
-        Connection { stream }
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    pub fn new(stream: S) -> Self {
+        Connection {
+            stream,
+            buffer: BytesMut::with_capacity(4096),
+            write_buffer: BytesMut::with_capacity(4096),
+            limits: resp::Limits::default(),
+            consumed: 0,
+            read_timeout: None,
+        }
+    }
+
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// How many bytes the values read so far took up on the wire.
+    pub fn bytes_consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// Attempts to parse a complete value out of the buffered bytes, consuming
+    /// them on success. Returns `None` when more data is needed.
+    fn parse_value(&mut self) -> Result<Option<resp::Value>> {
+        // Frames are measured in place and only split off once whole, so a
+        // large value arriving in many reads is not copied on each of them.
+        let len = match resp::frame_len(&self.buffer, &self.limits) {
+            Err(resp::ParseError::Incomplete) => return Ok(None),
+            Err(err) => return Err(err.into()),
+            Ok(len) => len,
+        };
+
+        let mut frame = self.buffer.split_to(len).freeze();
+        let (value, _) = resp::parse_frame(&mut frame, &self.limits)?;
+        self.consumed += len as u64;
+        Ok(Some(value))
     }
 
     pub async fn read_value(&mut self) -> Result<resp::Value> {
-        let mut buffer = BytesMut::with_capacity(32);
-        let bytes_read = self.stream.read_buf(&mut buffer).await?;
-        if bytes_read == 0 {
-            bail!("client closed connection");
-        }
+        loop {
+            match self.parse_value() {
+                Ok(Some(value)) => return Ok(value),
+                Ok(None) => {}
+                Err(err) => {
+                    // Let the client know why the connection is being closed.
+                    self.write_value(&resp::Value::Error(format!("ERR {}", err)))
+                        .await?;
+                    self.flush().await?;
+                    return Err(err);
+                }
+            }
+
+            // Every buffered command has been handled, send their replies in
+            // one go before waiting on the client.
+            self.flush().await?;
 
-        let (value, leftover_data) = resp::parse_resp(&mut buffer.into())?;
-        if leftover_data.len() > 0 {
-            println!(
-                "[warn] {} leftover bytes after reading command",
-                leftover_data.len()
-            );
+            let read = self.stream.read_buf(&mut self.buffer);
+            let bytes_read = match self.read_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, read)
+                    .await
+                    .map_err(|_| IdleTimeout)??,
+                None => read.await?,
+            };
+            if bytes_read == 0 {
+                return Err(ConnectionClosed.into());
+            }
         }
-        return Ok(value);
     }
 
     pub async fn read_command(&mut self) -> Result<(String, Arguments)> {
@@ -58,8 +126,287 @@ impl Connection {
         }
     }
 
+    /// Reads an RDB file sent the way a master does after FULLRESYNC: like a
+    /// bulk string, but without the trailing CRLF.
+    pub async fn read_rdb_payload(&mut self) -> Result<Bytes> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|window| window == b"\r\n") {
+                if self.buffer[0] != b'$' {
+                    bail!("expected an RDB payload");
+                }
+                let len = std::str::from_utf8(&self.buffer[1..end])?.parse::<usize>()?;
+                if len as i64 > self.limits.max_bulk_len {
+                    bail!("RDB payload too large");
+                }
+                if self.buffer.len() >= end + 2 + len {
+                    self.buffer.advance(end + 2);
+                    return Ok(self.buffer.split_to(len).freeze());
+                }
+            }
+
+            let bytes_read = self.stream.read_buf(&mut self.buffer).await?;
+            if bytes_read == 0 {
+                return Err(ConnectionClosed.into());
+            }
+        }
+    }
+
+    /// Queues `bytes` to be sent on the next `flush`.
     pub async fn write_all(&mut self, bytes: &[u8]) -> Result<()> {
-        self.stream.write_all(bytes).await?;
+        self.write_buffer.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    pub async fn flush(&mut self) -> Result<()> {
+        if !self.write_buffer.is_empty() {
+            self.stream.write_all(&self.write_buffer).await?;
+            self.write_buffer.clear();
+        }
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    pub async fn write_value(&mut self, value: &resp::Value) -> Result<()> {
+        self.write_all(&value.encode()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Connection, ConnectionClosed, IdleTimeout};
+    use crate::resp::{ParseError, Value};
+
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use bytes::Bytes;
+    use tokio::io::{self, AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    /// Connects a server side connection to a client through an in-memory
+    /// pipe, large enough that no test has to read and write concurrently.
+    fn connection_pair() -> (Connection<DuplexStream>, DuplexStream) {
+        let (server, client) = io::duplex(64 * 1024);
+
+        (Connection::new(server), client)
+    }
+
+    #[tokio::test]
+    async fn it_reads_a_command() -> Result<()> {
+        let (mut conn, mut client) = connection_pair();
+
+        client.write_all(b"*1\r\n$4\r\nPING\r\n").await?;
+
+        let (command, args) = conn.read_command().await?;
+        assert_eq!(command, "PING");
+        assert!(args.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_reads_a_value_split_across_multiple_reads() -> Result<()> {
+        let (mut conn, mut client) = connection_pair();
+
+        let reader = tokio::spawn(async move { conn.read_value().await });
+        client.write_all(b"*2\r\n$4\r\nECHO\r\n$5\r\nhel").await?;
+        client.flush().await?;
+        tokio::time::delay_for(std::time::Duration::from_millis(10)).await;
+        client.write_all(b"lo\r\n").await?;
+
+        assert_eq!(
+            reader.await??,
+            Value::Array {
+                len: 2,
+                elements: vec![
+                    Value::Bulk {
+                        size: 4,
+                        data: Bytes::from("ECHO")
+                    },
+                    Value::Bulk {
+                        size: 5,
+                        data: Bytes::from("hello")
+                    },
+                ]
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_reads_a_command_sent_one_byte_at_a_time() -> Result<()> {
+        let (mut conn, mut client) = connection_pair();
+
+        let reader = tokio::spawn(async move { conn.read_command().await });
+        for byte in b"*2\r\n$4\r\nECHO\r\n$2\r\nhi\r\n" {
+            client.write_all(&[*byte]).await?;
+            client.flush().await?;
+            tokio::time::delay_for(Duration::from_millis(1)).await;
+        }
+
+        let (command, args) = reader.await??;
+        assert_eq!(command, "ECHO");
+        assert_eq!(args, vec![Value::bulk(Bytes::from("hi"))]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_reads_a_value_larger_than_the_initial_buffer() -> Result<()> {
+        let (mut conn, mut client) = connection_pair();
+
+        let payload = "x".repeat(10_000);
+        client
+            .write_all(format!("${}\r\n{}\r\n", payload.len(), payload).as_bytes())
+            .await?;
+
+        assert_eq!(
+            conn.read_value().await?,
+            Value::Bulk {
+                size: payload.len() as i64,
+                data: Bytes::from(payload)
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_reads_a_large_value_arriving_in_many_reads() -> Result<()> {
+        let (mut conn, mut client) = connection_pair();
+
+        let payload = "x".repeat(4 * 1024 * 1024);
+        let writer = tokio::spawn(async move {
+            let frame = format!("${}\r\n{}\r\n+next\r\n", payload.len(), payload);
+            for chunk in frame.as_bytes().chunks(16 * 1024) {
+                client.write_all(chunk).await?;
+            }
+            Ok::<_, io::Error>(client)
+        });
+
+        match conn.read_value().await? {
+            Value::Bulk { size, data } => {
+                assert_eq!(size, 4 * 1024 * 1024);
+                assert!(data.iter().all(|byte| *byte == b'x'));
+            }
+            value => panic!("unexpected value: {:?}", value),
+        }
+        assert_eq!(conn.read_value().await?, Value::String("next".to_string()));
+        writer.await??;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_writes_an_encoded_value() -> Result<()> {
+        let (mut conn, mut client) = connection_pair();
+
+        conn.write_value(&Value::Array {
+            len: 2,
+            elements: vec![Value::bulk(Bytes::from("hello")), Value::Null],
+        })
+        .await?;
+        conn.flush().await?;
+
+        let expected = b"*2\r\n$5\r\nhello\r\n$-1\r\n";
+        let mut written = vec![0; expected.len()];
+        client.read_exact(&mut written).await?;
+        assert_eq!(written, expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_batches_writes_until_flushed() -> Result<()> {
+        let (mut conn, mut client) = connection_pair();
+
+        for _ in 0..1000 {
+            conn.write_value(&Value::String("OK".to_string())).await?;
+        }
+
+        let mut written = vec![0; 5 * 1000];
+        let early_read = tokio::time::timeout(
+            std::time::Duration::from_millis(20),
+            client.read_exact(&mut written),
+        )
+        .await;
+        assert!(early_read.is_err(), "replies were sent before flushing");
+
+        conn.flush().await?;
+        client.read_exact(&mut written).await?;
+        assert_eq!(written, b"+OK\r\n".repeat(1000));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_reports_a_closed_connection() -> Result<()> {
+        let (mut conn, client) = connection_pair();
+        drop(client);
+
+        let err = conn.read_value().await.unwrap_err();
+        assert!(err.is::<ConnectionClosed>());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_gives_up_on_a_client_idle_past_the_read_timeout() -> Result<()> {
+        let (mut conn, mut client) = connection_pair();
+        conn.set_read_timeout(Some(Duration::from_millis(50)));
+
+        // Half a command, then nothing.
+        client.write_all(b"*1\r\n$4\r\nPI").await?;
+
+        let err = conn.read_value().await.unwrap_err();
+        assert!(err.is::<IdleTimeout>());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_rejects_an_oversized_bulk_string_declaration() -> Result<()> {
+        let (mut conn, mut client) = connection_pair();
+
+        client.write_all(b"*1\r\n$100000000000\r\n").await?;
+
+        let err = conn.read_value().await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(ParseError::Protocol(_))));
+
+        let expected = b"-ERR Protocol error: invalid bulk length\r\n";
+        let mut written = vec![0; expected.len()];
+        client.read_exact(&mut written).await?;
+        assert_eq!(written, expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_rejects_a_malformed_frame_without_waiting_for_more() -> Result<()> {
+        let (mut conn, mut client) = connection_pair();
+
+        client.write_all(b"*2\r\n:nope\r\n").await?;
+
+        assert!(conn.read_value().await.is_err());
+
+        let expected = b"-ERR Protocol error: number parsing failed, invalid integer: 'nope'\r\n";
+        let mut written = vec![0; expected.len()];
+        client.read_exact(&mut written).await?;
+        assert_eq!(written, expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_reads_an_rdb_payload_without_a_trailing_crlf() -> Result<()> {
+        let (mut conn, mut client) = connection_pair();
+
+        client.write_all(b"$5\r\nREDIS*1\r\n$4\r\nPING\r\n").await?;
+
+        assert_eq!(conn.read_rdb_payload().await?, Bytes::from("REDIS"));
+        assert_eq!(conn.read_command().await?.0, "PING");
+        assert_eq!(conn.bytes_consumed(), 14);
+
         Ok(())
     }
 }
@@ -0,0 +1,2951 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::{Bytes, BytesMut};
+use tokio::sync::broadcast;
+
+use crate::error::CommandError;
+use crate::glob;
+use crate::sorted_set::{LexBound, ScoreBound, SortedSet};
+
+type Result<T> = std::result::Result<T, CommandError>;
+
+/// Number of logical databases that clients can switch between with SELECT.
+pub const DATABASES: usize = 16;
+
+/// How often the background task samples the store for expired keys.
+pub const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many keys with an expiry each round of active expiry draws. Rounds
+/// go on while more than a quarter of the keys drawn could be reclaimed.
+const ACTIVE_EXPIRE_SAMPLE: usize = 20;
+
+/// How long active expiry may run on each tick, which like Redis is a
+/// quarter of the interval, split between the databases.
+const ACTIVE_EXPIRE_BUDGET: Duration = Duration::from_micros(25_000 / DATABASES as u64);
+
+/// Converts a Unix timestamp into the matching point on the monotonic clock.
+/// Timestamps in the past map to the current instant.
+pub fn instant_from_unix(timestamp: Duration) -> Option<Instant> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    match timestamp.checked_sub(now) {
+        Some(remaining) => Instant::now().checked_add(remaining),
+        None => Some(Instant::now()),
+    }
+}
+
+/// Converts a point on the monotonic clock into a Unix timestamp.
+pub fn unix_from_instant(instant: Instant) -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let now_instant = Instant::now();
+    if instant >= now_instant {
+        now + (instant - now_instant)
+    } else {
+        now.saturating_sub(now_instant - instant)
+    }
+}
+
+/// Parses a stored string value as a signed 64-bit integer.
+fn parse_integer(value: &Bytes) -> Option<i64> {
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// Parses a stored string value as a finite double.
+fn parse_float(value: &Bytes) -> Option<f64> {
+    let value: f64 = std::str::from_utf8(value).ok()?.parse().ok()?;
+    if value.is_finite() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Resolves the inclusive `start` and `end` offsets of a range over `len`
+/// elements, where negative offsets count from the end, clamping them to the
+/// bounds. Returns `None` when the range is empty.
+fn normalize_range(start: i64, end: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 { len + end } else { end.min(len - 1) };
+    if start > end || end < 0 {
+        return None;
+    }
+
+    Some((start as usize, end as usize))
+}
+
+/// Resolves a possibly negative `index` into a list of `len` elements.
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    if index < 0 || index >= len as i64 {
+        return None;
+    }
+
+    Some(index as usize)
+}
+
+/// Longest string Redis embeds in its object header rather than allocating
+/// separately.
+const EMBSTR_MAX_LEN: usize = 44;
+
+/// Most elements, and longest element, a collection holds before Redis
+/// switches it from a compact listpack to a proper data structure.
+const LISTPACK_MAX_ENTRIES: usize = 128;
+const LISTPACK_MAX_VALUE: usize = 64;
+
+/// Most members a set of integers holds as an intset.
+const INTSET_MAX_ENTRIES: usize = 512;
+
+/// Whether a string is an integer printed the way Redis prints it, so not
+/// "007" or "+1", which Redis keeps as strings.
+fn is_canonical_integer(value: &Bytes) -> bool {
+    matches!(parse_integer(value), Some(n) if n.to_string().as_bytes() == value)
+}
+
+/// Whether Redis would keep `len` elements of the given sizes in a listpack.
+fn fits_listpack<'a>(len: usize, mut elements: impl Iterator<Item = &'a Bytes>) -> bool {
+    len <= LISTPACK_MAX_ENTRIES && elements.all(|element| element.len() <= LISTPACK_MAX_VALUE)
+}
+
+type List = VecDeque<Bytes>;
+type Hash = HashMap<Bytes, Bytes>;
+type Set = HashSet<Bytes>;
+
+/// A value held by the store, tagged with its Redis data type.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StoredValue {
+    String(Bytes),
+    List(List),
+    Hash(Hash),
+    Set(Set),
+    ZSet(SortedSet),
+}
+
+impl StoredValue {
+    /// Name of the type as reported by the TYPE command.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            StoredValue::String(_) => "string",
+            StoredValue::List(_) => "list",
+            StoredValue::Hash(_) => "hash",
+            StoredValue::Set(_) => "set",
+            StoredValue::ZSet(_) => "zset",
+        }
+    }
+
+    /// Name of the encoding Redis would pick for the value, as reported by
+    /// OBJECT ENCODING. Values are always stored the same way here, so this
+    /// only mimics the thresholds Redis uses by default.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            StoredValue::String(value) if is_canonical_integer(value) => "int",
+            StoredValue::String(value) if value.len() <= EMBSTR_MAX_LEN => "embstr",
+            StoredValue::String(_) => "raw",
+            StoredValue::List(list) if fits_listpack(list.len(), list.iter()) => "listpack",
+            StoredValue::List(_) => "quicklist",
+            StoredValue::Hash(hash)
+                if fits_listpack(hash.len(), hash.keys().chain(hash.values())) =>
+            {
+                "listpack"
+            }
+            StoredValue::Set(set)
+                if set.len() <= INTSET_MAX_ENTRIES
+                    && set.iter().all(|member| parse_integer(member).is_some()) =>
+            {
+                "intset"
+            }
+            StoredValue::Set(set) if fits_listpack(set.len(), set.iter()) => "listpack",
+            StoredValue::Hash(_) | StoredValue::Set(_) => "hashtable",
+            StoredValue::ZSet(zset) if fits_listpack(zset.len(), zset.members()) => "listpack",
+            StoredValue::ZSet(_) => "skiplist",
+        }
+    }
+}
+
+struct Entry {
+    value: StoredValue,
+    expires_at: Option<Instant>,
+}
+
+/// A type of value the store holds, which commands can look keys up as.
+trait Typed {
+    fn from_value(value: &StoredValue) -> Option<&Self>;
+    fn from_value_mut(value: &mut StoredValue) -> Option<&mut Self>;
+}
+
+impl Typed for Bytes {
+    fn from_value(value: &StoredValue) -> Option<&Self> {
+        match value {
+            StoredValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut StoredValue) -> Option<&mut Self> {
+        match value {
+            StoredValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl Typed for List {
+    fn from_value(value: &StoredValue) -> Option<&Self> {
+        match value {
+            StoredValue::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut StoredValue) -> Option<&mut Self> {
+        match value {
+            StoredValue::List(list) => Some(list),
+            _ => None,
+        }
+    }
+}
+
+impl Typed for Hash {
+    fn from_value(value: &StoredValue) -> Option<&Self> {
+        match value {
+            StoredValue::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut StoredValue) -> Option<&mut Self> {
+        match value {
+            StoredValue::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+}
+
+impl Typed for Set {
+    fn from_value(value: &StoredValue) -> Option<&Self> {
+        match value {
+            StoredValue::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut StoredValue) -> Option<&mut Self> {
+        match value {
+            StoredValue::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+}
+
+impl Typed for SortedSet {
+    fn from_value(value: &StoredValue) -> Option<&Self> {
+        match value {
+            StoredValue::ZSet(zset) => Some(zset),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut StoredValue) -> Option<&mut Self> {
+        match value {
+            StoredValue::ZSet(zset) => Some(zset),
+            _ => None,
+        }
+    }
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= now,
+            None => false,
+        }
+    }
+
+    /// Returns the value as a `T`, or a WRONGTYPE error if the key holds
+    /// another type. Every type-specific command goes through this, so they
+    /// all reject mismatched keys the same way.
+    fn typed<T: Typed>(&self) -> Result<&T> {
+        T::from_value(&self.value).ok_or(CommandError::WrongType)
+    }
+
+    /// Returns the value as a mutable `T`, or a WRONGTYPE error if the key
+    /// holds another type.
+    fn typed_mut<T: Typed>(&mut self) -> Result<&mut T> {
+        T::from_value_mut(&mut self.value).ok_or(CommandError::WrongType)
+    }
+}
+
+/// Keys that were given an expiry, which active expiry draws from rather
+/// than walking the whole keyspace. Keys deleted or persisted since stay
+/// listed until they are drawn.
+#[derive(Default)]
+struct VolatileKeys {
+    keys: Vec<String>,
+    listed: HashSet<String>,
+}
+
+impl VolatileKeys {
+    fn insert(&mut self, key: &str) {
+        if self.listed.insert(key.to_string()) {
+            self.keys.push(key.to_string());
+        }
+    }
+
+    fn swap_remove(&mut self, index: usize) {
+        let key = self.keys.swap_remove(index);
+        self.listed.remove(&key);
+    }
+}
+
+/// The keys of a database along with their entries, which are kept in a
+/// vector so that RANDOMKEY can draw one without walking the others.
+/// Removing a key moves the last entry into its slot.
+#[derive(Default)]
+struct Keyspace {
+    slots: HashMap<String, usize>,
+    entries: Vec<(String, Entry)>,
+}
+
+impl Keyspace {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.slots.contains_key(key)
+    }
+
+    fn get(&self, key: &str) -> Option<&Entry> {
+        self.slots.get(key).map(|&slot| &self.entries[slot].1)
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut Entry> {
+        let slot = *self.slots.get(key)?;
+        Some(&mut self.entries[slot].1)
+    }
+
+    /// Returns the key and entry in `slot`, where slots run from zero to the
+    /// number of keys.
+    fn get_index(&self, slot: usize) -> Option<(&String, &Entry)> {
+        self.entries.get(slot).map(|(key, entry)| (key, entry))
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        match self.slots.get(&key) {
+            Some(&slot) => Some(std::mem::replace(&mut self.entries[slot].1, entry)),
+            None => {
+                self.slots.insert(key.clone(), self.entries.len());
+                self.entries.push((key, entry));
+                None
+            }
+        }
+    }
+
+    /// Returns the entry at `key`, inserting the one `default` makes first
+    /// if there is none.
+    fn get_or_insert_with(&mut self, key: String, default: impl FnOnce() -> Entry) -> &mut Entry {
+        let slot = match self.slots.get(&key) {
+            Some(&slot) => slot,
+            None => {
+                let slot = self.entries.len();
+                self.slots.insert(key.clone(), slot);
+                self.entries.push((key, default()));
+                slot
+            }
+        };
+        &mut self.entries[slot].1
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let slot = self.slots.remove(key)?;
+        let (_, entry) = self.entries.swap_remove(slot);
+        if let Some((moved, _)) = self.entries.get(slot) {
+            self.slots.insert(moved.clone(), slot);
+        }
+        Some(entry)
+    }
+
+    fn clear(&mut self) {
+        self.slots.clear();
+        self.entries.clear();
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&String, &Entry)> {
+        self.entries.iter().map(|(key, entry)| (key, entry))
+    }
+
+    fn values(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().map(|(_, entry)| entry)
+    }
+}
+
+/// Removes `key` from `entries` if it has expired, so that callers can treat
+/// whatever remains as live.
+fn evict_if_expired(entries: &mut Keyspace, key: &str) {
+    if let Some(entry) = entries.get(key) {
+        if entry.is_expired(Instant::now()) {
+            entries.remove(key);
+        }
+    }
+}
+
+/// How many list pushes can be queued for a blocked client before it falls
+/// behind and has to re-check every key it waits on.
+const PUSH_CHANNEL_CAPACITY: usize = 1024;
+
+/// Largest string SETRANGE may produce, matching the default
+/// `proto-max-bulk-len`.
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// How many keys RANDOMKEY draws, evicting the expired ones, before giving
+/// up on finding a live one.
+const RANDOM_KEY_ATTEMPTS: usize = 100;
+
+/// Most members SRANDMEMBER returns when allowed to repeat them, matching
+/// the longest array a client may send, so that a huge count is refused
+/// rather than allocated.
+const MAX_RANDOM_REPEATS: usize = 1024 * 1024;
+
+/// Picks an index below `len`. `RandomState` is seeded differently for each
+/// instance, which is random enough for sampling keys.
+fn random_index(len: usize) -> usize {
+    RandomState::new().build_hasher().finish() as usize % len
+}
+
+/// Precondition on the existing key for a conditional set.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SetCondition {
+    #[default]
+    Always,
+    IfNotExists,
+    IfExists,
+}
+
+/// End of a list that elements are pushed to or popped from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+/// How SUNION, SINTER and SDIFF combine the sets they are given.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SetOperation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+/// Remaining time to live of a key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ttl {
+    Missing,
+    Persistent,
+    Remaining(Duration),
+}
+
+/// In-memory key/value store shared between all client connections.
+#[derive(Clone)]
+pub struct Db {
+    entries: Arc<Mutex<Keyspace>>,
+    /// Keys active expiry samples. Locked after `entries` when both are.
+    volatile: Arc<Mutex<VolatileKeys>>,
+    /// Announces the keys of lists that were pushed to, waking clients
+    /// blocked in BLPOP/BRPOP.
+    pushes: broadcast::Sender<String>,
+    /// Flags of the clients watching each key, set once the key is modified
+    /// so that their transaction is aborted.
+    watchers: Arc<Mutex<HashMap<String, Vec<Arc<AtomicBool>>>>>,
+}
+
+impl Default for Db {
+    fn default() -> Self {
+        let (pushes, _) = broadcast::channel(PUSH_CHANNEL_CAPACITY);
+        Db {
+            entries: Arc::default(),
+            volatile: Arc::default(),
+            pushes,
+            watchers: Arc::default(),
+        }
+    }
+}
+
+impl Db {
+    pub fn new() -> Self {
+        Db::default()
+    }
+
+    /// Sets `dirty` as soon as `key` is modified, which is how WATCH notices
+    /// changes.
+    pub fn watch(&self, key: &str, dirty: &Arc<AtomicBool>) {
+        self.watchers
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .push(dirty.clone());
+    }
+
+    /// Stops setting `dirty` when `key` is modified.
+    pub fn unwatch(&self, key: &str, dirty: &Arc<AtomicBool>) {
+        let mut watchers = self.watchers.lock().unwrap();
+        if let Some(flags) = watchers.get_mut(key) {
+            flags.retain(|flag| !Arc::ptr_eq(flag, dirty));
+            if flags.is_empty() {
+                watchers.remove(key);
+            }
+        }
+    }
+
+    /// Flags the clients watching `key` now that it was modified.
+    fn touch(&self, key: &str) {
+        if let Some(flags) = self.watchers.lock().unwrap().remove(key) {
+            for flag in flags {
+                flag.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Returns the value stored at `key`, evicting it first if it has expired.
+    pub fn get(&self, key: &str) -> Result<Option<Bytes>> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.is_expired(Instant::now()) => {
+                entries.remove(key);
+                Ok(None)
+            }
+            Some(entry) => Ok(Some(entry.typed::<Bytes>()?.clone())),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes the string stored at `key`, returning it.
+    pub fn get_delete(&self, key: &str) -> Result<Option<Bytes>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let value = match entries.get(key) {
+            Some(entry) => entry.typed::<Bytes>()?.clone(),
+            None => return Ok(None),
+        };
+        entries.remove(key);
+        self.touch(key);
+
+        Ok(Some(value))
+    }
+
+    /// Replaces the string stored at `key` with `value`, returning the old
+    /// one. As with SET, any expiry is discarded.
+    pub fn get_set(&self, key: &str, value: Bytes) -> Result<Option<Bytes>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let old = match entries.get(key) {
+            Some(entry) => Some(entry.typed::<Bytes>()?.clone()),
+            None => None,
+        };
+        entries.insert(
+            key.to_string(),
+            Entry {
+                value: StoredValue::String(value),
+                expires_at: None,
+            },
+        );
+        self.touch(key);
+
+        Ok(old)
+    }
+
+    /// Looks up several keys at once under a single lock. Keys holding
+    /// anything but a string are reported as missing.
+    pub fn get_many(&self, keys: &[&str]) -> Vec<Option<Bytes>> {
+        let entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        keys.iter()
+            .map(|key| match entries.get(key) {
+                Some(entry) if !entry.is_expired(now) => entry.typed::<Bytes>().ok().cloned(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Sets several keys at once, so no reader observes a partial update.
+    pub fn set_many(&self, pairs: Vec<(String, Bytes)>) {
+        let mut entries = self.entries.lock().unwrap();
+        for (key, value) in pairs {
+            self.touch(&key);
+            entries.insert(
+                key,
+                Entry {
+                    value: StoredValue::String(value),
+                    expires_at: None,
+                },
+            );
+        }
+    }
+
+    /// Returns the type name of the value at `key`, if it exists.
+    pub fn type_of(&self, key: &str) -> Option<&'static str> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if !entry.is_expired(Instant::now()) => Some(entry.value.type_name()),
+            _ => None,
+        }
+    }
+
+    /// Returns the encoding of the value at `key`, if it exists.
+    pub fn encoding_of(&self, key: &str) -> Option<&'static str> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if !entry.is_expired(Instant::now()) => Some(entry.value.encoding()),
+            _ => None,
+        }
+    }
+
+    /// Returns every live key matching the glob-style `pattern`.
+    pub fn keys(&self, pattern: &str) -> Vec<String> {
+        let entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries
+            .iter()
+            .filter(|(key, entry)| {
+                !entry.is_expired(now) && glob::matches(pattern.as_bytes(), key.as_bytes())
+            })
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Returns a random live key, or `None` if there is none. Expired keys
+    /// drawn along the way are evicted, and only so many are drawn so that
+    /// a mostly expired keyspace does not hold the lock for long.
+    pub fn random_key(&self) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+
+        for _ in 0..RANDOM_KEY_ATTEMPTS {
+            if entries.is_empty() {
+                return None;
+            }
+            let (key, entry) = entries.get_index(random_index(entries.len()))?;
+            if !entry.is_expired(now) {
+                return Some(key.clone());
+            }
+            let key = key.clone();
+            entries.remove(&key);
+        }
+        None
+    }
+
+    /// Walks the keyspace incrementally: returns up to `count` keys after
+    /// `cursor` in key order that match `pattern`, along with the cursor to
+    /// resume from, which is zero once every key has been visited.
+    pub fn scan(&self, cursor: usize, pattern: Option<&str>, count: usize) -> (usize, Vec<String>) {
+        let entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        let mut keys = entries
+            .iter()
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        let end = cursor.saturating_add(count).min(keys.len());
+        let batch = keys
+            .get(cursor..end)
+            .unwrap_or_default()
+            .iter()
+            .filter(|key| match pattern {
+                Some(pattern) => glob::matches(pattern.as_bytes(), key.as_bytes()),
+                None => true,
+            })
+            .map(|key| key.to_string())
+            .collect();
+        let next = if end < keys.len() { end } else { 0 };
+
+        (next, batch)
+    }
+
+    /// Returns the number of live keys.
+    pub fn len(&self) -> usize {
+        let entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries
+            .values()
+            .filter(|entry| !entry.is_expired(now))
+            .count()
+    }
+
+    /// Copies every live key along with its value and expiry time.
+    pub fn snapshot(&self) -> Vec<(String, StoredValue, Option<Instant>)> {
+        let entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries
+            .iter()
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| (key.clone(), entry.value.clone(), entry.expires_at))
+            .collect()
+    }
+
+    /// Copies the value at `key` along with its expiry time, if it exists.
+    pub fn dump(&self, key: &str) -> Option<(StoredValue, Option<Instant>)> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        entries
+            .get(key)
+            .map(|entry| (entry.value.clone(), entry.expires_at))
+    }
+
+    /// Stores `value` at `key`, unless the key exists and `replace` is unset.
+    /// Returns whether the value was stored.
+    pub fn restore(
+        &self,
+        key: String,
+        value: StoredValue,
+        expires_at: Option<Instant>,
+        replace: bool,
+    ) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, &key);
+
+        if !replace && entries.contains_key(&key) {
+            return false;
+        }
+        self.touch(&key);
+        if expires_at.is_some() {
+            self.volatile.lock().unwrap().insert(&key);
+        }
+        let is_list = matches!(value, StoredValue::List(_));
+        entries.insert(key.clone(), Entry { value, expires_at });
+        if is_list {
+            // A list appearing wakes clients blocked on the key, as a push
+            // would.
+            let _ = self.pushes.send(key);
+        }
+        true
+    }
+
+    /// Removes every key.
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clear();
+        *self.volatile.lock().unwrap() = VolatileKeys::default();
+        for (_, flags) in self.watchers.lock().unwrap().drain() {
+            for flag in flags {
+                flag.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) => !entry.is_expired(Instant::now()),
+            None => false,
+        }
+    }
+
+    pub fn ttl(&self, key: &str) -> Ttl {
+        let entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        match entries.get(key) {
+            Some(entry) if entry.is_expired(now) => Ttl::Missing,
+            Some(Entry {
+                expires_at: Some(expires_at),
+                ..
+            }) => Ttl::Remaining(expires_at.duration_since(now)),
+            Some(_) => Ttl::Persistent,
+            None => Ttl::Missing,
+        }
+    }
+
+    /// Sets the expiry of an existing `key`, returning whether it exists.
+    pub fn expire(&self, key: &str, expires_at: Instant) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(key) {
+            Some(entry) if !entry.is_expired(Instant::now()) => {
+                entry.expires_at = Some(expires_at);
+                self.volatile.lock().unwrap().insert(key);
+                self.touch(key);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Clears the expiry of `key`, returning whether one was removed.
+    pub fn persist(&self, key: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let persisted = match entries.get_mut(key) {
+            Some(entry) if !entry.is_expired(Instant::now()) => entry.expires_at.take().is_some(),
+            _ => false,
+        };
+        if persisted {
+            self.touch(key);
+        }
+
+        persisted
+    }
+
+    /// Removes `key`, returning whether a live entry was deleted.
+    pub fn remove(&self, key: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.remove(key) {
+            Some(entry) => {
+                self.touch(key);
+                !entry.is_expired(Instant::now())
+            }
+            None => false,
+        }
+    }
+
+    /// Adds `delta` to the integer stored at `key`, treating a missing key as
+    /// zero, and returns the new value. The key's expiry is left untouched.
+    pub fn incr_by(&self, key: &str, delta: i64) -> Result<i64> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+
+        let (current, expires_at) = match entries.get(key) {
+            Some(entry) if !entry.is_expired(now) => (
+                parse_integer(entry.typed::<Bytes>()?).ok_or(CommandError::NotInteger)?,
+                entry.expires_at,
+            ),
+            _ => (0, None),
+        };
+
+        let value = match current.checked_add(delta) {
+            Some(value) => value,
+            None => return Err(CommandError::err("increment or decrement would overflow")),
+        };
+        entries.insert(
+            key.to_string(),
+            Entry {
+                value: StoredValue::String(Bytes::from(value.to_string())),
+                expires_at,
+            },
+        );
+        self.touch(key);
+
+        Ok(value)
+    }
+
+    /// Adds `delta` to the float stored at `key`, treating a missing key as
+    /// zero, and returns the new value as it was stored.
+    pub fn incr_by_float(&self, key: &str, delta: f64) -> Result<Bytes> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+
+        let (current, expires_at) = match entries.get(key) {
+            Some(entry) if !entry.is_expired(now) => (
+                parse_float(entry.typed::<Bytes>()?).ok_or(CommandError::NotFloat)?,
+                entry.expires_at,
+            ),
+            _ => (0.0, None),
+        };
+
+        let value = current + delta;
+        if !value.is_finite() {
+            return Err(CommandError::err("increment would produce NaN or Infinity"));
+        }
+
+        // `f64`'s `Display` picks the shortest representation, so whole
+        // numbers come out without a fractional part.
+        let value = Bytes::from(value.to_string());
+        entries.insert(
+            key.to_string(),
+            Entry {
+                value: StoredValue::String(value.clone()),
+                expires_at,
+            },
+        );
+        self.touch(key);
+
+        Ok(value)
+    }
+
+    /// Appends `data` to the string at `key`, creating it if missing, and
+    /// returns the new length in bytes. The key keeps its time to live.
+    pub fn append(&self, key: &str, data: &[u8]) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+
+        let (mut value, expires_at) = match entries.get(key) {
+            Some(entry) if !entry.is_expired(now) => (
+                BytesMut::from(&entry.typed::<Bytes>()?[..]),
+                entry.expires_at,
+            ),
+            _ => (BytesMut::new(), None),
+        };
+        value.extend_from_slice(data);
+
+        let len = value.len();
+        entries.insert(
+            key.to_string(),
+            Entry {
+                value: StoredValue::String(value.freeze()),
+                expires_at,
+            },
+        );
+        self.touch(key);
+
+        Ok(len)
+    }
+
+    /// Returns the bytes of the string at `key` between the inclusive `start`
+    /// and `end` offsets. Negative offsets count from the end of the string
+    /// and both are clamped to its bounds.
+    pub fn get_range(&self, key: &str, start: i64, end: i64) -> Result<Bytes> {
+        let value = match self.get(key)? {
+            Some(value) => value,
+            None => return Ok(Bytes::new()),
+        };
+
+        match normalize_range(start, end, value.len()) {
+            Some((start, end)) => Ok(value.slice(start..end + 1)),
+            None => Ok(Bytes::new()),
+        }
+    }
+
+    /// Overwrites the string at `key` with `data` starting at `offset`,
+    /// zero-padding it if it is shorter, and returns the new length.
+    pub fn set_range(&self, key: &str, offset: usize, data: &[u8]) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+
+        let (mut value, expires_at) = match entries.get(key) {
+            Some(entry) if !entry.is_expired(now) => (
+                BytesMut::from(&entry.typed::<Bytes>()?[..]),
+                entry.expires_at,
+            ),
+            _ => (BytesMut::new(), None),
+        };
+        if data.is_empty() {
+            return Ok(value.len());
+        }
+
+        let end = match offset.checked_add(data.len()) {
+            Some(end) if end <= MAX_STRING_LEN => end,
+            _ => {
+                return Err(CommandError::err(
+                    "string exceeds maximum allowed size (proto-max-bulk-len)",
+                ))
+            }
+        };
+        if value.len() < end {
+            value.resize(end, 0);
+        }
+        value[offset..end].copy_from_slice(data);
+
+        let len = value.len();
+        entries.insert(
+            key.to_string(),
+            Entry {
+                value: StoredValue::String(value.freeze()),
+                expires_at,
+            },
+        );
+        self.touch(key);
+
+        Ok(len)
+    }
+
+    /// Pushes `values` one by one onto the given end of the list at `key`,
+    /// creating it if missing, and returns the new length of the list.
+    pub fn push(&self, key: &str, values: Vec<Bytes>, end: ListEnd) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let entry = entries.get_or_insert_with(key.to_string(), || Entry {
+            value: StoredValue::List(VecDeque::new()),
+            expires_at: None,
+        });
+        let list = entry.typed_mut::<List>()?;
+        for value in values {
+            match end {
+                ListEnd::Left => list.push_front(value),
+                ListEnd::Right => list.push_back(value),
+            }
+        }
+
+        self.touch(key);
+        // Nobody listening just means no client is blocked right now.
+        let _ = self.pushes.send(key.to_string());
+
+        Ok(list.len())
+    }
+
+    /// Pops an element from the `from` end of the list at `source` and pushes
+    /// it onto the `to` end of the one at `destination`, creating it if
+    /// missing. Moving within a single list rotates it. Returns `None` if
+    /// `source` is missing, and deletes it once empty.
+    pub fn list_move(
+        &self,
+        source: &str,
+        destination: &str,
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<Bytes>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, source);
+        evict_if_expired(&mut entries, destination);
+
+        match entries.get(source) {
+            Some(entry) => entry.typed::<List>()?,
+            None => return Ok(None),
+        };
+        if let Some(entry) = entries.get(destination) {
+            entry.typed::<List>()?;
+        }
+
+        let mut element = None;
+        if let Some(entry) = entries.get_mut(source) {
+            let list = entry.typed_mut::<List>()?;
+            element = match from {
+                ListEnd::Left => list.pop_front(),
+                ListEnd::Right => list.pop_back(),
+            };
+            if list.is_empty() {
+                entries.remove(source);
+            }
+        }
+        let element = match element {
+            Some(element) => element,
+            None => return Ok(None),
+        };
+
+        let entry = entries.get_or_insert_with(destination.to_string(), || Entry {
+            value: StoredValue::List(VecDeque::new()),
+            expires_at: None,
+        });
+        let list = entry.typed_mut::<List>()?;
+        match to {
+            ListEnd::Left => list.push_front(element.clone()),
+            ListEnd::Right => list.push_back(element.clone()),
+        }
+
+        self.touch(source);
+        self.touch(destination);
+        let _ = self.pushes.send(destination.to_string());
+
+        Ok(Some(element))
+    }
+
+    /// Pops up to `count` elements from the given end of the list at `key`,
+    /// or returns `None` if the key is missing. The key is deleted once the
+    /// list is empty.
+    pub fn pop(&self, key: &str, end: ListEnd, count: usize) -> Result<Option<Vec<Bytes>>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let list = match entries.get_mut(key) {
+            Some(entry) => entry.typed_mut::<List>()?,
+            None => return Ok(None),
+        };
+        let count = count.min(list.len());
+        let popped = match end {
+            ListEnd::Left => list.drain(..count).collect(),
+            ListEnd::Right => list.drain(list.len() - count..).rev().collect(),
+        };
+        if list.is_empty() {
+            entries.remove(key);
+        }
+        self.touch(key);
+
+        Ok(Some(popped))
+    }
+
+    /// Pops an element from the given end of the first non-empty list among
+    /// `keys`, waiting for another client to push to one of them if they are
+    /// all empty. Returns the key and element, or `None` once `timeout` has
+    /// elapsed; without a timeout it waits forever.
+    pub async fn blocking_pop(
+        &self,
+        keys: &[String],
+        end: ListEnd,
+        timeout: Option<Duration>,
+    ) -> Result<Option<(String, Bytes)>> {
+        // Subscribe before the first attempt so that no push slips through
+        // between checking the lists and starting to wait.
+        let mut pushes = self.pushes.subscribe();
+        let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+
+        loop {
+            for key in keys {
+                if let Some(mut popped) = self.pop(key, end, 1)? {
+                    return Ok(Some((key.clone(), popped.remove(0))));
+                }
+            }
+
+            let pushed = async {
+                loop {
+                    match pushes.recv().await {
+                        Ok(key) if keys.contains(&key) => break,
+                        Ok(_) => continue,
+                        // Lagging behind may have skipped one of our keys.
+                        Err(_) => break,
+                    }
+                }
+            };
+            match deadline {
+                Some(deadline) => {
+                    if tokio::time::timeout_at(deadline, pushed).await.is_err() {
+                        return Ok(None);
+                    }
+                }
+                None => pushed.await,
+            }
+        }
+    }
+
+    /// Returns the length of the list at `key`, or zero if it is missing.
+    pub fn list_len(&self, key: &str) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        match entries.get(key) {
+            Some(entry) => Ok(entry.typed::<List>()?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns the elements of the list at `key` between the inclusive
+    /// `start` and `stop` indices, which may be negative to count from the
+    /// tail and are clamped to the list bounds.
+    pub fn list_range(&self, key: &str, start: i64, stop: i64) -> Result<Vec<Bytes>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let list = match entries.get(key) {
+            Some(entry) => entry.typed::<List>()?,
+            None => return Ok(Vec::new()),
+        };
+        match normalize_range(start, stop, list.len()) {
+            Some((start, stop)) => Ok(list.range(start..stop + 1).cloned().collect()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the element at `index` of the list at `key`, counting from the
+    /// tail for negative indices.
+    pub fn list_index(&self, key: &str, index: i64) -> Result<Option<Bytes>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let list = match entries.get(key) {
+            Some(entry) => entry.typed::<List>()?,
+            None => return Ok(None),
+        };
+        Ok(resolve_index(index, list.len()).map(|index| list[index].clone()))
+    }
+
+    /// Returns the indices of the elements of the list at `key` equal to
+    /// `element`, skipping the first `rank - 1` matches and scanning from
+    /// the tail for a negative `rank`. Stops after `count` matches or
+    /// `maxlen` compared elements, where zero means no limit.
+    pub fn list_positions(
+        &self,
+        key: &str,
+        element: &[u8],
+        rank: i64,
+        count: usize,
+        maxlen: usize,
+    ) -> Result<Vec<usize>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let list = match entries.get(key) {
+            Some(entry) => entry.typed::<List>()?,
+            None => return Ok(Vec::new()),
+        };
+        let len = list.len();
+        let scanned = if maxlen == 0 { len } else { maxlen.min(len) };
+        let matches = (0..scanned)
+            .map(|offset| if rank > 0 { offset } else { len - 1 - offset })
+            .filter(|&index| list[index] == element)
+            .skip(rank.unsigned_abs() as usize - 1);
+
+        if count == 0 {
+            Ok(matches.collect())
+        } else {
+            Ok(matches.take(count).collect())
+        }
+    }
+
+    /// Replaces the element at `index` of the list at `key`.
+    pub fn list_set(&self, key: &str, index: i64, value: Bytes) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let list = match entries.get_mut(key) {
+            Some(entry) => entry.typed_mut::<List>()?,
+            None => return Err(CommandError::err("no such key")),
+        };
+        match resolve_index(index, list.len()) {
+            Some(index) => list[index] = value,
+            None => return Err(CommandError::err("index out of range")),
+        }
+        self.touch(key);
+
+        Ok(())
+    }
+
+    /// Trims the list at `key` down to the elements between the inclusive
+    /// `start` and `stop` indices, deleting the key if nothing remains.
+    pub fn list_trim(&self, key: &str, start: i64, stop: i64) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let list = match entries.get_mut(key) {
+            Some(entry) => entry.typed_mut::<List>()?,
+            None => return Ok(()),
+        };
+        match normalize_range(start, stop, list.len()) {
+            Some((start, stop)) => {
+                list.truncate(stop + 1);
+                list.drain(..start);
+            }
+            None => {
+                entries.remove(key);
+            }
+        }
+        self.touch(key);
+
+        Ok(())
+    }
+
+    /// Sets the given fields of the hash at `key`, creating it if missing,
+    /// and returns how many of the fields are new.
+    pub fn hash_set(&self, key: &str, fields: Vec<(Bytes, Bytes)>) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let entry = entries.get_or_insert_with(key.to_string(), || Entry {
+            value: StoredValue::Hash(HashMap::new()),
+            expires_at: None,
+        });
+        let hash = entry.typed_mut::<Hash>()?;
+        let added = fields
+            .into_iter()
+            .filter(|(field, value)| hash.insert(field.clone(), value.clone()).is_none())
+            .count();
+        self.touch(key);
+
+        Ok(added)
+    }
+
+    /// Returns the value of `field` in the hash at `key`.
+    pub fn hash_get(&self, key: &str, field: &[u8]) -> Result<Option<Bytes>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        match entries.get(key) {
+            Some(entry) => Ok(entry.typed::<Hash>()?.get(field).cloned()),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the values of several `fields` of the hash at `key` at once,
+    /// `None` for the missing ones.
+    pub fn hash_get_many(&self, key: &str, fields: &[Bytes]) -> Result<Vec<Option<Bytes>>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        match entries.get(key) {
+            Some(entry) => {
+                let hash = entry.typed::<Hash>()?;
+                Ok(fields
+                    .iter()
+                    .map(|field| hash.get(field).cloned())
+                    .collect())
+            }
+            None => Ok(vec![None; fields.len()]),
+        }
+    }
+
+    /// Returns the number of fields in the hash at `key`, or zero if it is
+    /// missing.
+    pub fn hash_len(&self, key: &str) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        match entries.get(key) {
+            Some(entry) => Ok(entry.typed::<Hash>()?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Removes `fields` from the hash at `key` and returns how many existed.
+    /// The key is deleted once the hash is empty.
+    pub fn hash_delete(&self, key: &str, fields: &[Bytes]) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let hash = match entries.get_mut(key) {
+            Some(entry) => entry.typed_mut::<Hash>()?,
+            None => return Ok(0),
+        };
+        let removed = fields
+            .iter()
+            .filter(|field| hash.remove(*field).is_some())
+            .count();
+        if hash.is_empty() {
+            entries.remove(key);
+        }
+        if removed > 0 {
+            self.touch(key);
+        }
+
+        Ok(removed)
+    }
+
+    /// Returns every field and value of the hash at `key`.
+    pub fn hash_get_all(&self, key: &str) -> Result<Vec<(Bytes, Bytes)>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        match entries.get(key) {
+            Some(entry) => Ok(entry
+                .typed::<Hash>()?
+                .iter()
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Adds `members` to the set at `key`, creating it if missing, and
+    /// returns how many of them were not already present.
+    pub fn set_add(&self, key: &str, members: Vec<Bytes>) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let entry = entries.get_or_insert_with(key.to_string(), || Entry {
+            value: StoredValue::Set(HashSet::new()),
+            expires_at: None,
+        });
+        let set = entry.typed_mut::<Set>()?;
+        let added = members
+            .into_iter()
+            .filter(|member| set.insert(member.clone()))
+            .count();
+        self.touch(key);
+
+        Ok(added)
+    }
+
+    /// Removes `members` from the set at `key` and returns how many were
+    /// present. The key is deleted once the set is empty.
+    pub fn set_remove(&self, key: &str, members: &[Bytes]) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let set = match entries.get_mut(key) {
+            Some(entry) => entry.typed_mut::<Set>()?,
+            None => return Ok(0),
+        };
+        let removed = members.iter().filter(|member| set.remove(*member)).count();
+        if set.is_empty() {
+            entries.remove(key);
+        }
+        if removed > 0 {
+            self.touch(key);
+        }
+
+        Ok(removed)
+    }
+
+    /// Moves `member` from the set at `source` to the one at `destination`,
+    /// creating it if missing, and returns whether `member` was in the
+    /// source set.
+    pub fn set_move(&self, source: &str, destination: &str, member: Bytes) -> Result<bool> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, source);
+        evict_if_expired(&mut entries, destination);
+
+        let present = match entries.get(source) {
+            Some(entry) => entry.typed::<Set>()?.contains(&member),
+            None => return Ok(false),
+        };
+        if let Some(entry) = entries.get(destination) {
+            entry.typed::<Set>()?;
+        }
+        if !present || source == destination {
+            return Ok(present);
+        }
+
+        if let Some(entry) = entries.get_mut(source) {
+            let set = entry.typed_mut::<Set>()?;
+            set.remove(&member);
+            if set.is_empty() {
+                entries.remove(source);
+            }
+        }
+        let entry = entries.get_or_insert_with(destination.to_string(), || Entry {
+            value: StoredValue::Set(HashSet::new()),
+            expires_at: None,
+        });
+        entry.typed_mut::<Set>()?.insert(member);
+        self.touch(source);
+        self.touch(destination);
+
+        Ok(true)
+    }
+
+    /// Removes and returns up to `count` random members of the set at `key`,
+    /// deleting it once empty.
+    pub fn set_pop(&self, key: &str, count: usize) -> Result<Vec<Bytes>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let set = match entries.get_mut(key) {
+            Some(entry) => entry.typed_mut::<Set>()?,
+            None => return Ok(Vec::new()),
+        };
+        let popped: Vec<Bytes> = if count >= set.len() {
+            set.drain().collect()
+        } else {
+            // Shuffles only as much of the members as gets popped.
+            let mut members: Vec<&Bytes> = set.iter().collect();
+            for picked in 0..count {
+                let index = picked + random_index(members.len() - picked);
+                members.swap(picked, index);
+            }
+            let popped = members[..count]
+                .iter()
+                .map(|member| (*member).clone())
+                .collect();
+            for member in &popped {
+                set.remove(member);
+            }
+            popped
+        };
+        if set.is_empty() {
+            entries.remove(key);
+        }
+        if !popped.is_empty() {
+            self.touch(key);
+        }
+
+        Ok(popped)
+    }
+
+    /// Returns `count` random members of the set at `key` without removing
+    /// them. Distinct members are capped at the set size, otherwise the
+    /// same member may be picked several times.
+    pub fn set_random_members(
+        &self,
+        key: &str,
+        count: usize,
+        distinct: bool,
+    ) -> Result<Vec<Bytes>> {
+        if !distinct && count > MAX_RANDOM_REPEATS {
+            return Err(CommandError::err("value is out of range"));
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let set = match entries.get(key) {
+            Some(entry) => entry.typed::<Set>()?,
+            None => return Ok(Vec::new()),
+        };
+        let mut members: Vec<&Bytes> = set.iter().collect();
+        if !distinct {
+            return Ok((0..count)
+                .map(|_| members[random_index(members.len())].clone())
+                .collect());
+        }
+
+        // Shuffles only as much of the members as gets picked.
+        let count = count.min(members.len());
+        for picked in 0..count {
+            let index = picked + random_index(members.len() - picked);
+            members.swap(picked, index);
+        }
+        Ok(members[..count]
+            .iter()
+            .map(|member| (*member).clone())
+            .collect())
+    }
+
+    /// Returns whether `member` belongs to the set at `key`.
+    pub fn set_contains(&self, key: &str, member: &[u8]) -> Result<bool> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        match entries.get(key) {
+            Some(entry) => Ok(entry.typed::<Set>()?.contains(member)),
+            None => Ok(false),
+        }
+    }
+
+    /// Returns every member of the set at `key`, in no particular order.
+    pub fn set_members(&self, key: &str) -> Result<Vec<Bytes>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        match entries.get(key) {
+            Some(entry) => Ok(entry.typed::<Set>()?.iter().cloned().collect()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the number of members of the set at `key`.
+    pub fn set_len(&self, key: &str) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        match entries.get(key) {
+            Some(entry) => Ok(entry.typed::<Set>()?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Combines the sets at `keys` with `operation`, treating missing keys as
+    /// empty sets. Differences subtract every other set from the first one.
+    pub fn set_combine(&self, keys: &[String], operation: SetOperation) -> Result<Vec<Bytes>> {
+        let mut entries = self.entries.lock().unwrap();
+        for key in keys {
+            evict_if_expired(&mut entries, key);
+        }
+
+        let empty = HashSet::new();
+        let mut sets = Vec::with_capacity(keys.len());
+        for key in keys {
+            match entries.get(key.as_str()) {
+                Some(entry) => sets.push(entry.typed::<Set>()?),
+                None => sets.push(&empty),
+            }
+        }
+
+        let (first, rest) = match sets.split_first() {
+            Some(split) => split,
+            None => return Ok(Vec::new()),
+        };
+        let members = match operation {
+            SetOperation::Union => sets
+                .iter()
+                .flat_map(|set| set.iter())
+                .collect::<HashSet<_>>(),
+            SetOperation::Intersection => {
+                // An empty set empties the intersection, so skip the scan.
+                if sets.iter().any(|set| set.is_empty()) {
+                    return Ok(Vec::new());
+                }
+                first
+                    .iter()
+                    .filter(|member| rest.iter().all(|set| set.contains(*member)))
+                    .collect()
+            }
+            SetOperation::Difference => first
+                .iter()
+                .filter(|member| !rest.iter().any(|set| set.contains(*member)))
+                .collect(),
+        };
+
+        Ok(members.into_iter().cloned().collect())
+    }
+
+    /// Counts the members the sets at `keys` have in common, stopping once
+    /// `limit` of them were found, if given.
+    pub fn set_intersection_len(&self, keys: &[String], limit: Option<usize>) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        for key in keys {
+            evict_if_expired(&mut entries, key);
+        }
+
+        let mut sets = Vec::with_capacity(keys.len());
+        let mut missing = false;
+        for key in keys {
+            // Every key is still type checked, even past a missing one.
+            match entries.get(key.as_str()) {
+                Some(entry) => sets.push(entry.typed::<Set>()?),
+                None => missing = true,
+            }
+        }
+        if missing {
+            return Ok(0);
+        }
+
+        // Scanning the smallest set checks the fewest candidates.
+        sets.sort_by_key(|set| set.len());
+        let (smallest, rest) = match sets.split_first() {
+            Some(split) => split,
+            None => return Ok(0),
+        };
+        let common = smallest
+            .iter()
+            .filter(|member| rest.iter().all(|set| set.contains(*member)));
+
+        Ok(match limit {
+            Some(limit) => common.take(limit).count(),
+            None => common.count(),
+        })
+    }
+
+    /// Adds `members` with their scores to the sorted set at `key`, creating
+    /// it if missing, and returns how many were new. Existing members are
+    /// rescored.
+    pub fn zset_add(&self, key: &str, members: Vec<(f64, Bytes)>) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let entry = entries.get_or_insert_with(key.to_string(), || Entry {
+            value: StoredValue::ZSet(SortedSet::new()),
+            expires_at: None,
+        });
+        let zset = entry.typed_mut::<SortedSet>()?;
+        let added = members
+            .into_iter()
+            .filter(|(score, member)| zset.insert(member.clone(), *score))
+            .count();
+        self.touch(key);
+
+        Ok(added)
+    }
+
+    /// Adds `delta` to the score of `member` in the sorted set at `key`,
+    /// adding it with a score of `delta` if it is missing, and returns the
+    /// new score.
+    pub fn zset_incr_by(&self, key: &str, delta: f64, member: Bytes) -> Result<f64> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let entry = entries.get_or_insert_with(key.to_string(), || Entry {
+            value: StoredValue::ZSet(SortedSet::new()),
+            expires_at: None,
+        });
+        let zset = entry.typed_mut::<SortedSet>()?;
+        let score = zset.score(&member).unwrap_or(0.0) + delta;
+        // Only adding the opposite infinity to an existing score gets there.
+        if score.is_nan() {
+            return Err(CommandError::err("resulting score is not a number (NaN)"));
+        }
+        zset.insert(member, score);
+        self.touch(key);
+
+        Ok(score)
+    }
+
+    /// Returns the position of `member` in the sorted set at `key`, counting
+    /// from the highest score when `reverse` is set.
+    pub fn zset_rank(&self, key: &str, member: &[u8], reverse: bool) -> Result<Option<usize>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let zset = match entries.get(key) {
+            Some(entry) => entry.typed::<SortedSet>()?,
+            None => return Ok(None),
+        };
+        Ok(zset
+            .rank(member)
+            .map(|rank| if reverse { zset.len() - 1 - rank } else { rank }))
+    }
+
+    /// Returns the score of `member` in the sorted set at `key`.
+    pub fn zset_score(&self, key: &str, member: &[u8]) -> Result<Option<f64>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        match entries.get(key) {
+            Some(entry) => Ok(entry.typed::<SortedSet>()?.score(member)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the number of members of the sorted set at `key`.
+    pub fn zset_len(&self, key: &str) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        match entries.get(key) {
+            Some(entry) => Ok(entry.typed::<SortedSet>()?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns the members and scores of the sorted set at `key` between the
+    /// inclusive `start` and `stop` ranks, which may be negative to count
+    /// from the highest score.
+    pub fn zset_range(&self, key: &str, start: i64, stop: i64) -> Result<Vec<(Bytes, f64)>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let zset = match entries.get(key) {
+            Some(entry) => entry.typed::<SortedSet>()?,
+            None => return Ok(Vec::new()),
+        };
+        match normalize_range(start, stop, zset.len()) {
+            Some((start, stop)) => Ok(zset.range(start, stop)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the members and scores of the sorted set at `key` whose score
+    /// lies between `min` and `max`.
+    pub fn zset_range_by_score(
+        &self,
+        key: &str,
+        min: ScoreBound,
+        max: ScoreBound,
+    ) -> Result<Vec<(Bytes, f64)>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        match entries.get(key) {
+            Some(entry) => Ok(entry.typed::<SortedSet>()?.range_by_score(min, max)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the members of the sorted set at `key` between the
+    /// lexicographic bounds `min` and `max`.
+    pub fn zset_range_by_lex(
+        &self,
+        key: &str,
+        min: &LexBound,
+        max: &LexBound,
+    ) -> Result<Vec<Bytes>> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        match entries.get(key) {
+            Some(entry) => Ok(entry.typed::<SortedSet>()?.range_by_lex(min, max)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Removes `members` from the sorted set at `key` and returns how many
+    /// were present. The key is deleted once the sorted set is empty.
+    pub fn zset_remove(&self, key: &str, members: &[Bytes]) -> Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        evict_if_expired(&mut entries, key);
+
+        let zset = match entries.get_mut(key) {
+            Some(entry) => entry.typed_mut::<SortedSet>()?,
+            None => return Ok(0),
+        };
+        let removed = members.iter().filter(|member| zset.remove(member)).count();
+        if zset.is_empty() {
+            entries.remove(key);
+        }
+        if removed > 0 {
+            self.touch(key);
+        }
+
+        Ok(removed)
+    }
+
+    /// Evicts expired keys the way Redis's active expiry cycle does,
+    /// returning how many were removed. Each round draws a sample of the
+    /// keys with an expiry, and rounds go on while a good part of the
+    /// sample could be reclaimed and the time budget allows. The lock is
+    /// released between rounds, so clients are only held up for a sample.
+    pub fn remove_expired(&self) -> usize {
+        let started = Instant::now();
+        let mut removed = 0;
+        loop {
+            let mut entries = self.entries.lock().unwrap();
+            let mut volatile = self.volatile.lock().unwrap();
+            let now = Instant::now();
+            let mut sampled = 0;
+            let mut reclaimed = 0;
+            while sampled < ACTIVE_EXPIRE_SAMPLE && !volatile.keys.is_empty() {
+                sampled += 1;
+                let index = random_index(volatile.keys.len());
+                let key = &volatile.keys[index];
+                match entries.get(key) {
+                    Some(entry) if entry.is_expired(now) => {
+                        entries.remove(key);
+                        removed += 1;
+                    }
+                    Some(Entry {
+                        expires_at: Some(_),
+                        ..
+                    }) => continue,
+                    // Deleted or persisted since, so no longer volatile.
+                    _ => {}
+                }
+                volatile.swap_remove(index);
+                reclaimed += 1;
+            }
+
+            if reclaimed * 4 <= sampled || started.elapsed() >= ACTIVE_EXPIRE_BUDGET {
+                return removed;
+            }
+        }
+    }
+
+    /// Sets `key` only if `condition` holds, returning whether it was set.
+    pub fn set_if(
+        &self,
+        key: String,
+        value: Bytes,
+        expires_at: Option<Instant>,
+        condition: SetCondition,
+    ) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let exists = match entries.get(&key) {
+            Some(entry) => !entry.is_expired(Instant::now()),
+            None => false,
+        };
+
+        match (condition, exists) {
+            (SetCondition::IfNotExists, true) | (SetCondition::IfExists, false) => false,
+            _ => {
+                self.touch(&key);
+                if expires_at.is_some() {
+                    self.volatile.lock().unwrap().insert(&key);
+                }
+                entries.insert(
+                    key,
+                    Entry {
+                        value: StoredValue::String(value),
+                        expires_at,
+                    },
+                );
+                true
+            }
+        }
+    }
+}
+
+/// Creates the logical databases, indexed by the number clients SELECT.
+pub fn databases() -> Arc<Vec<Db>> {
+    Arc::new((0..DATABASES).map(|_| Db::new()).collect())
+}
+
+/// Periodically evicts expired keys so that keys which are never accessed
+/// again still get their memory reclaimed. Eviction is skipped for as long as
+/// `enabled` is unset.
+pub async fn expire_keys(db: Db, enabled: Arc<AtomicBool>) {
+    let mut interval = tokio::time::interval(ACTIVE_EXPIRE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if enabled.load(Ordering::SeqCst) {
+            db.remove_expired();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        expire_keys, Db, Entry, ListEnd, SetCondition, SetOperation, StoredValue, Ttl,
+        ACTIVE_EXPIRE_INTERVAL, MAX_STRING_LEN,
+    };
+    use crate::error::CommandError;
+    use crate::sorted_set::SortedSet;
+    use std::collections::{HashMap, HashSet, VecDeque};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    fn set(db: &Db, key: &str, value: &str, expires_at: Option<Instant>) {
+        db.set_if(
+            key.to_string(),
+            Bytes::from(value.to_string()),
+            expires_at,
+            SetCondition::Always,
+        );
+    }
+
+    #[test]
+    fn it_returns_none_for_a_missing_key() {
+        let db = Db::new();
+
+        assert_eq!(db.get("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn it_gets_a_value_after_setting_it() {
+        let db = Db::new();
+        set(&db, "key", "value", None);
+
+        assert_eq!(db.get("key").unwrap(), Some(Bytes::from("value")));
+    }
+
+    #[test]
+    fn it_overwrites_an_existing_value() {
+        let db = Db::new();
+        set(&db, "key", "first", None);
+        set(&db, "key", "second", None);
+
+        assert_eq!(db.get("key").unwrap(), Some(Bytes::from("second")));
+    }
+
+    #[test]
+    fn it_shares_entries_between_clones() {
+        let db = Db::new();
+        let other = db.clone();
+        set(&other, "key", "value", None);
+
+        assert_eq!(db.get("key").unwrap(), Some(Bytes::from("value")));
+    }
+
+    #[test]
+    fn it_keeps_a_key_until_it_expires() {
+        let db = Db::new();
+        let expires_at = Instant::now() + Duration::from_secs(60);
+        set(&db, "key", "value", Some(expires_at));
+
+        assert_eq!(db.get("key").unwrap(), Some(Bytes::from("value")));
+    }
+
+    #[test]
+    fn it_treats_an_expired_key_as_missing() {
+        let db = Db::new();
+        set(&db, "key", "value", Some(Instant::now()));
+
+        assert_eq!(db.get("key").unwrap(), None);
+    }
+
+    #[test]
+    fn it_sets_a_missing_key_only_if_not_exists() {
+        let db = Db::new();
+
+        assert!(db.set_if(
+            "key".to_string(),
+            Bytes::from("first"),
+            None,
+            SetCondition::IfNotExists
+        ));
+        assert!(!db.set_if(
+            "key".to_string(),
+            Bytes::from("second"),
+            None,
+            SetCondition::IfNotExists
+        ));
+        assert_eq!(db.get("key").unwrap(), Some(Bytes::from("first")));
+    }
+
+    #[test]
+    fn it_sets_an_existing_key_only_if_exists() {
+        let db = Db::new();
+
+        assert!(!db.set_if(
+            "key".to_string(),
+            Bytes::from("first"),
+            None,
+            SetCondition::IfExists
+        ));
+        assert_eq!(db.get("key").unwrap(), None);
+
+        set(&db, "key", "first", None);
+        assert!(db.set_if(
+            "key".to_string(),
+            Bytes::from("second"),
+            None,
+            SetCondition::IfExists
+        ));
+        assert_eq!(db.get("key").unwrap(), Some(Bytes::from("second")));
+    }
+
+    #[test]
+    fn it_treats_an_expired_key_as_missing_when_setting_conditionally() {
+        let db = Db::new();
+        set(&db, "key", "old", Some(Instant::now()));
+
+        assert!(db.set_if(
+            "key".to_string(),
+            Bytes::from("new"),
+            None,
+            SetCondition::IfNotExists
+        ));
+        assert_eq!(db.get("key").unwrap(), Some(Bytes::from("new")));
+    }
+
+    #[test]
+    fn it_removes_a_key() {
+        let db = Db::new();
+        set(&db, "key", "value", None);
+
+        assert!(db.remove("key"));
+        assert!(!db.remove("key"));
+        assert_eq!(db.get("key").unwrap(), None);
+    }
+
+    #[test]
+    fn it_does_not_count_an_expired_key_as_removed() {
+        let db = Db::new();
+        set(&db, "key", "value", Some(Instant::now()));
+
+        assert!(!db.remove("key"));
+    }
+
+    #[test]
+    fn it_does_not_contain_an_expired_key() {
+        let db = Db::new();
+        set(&db, "live", "value", None);
+        set(&db, "expired", "value", Some(Instant::now()));
+
+        assert!(db.contains("live"));
+        assert!(!db.contains("expired"));
+        assert!(!db.contains("missing"));
+    }
+
+    #[test]
+    fn it_increments_a_missing_key_from_zero() -> Result<()> {
+        let db = Db::new();
+
+        assert_eq!(db.incr_by("counter", 1)?, 1);
+        assert_eq!(db.incr_by("counter", 1)?, 2);
+        assert_eq!(db.get("counter").unwrap(), Some(Bytes::from("2")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_decrements_a_missing_key_from_zero() -> Result<()> {
+        let db = Db::new();
+
+        assert_eq!(db.incr_by("counter", -1)?, -1);
+        assert_eq!(db.get("counter").unwrap(), Some(Bytes::from("-1")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_refuses_to_increment_a_non_integer() {
+        let db = Db::new();
+        set(&db, "key", "abc", None);
+
+        let err = db.incr_by("key", 1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ERR value is not an integer or out of range"
+        );
+        assert_eq!(db.get("key").unwrap(), Some(Bytes::from("abc")));
+    }
+
+    #[test]
+    fn it_refuses_to_overflow_when_incrementing() {
+        let db = Db::new();
+        set(&db, "key", &i64::MAX.to_string(), None);
+
+        let err = db.incr_by("key", 1).unwrap_err();
+        assert_eq!(err.to_string(), "ERR increment or decrement would overflow");
+    }
+
+    #[test]
+    fn it_increments_a_float_without_trailing_zeros() -> Result<()> {
+        let db = Db::new();
+        set(&db, "key", "1.5", None);
+
+        assert_eq!(db.incr_by_float("key", 1.5)?, Bytes::from("3"));
+        assert_eq!(db.incr_by_float("key", 0.25)?, Bytes::from("3.25"));
+        assert_eq!(db.get("key").unwrap(), Some(Bytes::from("3.25")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_normalizes_a_float_with_trailing_zeros() -> Result<()> {
+        let db = Db::new();
+        set(&db, "key", "10.50", None);
+
+        assert_eq!(db.incr_by_float("key", 0.0)?, Bytes::from("10.5"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_increments_a_missing_key_by_a_float() -> Result<()> {
+        let db = Db::new();
+
+        assert_eq!(db.incr_by_float("key", -2.5)?, Bytes::from("-2.5"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_refuses_to_increment_a_non_numeric_value_by_a_float() {
+        let db = Db::new();
+        set(&db, "key", "abc", None);
+
+        let err = db.incr_by_float("key", 1.0).unwrap_err();
+        assert_eq!(err.to_string(), "ERR value is not a valid float");
+    }
+
+    #[test]
+    fn it_reports_the_ttl_of_a_key() {
+        let db = Db::new();
+        set(&db, "persistent", "value", None);
+        set(
+            &db,
+            "volatile",
+            "value",
+            Some(Instant::now() + Duration::from_secs(10)),
+        );
+
+        assert_eq!(db.ttl("missing"), Ttl::Missing);
+        assert_eq!(db.ttl("persistent"), Ttl::Persistent);
+        match db.ttl("volatile") {
+            Ttl::Remaining(remaining) => {
+                assert!(remaining <= Duration::from_secs(10));
+                assert!(remaining > Duration::from_secs(9));
+            }
+            ttl => panic!("unexpected ttl: {:?}", ttl),
+        }
+    }
+
+    #[test]
+    fn it_expires_only_existing_keys() {
+        let db = Db::new();
+        set(&db, "key", "value", None);
+
+        let expires_at = Instant::now() + Duration::from_secs(10);
+        assert!(db.expire("key", expires_at));
+        assert!(!db.expire("missing", expires_at));
+        assert!(matches!(db.ttl("key"), Ttl::Remaining(_)));
+        assert_eq!(db.ttl("missing"), Ttl::Missing);
+    }
+
+    #[test]
+    fn it_persists_a_volatile_key() {
+        let db = Db::new();
+        set(&db, "key", "value", None);
+
+        assert!(!db.persist("key"));
+        assert!(db.expire("key", Instant::now() + Duration::from_secs(10)));
+        assert!(db.persist("key"));
+        assert_eq!(db.ttl("key"), Ttl::Persistent);
+    }
+
+    #[test]
+    fn it_removes_only_expired_keys() {
+        let db = Db::new();
+        set(&db, "live", "value", None);
+        set(&db, "expired", "value", Some(Instant::now()));
+
+        assert_eq!(db.remove_expired(), 1);
+        assert_eq!(db.entries.lock().unwrap().len(), 1);
+        assert!(db.contains("live"));
+    }
+
+    #[test]
+    fn it_keeps_sampling_while_most_sampled_keys_have_expired() {
+        let db = Db::new();
+        for n in 0..100 {
+            set(
+                &db,
+                &format!("expired:{}", n),
+                "value",
+                Some(Instant::now()),
+            );
+        }
+        set(&db, "live", "value", None);
+
+        assert_eq!(db.remove_expired(), 100);
+        assert_eq!(db.entries.lock().unwrap().len(), 1);
+        assert!(db.volatile.lock().unwrap().keys.is_empty());
+    }
+
+    #[test]
+    fn it_forgets_keys_that_lost_their_expiry() {
+        let db = Db::new();
+        let later = Instant::now() + Duration::from_secs(60);
+        set(&db, "persisted", "value", Some(later));
+        set(&db, "deleted", "value", Some(later));
+        assert!(db.persist("persisted"));
+        assert!(db.remove("deleted"));
+
+        assert_eq!(db.remove_expired(), 0);
+        assert!(db.volatile.lock().unwrap().keys.is_empty());
+        assert!(db.contains("persisted"));
+    }
+
+    #[tokio::test]
+    async fn it_wakes_a_blocked_pop_when_another_client_pushes() -> Result<()> {
+        let db = Db::new();
+        let keys = vec!["empty".to_string(), "list".to_string()];
+        let waiter = {
+            let db = db.clone();
+            tokio::spawn(async move { db.blocking_pop(&keys, ListEnd::Left, None).await })
+        };
+
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+        db.push("list", vec![Bytes::from("value")], ListEnd::Right)?;
+
+        assert_eq!(
+            waiter.await??,
+            Some(("list".to_string(), Bytes::from("value")))
+        );
+        assert!(!db.contains("list"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_times_out_a_blocked_pop() -> Result<()> {
+        let db = Db::new();
+        let keys = vec!["list".to_string()];
+
+        let popped = db
+            .blocking_pop(&keys, ListEnd::Right, Some(Duration::from_millis(20)))
+            .await?;
+
+        assert_eq!(popped, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_sets_hash_fields_counting_only_new_ones() -> Result<()> {
+        let db = Db::new();
+        let field =
+            |field: &'static str, value: &'static str| (Bytes::from(field), Bytes::from(value));
+
+        assert_eq!(
+            db.hash_set("hash", vec![field("a", "1"), field("b", "2")])?,
+            2
+        );
+        assert_eq!(
+            db.hash_set("hash", vec![field("a", "3"), field("c", "4")])?,
+            1
+        );
+
+        assert_eq!(db.hash_get("hash", b"a")?, Some(Bytes::from("3")));
+        assert_eq!(db.hash_get("hash", b"missing")?, None);
+        assert_eq!(db.hash_get("missing", b"a")?, None);
+
+        let mut pairs = db.hash_get_all("hash")?;
+        pairs.sort();
+        assert_eq!(
+            pairs,
+            vec![field("a", "3"), field("b", "2"), field("c", "4")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_deletes_hash_fields_and_the_empty_hash() -> Result<()> {
+        let db = Db::new();
+        db.hash_set("hash", vec![(Bytes::from("a"), Bytes::from("1"))])?;
+
+        let fields = [Bytes::from("a"), Bytes::from("b")];
+        assert_eq!(db.hash_delete("hash", &fields)?, 1);
+        assert!(!db.contains("hash"));
+        assert_eq!(db.hash_delete("hash", &fields)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_deduplicates_set_members() -> Result<()> {
+        let db = Db::new();
+        let members = |members: &[&'static str]| -> Vec<Bytes> {
+            members.iter().map(|m| Bytes::from(*m)).collect()
+        };
+
+        assert_eq!(db.set_add("set", members(&["a", "b", "a"]))?, 2);
+        assert_eq!(db.set_add("set", members(&["b", "c"]))?, 1);
+        assert_eq!(db.set_len("set")?, 3);
+
+        let mut all = db.set_members("set")?;
+        all.sort();
+        assert_eq!(all, members(&["a", "b", "c"]));
+
+        assert!(db.set_contains("set", b"a")?);
+        assert!(!db.set_contains("set", b"z")?);
+        assert!(!db.set_contains("missing", b"a")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_removes_set_members_and_the_empty_set() -> Result<()> {
+        let db = Db::new();
+        db.set_add("set", vec![Bytes::from("a"), Bytes::from("b")])?;
+
+        assert_eq!(
+            db.set_remove("set", &[Bytes::from("a"), Bytes::from("z")])?,
+            1
+        );
+        assert_eq!(db.set_remove("set", &[Bytes::from("b")])?, 1);
+        assert!(!db.contains("set"));
+        assert_eq!(db.set_len("set")?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_moves_a_member_between_sets() -> Result<()> {
+        let db = Db::new();
+        db.set_add("source", vec![Bytes::from("a"), Bytes::from("b")])?;
+
+        assert!(db.set_move("source", "destination", Bytes::from("a"))?);
+        assert!(!db.set_move("source", "destination", Bytes::from("z"))?);
+        assert!(!db.set_contains("source", b"a")?);
+        assert!(db.set_contains("destination", b"a")?);
+
+        assert!(db.set_move("source", "destination", Bytes::from("b"))?);
+        assert!(!db.contains("source"));
+        assert_eq!(db.set_len("destination")?, 2);
+
+        db.set_many(vec![("string".to_string(), Bytes::from("x"))]);
+        assert_eq!(
+            db.set_move("destination", "string", Bytes::from("a")),
+            Err(CommandError::WrongType)
+        );
+        assert!(db.set_contains("destination", b"a")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_pops_random_set_members() -> Result<()> {
+        let db = Db::new();
+        let members = ["a", "b", "c"].iter().map(|m| Bytes::from(*m));
+        db.set_add("set", members.collect())?;
+
+        let popped = db.set_pop("set", 2)?;
+        assert_eq!(popped.len(), 2);
+        assert_eq!(db.set_len("set")?, 1);
+        for member in &popped {
+            assert!(!db.set_contains("set", member)?);
+        }
+
+        assert_eq!(db.set_pop("set", 5)?.len(), 1);
+        assert!(!db.contains("set"));
+        assert!(db.set_pop("set", 1)?.is_empty());
+
+        let members = (0..1000).map(|n| Bytes::from(n.to_string()));
+        db.set_add("large", members.collect())?;
+        let popped = db.set_pop("large", 400)?;
+        assert_eq!(popped.iter().collect::<HashSet<_>>().len(), 400);
+        assert_eq!(db.set_len("large")?, 600);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_picks_random_set_members_with_or_without_repeats() -> Result<()> {
+        let db = Db::new();
+        let members = ["a", "b", "c"].iter().map(|m| Bytes::from(*m));
+        db.set_add("set", members.collect())?;
+
+        let mut distinct = db.set_random_members("set", 2, true)?;
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 2);
+
+        let mut all = db.set_random_members("set", 10, true)?;
+        all.sort();
+        assert_eq!(
+            all,
+            vec![Bytes::from("a"), Bytes::from("b"), Bytes::from("c")]
+        );
+
+        let repeated = db.set_random_members("set", 10, false)?;
+        assert_eq!(repeated.len(), 10);
+        for member in &repeated {
+            assert!(db.set_contains("set", member)?);
+        }
+        assert_eq!(db.set_len("set")?, 3);
+
+        assert!(db.set_random_members("missing", 5, false)?.is_empty());
+        assert_eq!(
+            db.set_random_members("set", 1 << 62, false),
+            Err(CommandError::err("value is out of range"))
+        );
+        assert_eq!(db.set_random_members("set", 1 << 62, true)?.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_combines_sets() -> Result<()> {
+        let db = Db::new();
+        let members = |members: &[&'static str]| -> HashSet<Bytes> {
+            members.iter().map(|m| Bytes::from(*m)).collect()
+        };
+        db.set_add("a", members(&["1", "2", "3"]).into_iter().collect())?;
+        db.set_add("b", members(&["2", "3", "4"]).into_iter().collect())?;
+        let keys = |keys: &[&str]| -> Vec<String> { keys.iter().map(|k| k.to_string()).collect() };
+        let combine = |names: &[&str], operation| -> Result<HashSet<Bytes>> {
+            Ok(db
+                .set_combine(&keys(names), operation)?
+                .into_iter()
+                .collect())
+        };
+
+        assert_eq!(
+            combine(&["a", "b"], SetOperation::Union)?,
+            members(&["1", "2", "3", "4"])
+        );
+        assert_eq!(
+            combine(&["a", "b"], SetOperation::Intersection)?,
+            members(&["2", "3"])
+        );
+        assert_eq!(
+            combine(&["a", "b"], SetOperation::Difference)?,
+            members(&["1"])
+        );
+        assert_eq!(
+            combine(&["a", "missing"], SetOperation::Intersection)?,
+            members(&[])
+        );
+        assert_eq!(
+            combine(&["a", "missing"], SetOperation::Union)?,
+            members(&["1", "2", "3"])
+        );
+        assert_eq!(
+            combine(&["missing", "a"], SetOperation::Difference)?,
+            members(&[])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_counts_the_intersection_of_sets_up_to_a_limit() -> Result<()> {
+        let db = Db::new();
+        let members = |members: &[&'static str]| -> Vec<Bytes> {
+            members.iter().map(|m| Bytes::from(*m)).collect()
+        };
+        db.set_add("a", members(&["1", "2", "3", "4"]))?;
+        db.set_add("b", members(&["2", "3", "4", "5"]))?;
+        db.set_add("c", members(&["3", "4", "5"]))?;
+        let keys = |keys: &[&str]| -> Vec<String> { keys.iter().map(|k| k.to_string()).collect() };
+
+        assert_eq!(db.set_intersection_len(&keys(&["a", "b"]), None)?, 3);
+        assert_eq!(db.set_intersection_len(&keys(&["a", "b", "c"]), None)?, 2);
+        assert_eq!(db.set_intersection_len(&keys(&["a", "b"]), Some(2))?, 2);
+        assert_eq!(db.set_intersection_len(&keys(&["a", "b"]), Some(10))?, 3);
+        assert_eq!(db.set_intersection_len(&keys(&["a", "missing"]), None)?, 0);
+
+        db.set_many(vec![("string".to_string(), Bytes::from("x"))]);
+        assert_eq!(
+            db.set_intersection_len(&keys(&["missing", "string"]), None),
+            Err(CommandError::WrongType)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rescores_sorted_set_members_and_counts_them() -> Result<()> {
+        let db = Db::new();
+        let members = vec![(1.0, Bytes::from("a")), (2.0, Bytes::from("b"))];
+
+        assert_eq!(db.zset_add("zset", members)?, 2);
+        assert_eq!(db.zset_add("zset", vec![(5.0, Bytes::from("a"))])?, 0);
+        assert_eq!(db.zset_score("zset", b"a")?, Some(5.0));
+        assert_eq!(db.zset_len("zset")?, 2);
+
+        assert_eq!(
+            db.zset_remove("zset", &[Bytes::from("a"), Bytes::from("z")])?,
+            1
+        );
+        assert_eq!(db.zset_len("zset")?, 1);
+        assert_eq!(db.zset_remove("zset", &[Bytes::from("b")])?, 1);
+        assert!(!db.contains("zset"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_lists_keys_matching_a_pattern_skipping_expired_ones() {
+        let db = Db::new();
+        set(&db, "user:1", "a", None);
+        set(&db, "user:2", "b", None);
+        set(&db, "session:1", "c", None);
+        set(&db, "user:3", "d", Some(Instant::now()));
+
+        let mut keys = db.keys("user:*");
+        keys.sort();
+
+        assert_eq!(keys, vec!["user:1", "user:2"]);
+        assert_eq!(db.keys("*").len(), 3);
+    }
+
+    #[test]
+    fn it_scans_every_key_exactly_once() {
+        let db = Db::new();
+        for i in 0..25 {
+            set(&db, &format!("key:{}", i), "value", None);
+        }
+
+        let mut visited = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = db.scan(cursor, None, 10);
+            visited.extend(keys);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        visited.sort();
+        visited.dedup();
+
+        assert_eq!(visited.len(), 25);
+    }
+
+    #[test]
+    fn it_filters_scanned_keys_by_pattern() {
+        let db = Db::new();
+        set(&db, "a:1", "value", None);
+        set(&db, "b:1", "value", None);
+
+        assert_eq!(db.scan(0, Some("b:*"), 10), (0, vec!["b:1".to_string()]));
+    }
+
+    #[test]
+    fn it_counts_and_clears_live_keys() {
+        let db = Db::new();
+        set(&db, "a", "1", None);
+        set(&db, "b", "2", None);
+        set(&db, "expired", "3", Some(Instant::now()));
+
+        assert_eq!(db.len(), 2);
+
+        db.clear();
+        assert_eq!(db.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn it_actively_expires_keys_in_the_background() {
+        let db = Db::new();
+        set(
+            &db,
+            "key",
+            "value",
+            Some(Instant::now() + Duration::from_millis(20)),
+        );
+        tokio::spawn(expire_keys(db.clone(), Arc::new(AtomicBool::new(true))));
+
+        tokio::time::delay_for(ACTIVE_EXPIRE_INTERVAL * 3).await;
+
+        assert!(db.entries.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_keeps_expired_keys_while_active_expiry_is_disabled() {
+        let db = Db::new();
+        set(&db, "key", "value", Some(Instant::now()));
+        tokio::spawn(expire_keys(db.clone(), Arc::new(AtomicBool::new(false))));
+
+        tokio::time::delay_for(ACTIVE_EXPIRE_INTERVAL * 3).await;
+
+        assert_eq!(db.entries.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn it_draws_every_live_key_at_random() {
+        let db = Db::new();
+        assert_eq!(db.random_key(), None);
+
+        set(&db, "a", "1", None);
+        set(&db, "b", "2", None);
+        set(&db, "expired", "3", Some(Instant::now()));
+
+        let drawn = (0..100)
+            .filter_map(|_| db.random_key())
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            drawn,
+            vec!["a".to_string(), "b".to_string()].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn it_keeps_keys_reachable_after_removing_others() {
+        let db = Db::new();
+        for key in ["a", "b", "c", "d"] {
+            set(&db, key, key, None);
+        }
+
+        assert!(db.remove("a"));
+        assert!(db.remove("d"));
+        set(&db, "e", "e", None);
+
+        for key in ["b", "c", "e"] {
+            assert_eq!(db.get(key).unwrap(), Some(Bytes::from(key)));
+        }
+        assert_eq!(db.get("a").unwrap(), None);
+        assert_eq!(db.len(), 3);
+        let drawn = (0..100)
+            .filter_map(|_| db.random_key())
+            .collect::<HashSet<_>>();
+        assert_eq!(drawn.len(), 3);
+    }
+
+    #[test]
+    fn it_gets_many_keys_with_hits_and_misses() {
+        let db = Db::new();
+        set(&db, "a", "1", None);
+        set(&db, "c", "3", Some(Instant::now()));
+
+        assert_eq!(
+            db.get_many(&["a", "b", "c"]),
+            vec![Some(Bytes::from("1")), None, None]
+        );
+    }
+
+    #[test]
+    fn it_sets_many_keys_clearing_their_expiry() {
+        let db = Db::new();
+        set(
+            &db,
+            "a",
+            "old",
+            Some(Instant::now() + Duration::from_secs(10)),
+        );
+
+        db.set_many(vec![
+            ("a".to_string(), Bytes::from("1")),
+            ("b".to_string(), Bytes::from("2")),
+        ]);
+
+        assert_eq!(
+            db.get_many(&["a", "b"]),
+            vec![Some(Bytes::from("1")), Some(Bytes::from("2"))]
+        );
+        assert_eq!(db.ttl("a"), Ttl::Persistent);
+    }
+
+    #[test]
+    fn it_appends_to_a_new_then_existing_key() {
+        let db = Db::new();
+
+        assert_eq!(db.append("key", b"Hello").unwrap(), 5);
+        assert_eq!(db.append("key", b" \xffWorld").unwrap(), 12);
+        assert_eq!(
+            db.get("key").unwrap(),
+            Some(Bytes::from(&b"Hello \xffWorld"[..]))
+        );
+    }
+
+    #[test]
+    fn it_gets_a_range_with_negative_and_out_of_range_offsets() {
+        let db = Db::new();
+        set(&db, "key", "This is a string", None);
+
+        assert_eq!(db.get_range("key", 0, 3).unwrap(), Bytes::from("This"));
+        assert_eq!(db.get_range("key", -3, -1).unwrap(), Bytes::from("ing"));
+        assert_eq!(
+            db.get_range("key", 0, -1).unwrap(),
+            Bytes::from("This is a string")
+        );
+        assert_eq!(db.get_range("key", 10, 100).unwrap(), Bytes::from("string"));
+        assert_eq!(db.get_range("key", -100, 1).unwrap(), Bytes::from("Th"));
+        assert_eq!(db.get_range("key", 5, 2).unwrap(), Bytes::new());
+        assert_eq!(db.get_range("key", 0, -100).unwrap(), Bytes::new());
+        assert_eq!(db.get_range("missing", 0, -1).unwrap(), Bytes::new());
+    }
+
+    #[test]
+    fn it_sets_a_range_zero_padding_the_gap() -> Result<()> {
+        let db = Db::new();
+        set(&db, "key", "Hello World", None);
+
+        assert_eq!(db.set_range("key", 6, b"Redis")?, 11);
+        assert_eq!(db.get("key").unwrap(), Some(Bytes::from("Hello Redis")));
+
+        assert_eq!(db.set_range("padded", 3, b"abc")?, 6);
+        assert_eq!(
+            db.get("padded").unwrap(),
+            Some(Bytes::from(&b"\0\0\0abc"[..]))
+        );
+
+        assert_eq!(db.set_range("empty", 10, b"")?, 0);
+        assert!(!db.contains("empty"));
+
+        assert!(db.set_range("key", MAX_STRING_LEN, b"x").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_the_type_of_each_value() {
+        let db = Db::new();
+        set(&db, "string", "value", None);
+        {
+            let mut entries = db.entries.lock().unwrap();
+            for (key, value) in [
+                ("list", StoredValue::List(VecDeque::new())),
+                ("hash", StoredValue::Hash(HashMap::new())),
+                ("set", StoredValue::Set(HashSet::new())),
+                ("zset", StoredValue::ZSet(SortedSet::new())),
+            ] {
+                let entry = Entry {
+                    value,
+                    expires_at: None,
+                };
+                entries.insert(key.to_string(), entry);
+            }
+        }
+
+        assert_eq!(db.type_of("string"), Some("string"));
+        assert_eq!(db.type_of("list"), Some("list"));
+        assert_eq!(db.type_of("hash"), Some("hash"));
+        assert_eq!(db.type_of("set"), Some("set"));
+        assert_eq!(db.type_of("zset"), Some("zset"));
+        assert_eq!(db.type_of("missing"), None);
+    }
+
+    #[test]
+    fn it_rejects_string_commands_on_other_types() {
+        let db = Db::new();
+        let entry = Entry {
+            value: StoredValue::List(VecDeque::new()),
+            expires_at: None,
+        };
+        db.entries.lock().unwrap().insert("list".to_string(), entry);
+
+        let err = db.get("list").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "WRONGTYPE Operation against a key holding the wrong kind of value"
+        );
+        assert!(db.incr_by("list", 1).is_err());
+        assert!(db.append("list", b"x").is_err());
+        assert_eq!(db.get_many(&["list"]), vec![None]);
+    }
+
+    #[test]
+    fn it_pushes_multiple_elements_in_order() -> Result<()> {
+        let db = Db::new();
+
+        assert_eq!(
+            db.push(
+                "list",
+                vec![Bytes::from("a"), Bytes::from("b")],
+                ListEnd::Left
+            )?,
+            2
+        );
+        assert_eq!(db.push("list", vec![Bytes::from("c")], ListEnd::Right)?, 3);
+
+        assert_eq!(
+            db.pop("list", ListEnd::Left, 3)?,
+            Some(vec![Bytes::from("b"), Bytes::from("a"), Bytes::from("c")])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_pops_a_count_of_elements_and_deletes_empty_lists() -> Result<()> {
+        let db = Db::new();
+        let values = ["a", "b", "c"].iter().map(|v| Bytes::from(*v)).collect();
+        db.push("list", values, ListEnd::Right)?;
+
+        assert_eq!(
+            db.pop("list", ListEnd::Right, 2)?,
+            Some(vec![Bytes::from("c"), Bytes::from("b")])
+        );
+        assert_eq!(
+            db.pop("list", ListEnd::Left, 5)?,
+            Some(vec![Bytes::from("a")])
+        );
+        assert!(!db.contains("list"));
+        assert_eq!(db.pop("list", ListEnd::Left, 1)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_pushing_onto_a_string() {
+        let db = Db::new();
+        set(&db, "key", "value", None);
+
+        assert!(db
+            .push("key", vec![Bytes::from("a")], ListEnd::Left)
+            .is_err());
+    }
+
+    #[test]
+    fn it_measures_list_length() -> Result<()> {
+        let db = Db::new();
+        db.push(
+            "list",
+            vec![Bytes::from("a"), Bytes::from("b")],
+            ListEnd::Right,
+        )?;
+
+        assert_eq!(db.list_len("list")?, 2);
+        assert_eq!(db.list_len("missing")?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_returns_list_ranges_with_negative_indices() -> Result<()> {
+        let db = Db::new();
+        let values = ["a", "b", "c", "d"]
+            .iter()
+            .map(|v| Bytes::from(*v))
+            .collect();
+        db.push("list", values, ListEnd::Right)?;
+
+        let range = |start, stop| db.list_range("list", start, stop);
+        let elements = |values: &[&'static str]| -> Vec<Bytes> {
+            values.iter().map(|v| Bytes::from(*v)).collect()
+        };
+
+        assert_eq!(range(0, -1)?, elements(&["a", "b", "c", "d"]));
+        assert_eq!(range(-2, -1)?, elements(&["c", "d"]));
+        assert_eq!(range(-100, 1)?, elements(&["a", "b"]));
+        assert_eq!(range(2, 100)?, elements(&["c", "d"]));
+        assert_eq!(range(3, 1)?, elements(&[]));
+        assert_eq!(db.list_range("missing", 0, -1)?, elements(&[]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_indexes_and_sets_list_elements_from_either_end() -> Result<()> {
+        let db = Db::new();
+        let values = ["a", "b", "c"].iter().map(|v| Bytes::from(*v)).collect();
+        db.push("list", values, ListEnd::Right)?;
+
+        assert_eq!(db.list_index("list", 0)?, Some(Bytes::from("a")));
+        assert_eq!(db.list_index("list", -1)?, Some(Bytes::from("c")));
+        assert_eq!(db.list_index("list", 3)?, None);
+        assert_eq!(db.list_index("list", -4)?, None);
+
+        db.list_set("list", -2, Bytes::from("B"))?;
+        assert_eq!(db.list_index("list", 1)?, Some(Bytes::from("B")));
+
+        let err = db.list_set("list", 5, Bytes::from("x")).unwrap_err();
+        assert_eq!(err.to_string(), "ERR index out of range");
+        let err = db.list_set("missing", 0, Bytes::from("x")).unwrap_err();
+        assert_eq!(err.to_string(), "ERR no such key");
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_finds_the_positions_of_list_elements() -> Result<()> {
+        let db = Db::new();
+        let values = ["a", "b", "a", "c", "a"]
+            .iter()
+            .map(|v| Bytes::from(*v))
+            .collect();
+        db.push("list", values, ListEnd::Right)?;
+
+        assert_eq!(db.list_positions("list", b"a", 1, 1, 0)?, vec![0]);
+        assert_eq!(db.list_positions("list", b"a", 2, 1, 0)?, vec![2]);
+        assert_eq!(db.list_positions("list", b"a", -1, 1, 0)?, vec![4]);
+        assert_eq!(db.list_positions("list", b"a", -2, 0, 0)?, vec![2, 0]);
+        assert_eq!(db.list_positions("list", b"a", 1, 0, 0)?, vec![0, 2, 4]);
+        assert_eq!(db.list_positions("list", b"a", 1, 2, 0)?, vec![0, 2]);
+        assert_eq!(db.list_positions("list", b"a", 1, 0, 3)?, vec![0, 2]);
+        assert_eq!(db.list_positions("list", b"a", -1, 0, 2)?, vec![4]);
+        assert!(db.list_positions("list", b"z", 1, 0, 0)?.is_empty());
+        assert!(db.list_positions("missing", b"a", 1, 0, 0)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_moves_list_elements_between_and_within_lists() -> Result<()> {
+        let db = Db::new();
+        let values = ["a", "b", "c"].iter().map(|v| Bytes::from(*v)).collect();
+        db.push("list", values, ListEnd::Right)?;
+
+        assert_eq!(
+            db.list_move("list", "list", ListEnd::Right, ListEnd::Left)?,
+            Some(Bytes::from("c"))
+        );
+        assert_eq!(
+            db.list_range("list", 0, -1)?,
+            vec![Bytes::from("c"), Bytes::from("a"), Bytes::from("b")]
+        );
+
+        assert_eq!(
+            db.list_move("list", "other", ListEnd::Left, ListEnd::Right)?,
+            Some(Bytes::from("c"))
+        );
+        assert_eq!(db.list_range("other", 0, -1)?, vec![Bytes::from("c")]);
+        assert_eq!(db.list_len("list")?, 2);
+
+        db.list_move("list", "other", ListEnd::Left, ListEnd::Right)?;
+        db.list_move("list", "other", ListEnd::Left, ListEnd::Right)?;
+        assert!(!db.contains("list"));
+        assert_eq!(db.list_len("other")?, 3);
+        assert_eq!(
+            db.list_move("list", "other", ListEnd::Left, ListEnd::Right)?,
+            None
+        );
+
+        set(&db, "string", "x", None);
+        assert_eq!(
+            db.list_move("other", "string", ListEnd::Left, ListEnd::Right),
+            Err(CommandError::WrongType)
+        );
+        assert_eq!(db.list_len("other")?, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_trims_lists_and_deletes_them_when_empty() -> Result<()> {
+        let db = Db::new();
+        let values = ["a", "b", "c", "d"]
+            .iter()
+            .map(|v| Bytes::from(*v))
+            .collect();
+        db.push("list", values, ListEnd::Right)?;
+
+        db.list_trim("list", 1, -2)?;
+        assert_eq!(
+            db.list_range("list", 0, -1)?,
+            vec![Bytes::from("b"), Bytes::from("c")]
+        );
+
+        db.list_trim("list", 5, 10)?;
+        assert!(!db.contains("list"));
+
+        db.list_trim("missing", 0, 1)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_reports_the_encoding_redis_would_pick() -> Result<()> {
+        let db = Db::new();
+        set(&db, "int", "-12345", None);
+        set(&db, "embstr", "hello", None);
+        set(&db, "raw", &"x".repeat(45), None);
+        db.push("list", vec![Bytes::from("a")], ListEnd::Right)?;
+        db.set_add("intset", vec![Bytes::from("1"), Bytes::from("2")])?;
+        db.set_add("set", vec![Bytes::from("a")])?;
+        let members = (0..200).map(|n| Bytes::from(n.to_string())).collect();
+        db.push("long", members, ListEnd::Right)?;
+
+        assert_eq!(db.encoding_of("int"), Some("int"));
+        assert_eq!(db.encoding_of("embstr"), Some("embstr"));
+        assert_eq!(db.encoding_of("raw"), Some("raw"));
+        assert_eq!(db.encoding_of("list"), Some("listpack"));
+        assert_eq!(db.encoding_of("long"), Some("quicklist"));
+        assert_eq!(db.encoding_of("intset"), Some("intset"));
+        assert_eq!(db.encoding_of("set"), Some("listpack"));
+        assert_eq!(db.encoding_of("missing"), None);
+
+        Ok(())
+    }
+}
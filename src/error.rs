@@ -0,0 +1,86 @@
+use crate::resp;
+
+pub type CommandResult<T> = std::result::Result<T, CommandError>;
+
+/// An error replied to a client when a command fails. Its message starts with
+/// the error prefix (`ERR`, `WRONGTYPE`, ...) that Redis clients match on.
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum CommandError {
+    #[error("ERR wrong number of arguments for '{0}' command")]
+    WrongArgCount(String),
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("ERR value is not an integer or out of range")]
+    NotInteger,
+    #[error("ERR value is not a valid float")]
+    NotFloat,
+    #[error("ERR syntax error")]
+    SyntaxError,
+    #[error("ERR unknown command '{0}'")]
+    UnknownCommand(String),
+    /// Any other failure, with its message already prefixed.
+    #[error("{0}")]
+    Other(String),
+}
+
+impl CommandError {
+    /// Builds an error with a plain `ERR` prefix.
+    pub fn err(message: &str) -> Self {
+        CommandError::Other(format!("ERR {}", message))
+    }
+
+    /// Converts the error into the RESP error value sent to the client.
+    pub fn to_value(&self) -> resp::Value {
+        resp::Value::Error(self.to_string())
+    }
+}
+
+impl From<anyhow::Error> for CommandError {
+    fn from(err: anyhow::Error) -> Self {
+        CommandError::err(&err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CommandError;
+
+    fn encode(err: CommandError) -> String {
+        String::from_utf8(err.to_value().encode().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn it_encodes_each_error_with_its_prefix() {
+        assert_eq!(
+            encode(CommandError::WrongArgCount("get".to_string())),
+            "-ERR wrong number of arguments for 'get' command\r\n"
+        );
+        assert_eq!(
+            encode(CommandError::WrongType),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert_eq!(
+            encode(CommandError::NotInteger),
+            "-ERR value is not an integer or out of range\r\n"
+        );
+        assert_eq!(
+            encode(CommandError::NotFloat),
+            "-ERR value is not a valid float\r\n"
+        );
+        assert_eq!(encode(CommandError::SyntaxError), "-ERR syntax error\r\n");
+        assert_eq!(
+            encode(CommandError::UnknownCommand("foo".to_string())),
+            "-ERR unknown command 'foo'\r\n"
+        );
+        assert_eq!(
+            encode(CommandError::Other(
+                "NOPROTO unsupported protocol version".to_string()
+            )),
+            "-NOPROTO unsupported protocol version\r\n"
+        );
+        assert_eq!(
+            encode(CommandError::err("no such key")),
+            "-ERR no such key\r\n"
+        );
+    }
+}
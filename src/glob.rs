@@ -0,0 +1,159 @@
+/// Matches `subject` against a Redis glob-style `pattern`, supporting `*`,
+/// `?`, `[...]` character classes (with ranges and `^` negation) and `\`
+/// escapes.
+///
+/// Like Redis's `stringmatchlen`, this walks both strings once and only
+/// remembers the last `*`: when the rest of the pattern stops matching, that
+/// `*` swallows one more byte and matching resumes right after it. Earlier
+/// stars never need revisiting, so long or star-heavy patterns take neither
+/// deep recursion nor exponential time.
+pub fn matches(pattern: &[u8], subject: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // The pattern past the last `*`, and where in the subject it resumes.
+    let mut backtrack = None;
+
+    while s < subject.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            backtrack = Some((p, s));
+            continue;
+        }
+        if p < pattern.len() {
+            if let Some(len) = match_one(&pattern[p..], subject[s]) {
+                p += len;
+                s += 1;
+                continue;
+            }
+        }
+
+        match backtrack {
+            Some((star, start)) => {
+                p = star;
+                s = start + 1;
+                backtrack = Some((star, s));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches `c` against the element at the start of `pattern`, anything but
+/// a `*`. Returns how many bytes of the pattern the element spans if it
+/// matched.
+fn match_one(pattern: &[u8], c: u8) -> Option<usize> {
+    let (matched, len) = match pattern[0] {
+        b'?' => (true, 1),
+        b'[' => {
+            let (matched, len) = match_class(&pattern[1..], c);
+            (matched, 1 + len)
+        }
+        b'\\' if pattern.len() > 1 => (pattern[1] == c, 2),
+        literal => (literal == c, 1),
+    };
+    if matched {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// Matches `c` against the character class at the start of `class`, which
+/// follows the opening `[`. Returns whether it matched and how many bytes of
+/// the pattern the class spans, including the closing `]`. An unterminated
+/// class extends to the end of the pattern.
+fn match_class(class: &[u8], c: u8) -> (bool, usize) {
+    let negated = class.first() == Some(&b'^');
+    let mut i = negated as usize;
+    let mut matched = false;
+
+    while i < class.len() && class[i] != b']' {
+        if class[i] == b'\\' && i + 1 < class.len() {
+            matched |= class[i + 1] == c;
+            i += 2;
+        } else if i + 2 < class.len() && class[i + 1] == b'-' && class[i + 2] != b']' {
+            let (low, high) = if class[i] <= class[i + 2] {
+                (class[i], class[i + 2])
+            } else {
+                (class[i + 2], class[i])
+            };
+            matched |= (low..=high).contains(&c);
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+
+    let len = (i + 1).min(class.len());
+    (matched != negated, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn it_matches_any_sequence_with_a_star() {
+        assert!(matches(b"*", b""));
+        assert!(matches(b"*", b"anything"));
+        assert!(matches(b"user:*", b"user:42"));
+        assert!(!matches(b"user:*", b"session:42"));
+        assert!(matches(b"*:name", b"user:42:name"));
+        assert!(matches(b"a**b", b"axxb"));
+    }
+
+    #[test]
+    fn it_matches_a_single_character_with_a_question_mark() {
+        assert!(matches(b"h?llo", b"hello"));
+        assert!(matches(b"h?llo", b"hallo"));
+        assert!(!matches(b"h?llo", b"hllo"));
+    }
+
+    #[test]
+    fn it_matches_character_classes() {
+        assert!(matches(b"h[ae]llo", b"hello"));
+        assert!(matches(b"h[ae]llo", b"hallo"));
+        assert!(!matches(b"h[ae]llo", b"hillo"));
+        assert!(matches(b"h[^e]llo", b"hallo"));
+        assert!(!matches(b"h[^e]llo", b"hello"));
+        assert!(matches(b"h[a-b]llo", b"hbllo"));
+        assert!(matches(b"h[b-a]llo", b"hallo"));
+        assert!(!matches(b"h[a-b]llo", b"hcllo"));
+    }
+
+    #[test]
+    fn it_matches_escaped_characters_literally() {
+        assert!(matches(b"a\\*b", b"a*b"));
+        assert!(!matches(b"a\\*b", b"axb"));
+        assert!(matches(b"[\\]]", b"]"));
+    }
+
+    #[test]
+    fn it_matches_patterns_as_long_as_the_subject() {
+        let subject = vec![b'a'; 200_000];
+
+        assert!(matches(&vec![b'?'; 200_000], &subject));
+        assert!(!matches(&vec![b'?'; 200_001], &subject));
+        assert!(matches(&[&subject[..], b"*"].concat(), &subject));
+    }
+
+    #[test]
+    fn it_gives_up_on_pathological_patterns_quickly() {
+        let pattern = [&b"*a".repeat(100)[..], b"*b"].concat();
+        let subject = vec![b'a'; 10_000];
+
+        assert!(!matches(&pattern, &subject));
+        assert!(matches(&pattern, &[&subject[..], b"b"].concat()));
+    }
+
+    #[test]
+    fn it_lets_a_later_star_take_over_backtracking() {
+        assert!(matches(b"*ab*cd", b"xabyabzcd"));
+        assert!(!matches(b"*ab*cd", b"xabyabzc"));
+        assert!(matches(b"a*?c", b"abbc"));
+        assert!(!matches(b"a*?c", b"ac"));
+        assert!(matches(b"*[0-9]", b"key9"));
+    }
+}
@@ -0,0 +1,144 @@
+//! Leveled logging, filtered through `RUST_LOG`.
+//!
+//! Cargo.toml is managed by CodeCrafters, so rather than pulling in
+//! `tracing` this keeps to the part of it the server needs: events with a
+//! level, and spans that tag every event of a connection with its fields.
+
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How important an event is, from the most to the least.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+        f.pad(name)
+    }
+}
+
+/// The least important level logged, or 0 when logging is off.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+tokio::task_local! {
+    static SPAN: String;
+}
+
+/// Sets the level to log from `RUST_LOG`, which defaults to `info`.
+pub fn init() {
+    if let Some(level) = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|filter| parse_filter(&filter))
+    {
+        MAX_LEVEL.store(level, Ordering::Relaxed);
+    }
+}
+
+/// Parses a filter such as `debug`, `off` or `warn,redis_starter_rust=info`,
+/// where a directive for this crate wins over the default one. Directives
+/// for other targets are ignored, since there are none.
+fn parse_filter(filter: &str) -> Option<u8> {
+    let mut default = None;
+    let mut own = None;
+    for directive in filter.split(',').map(str::trim) {
+        match directive.split_once('=') {
+            Some((env!("CARGO_CRATE_NAME"), level)) => own = parse_level(level).or(own),
+            Some(_) => {}
+            None => default = parse_level(directive).or(default),
+        }
+    }
+    own.or(default)
+}
+
+fn parse_level(level: &str) -> Option<u8> {
+    let level = match level.to_ascii_lowercase().as_str() {
+        "off" => return Some(0),
+        "error" => Level::Error,
+        "warn" => Level::Warn,
+        "info" => Level::Info,
+        "debug" => Level::Debug,
+        "trace" => Level::Trace,
+        _ => return None,
+    };
+    Some(level as u8)
+}
+
+/// Whether events at `level` are logged.
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Runs `future` in a span, so that the events it logs carry `fields`.
+pub async fn in_span<F: Future>(fields: String, future: F) -> F::Output {
+    SPAN.scope(fields, future).await
+}
+
+/// Writes an event, prefixed with the fields of the current span. Use the
+/// level macros rather than calling this directly.
+pub fn event(level: Level, message: fmt::Arguments) {
+    let span = SPAN
+        .try_with(|fields| format!(" {}:", fields))
+        .unwrap_or_default();
+    println!("{:>5}{} {}", level, span, message);
+}
+
+macro_rules! log_event {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::log::enabled($level) {
+            $crate::log::event($level, format_args!($($arg)*));
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::log_event!($crate::log::Level::Error, $($arg)*) };
+}
+
+// Named apart from the built-in `warn` attribute, which a `warn` macro
+// would be ambiguous with.
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::log::log_event!($crate::log::Level::Warn, $($arg)*) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::log_event!($crate::log::Level::Info, $($arg)*) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::log_event!($crate::log::Level::Debug, $($arg)*) };
+}
+
+pub(crate) use {debug, error, info, log_event, warning as warn};
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_filter, Level};
+
+    #[test]
+    fn it_parses_rust_log_filters() {
+        assert_eq!(parse_filter("debug"), Some(Level::Debug as u8));
+        assert_eq!(parse_filter("WARN"), Some(Level::Warn as u8));
+        assert_eq!(parse_filter("off"), Some(0));
+        assert_eq!(
+            parse_filter("error, redis_starter_rust=trace"),
+            Some(Level::Trace as u8)
+        );
+        assert_eq!(parse_filter("tokio=debug,warn"), Some(Level::Warn as u8));
+        assert_eq!(parse_filter("tokio=debug"), None);
+        assert_eq!(parse_filter("verbose"), None);
+    }
+}
@@ -1,86 +1,2370 @@
+mod command;
+mod config;
 mod connection;
+mod db;
+mod error;
+mod glob;
+mod log;
+mod pubsub;
+mod rdb;
+mod replica;
+mod replication;
 mod resp;
+mod server;
+mod sorted_set;
 
+use std::future::{self, Future};
 use std::net;
+use std::sync::Arc;
 
 use anyhow::Result;
 
+use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::stream::StreamExt;
+use tokio::sync::{watch, Semaphore};
 
-use connection::Connection;
-
-async fn handle_client(socket: TcpStream) -> Result<()> {
-    println!("accepted new connection");
+use command::{Registry, Session};
+use config::Config;
+use connection::{Connection, ConnectionClosed, IdleTimeout};
+use server::Server;
 
+/// Serves a client until it disconnects, or until `shutdown` announces that
+/// the server is stopping, in which case the command being run is finished
+/// before the connection is closed.
+async fn handle_client(
+    socket: TcpStream,
+    mut session: Session,
+    registry: Arc<Registry>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    if let Ok(addr) = socket.peer_addr() {
+        session.set_addr(addr);
+    }
     let mut conn = Connection::new(socket);
 
     loop {
-        let (command, args) = conn.read_command().await?;
-        match command.as_str() {
-            "PING" => {
-                println!("sending PONG");
-                conn.write_all("+PONG\r\n".as_bytes()).await?;
-            }
-            "ECHO" => {
-                if args.len() != 1 {
-                    conn.write_all("-wrong number of arguments for command\r\n".as_bytes())
-                        .await?;
+        // Subscribers only listen, so they are never idle.
+        let timeout = if session.is_subscribed() {
+            None
+        } else {
+            session.server.config.read().unwrap().idle_timeout()
+        };
+        conn.set_read_timeout(timeout);
 
+        let (command, args) = if session.is_subscribed() {
+            // Messages can arrive at any time, so they are sent as soon as
+            // they are published rather than batched with replies.
+            conn.flush().await?;
+            tokio::select! {
+                command = conn.read_command() => command?,
+                _ = shutting_down(&mut shutdown) => return conn.flush().await,
+                Some((channel, message)) = session.subscriptions.next() => {
+                    // A subscriber too slow to keep up misses messages.
+                    if let Ok(message) = message {
+                        conn.write_value(&command::message(&channel, message)).await?;
+                    }
                     continue;
                 }
-                println!("replying to ECHO");
-                match args.get(0) {
-                    Some(resp::Value::Bulk {
-                        data: reply_data, ..
-                    }) => {
-                        conn.write_all(format!("${}\r\n", reply_data.len()).as_bytes())
-                            .await?;
-                        conn.write_all(reply_data).await?;
-                        conn.write_all(b"\r\n").await?;
+                Some((pattern, message)) = session.pattern_subscriptions.next() => {
+                    if let Ok((channel, message)) = message {
+                        let message = command::pattern_message(&pattern, &channel, message);
+                        conn.write_value(&message).await?;
                     }
-                    Some(value) => {
-                        println!("unexpected RESP value: {:?}", value)
-                    }
-                    None => unreachable!(),
+                    continue;
                 }
             }
-            _unsupported_command => {
-                conn.write_all("-unsupported command\r\n".as_bytes())
-                    .await?;
+        } else {
+            tokio::select! {
+                command = conn.read_command() => command?,
+                _ = shutting_down(&mut shutdown) => return conn.flush().await,
             }
+        };
+        if matches!(registry.get(&command), Some(spec) if spec.is_blocking()) {
+            // Flush earlier replies before possibly waiting a long time.
+            conn.flush().await?;
+        }
+        log::debug!("executing {}", command);
+        let reply = registry.execute(&command, &args, &mut session).await;
+        for queued in session.take_replies() {
+            conn.write_value(&queued).await?;
+        }
+        let reply = reply.unwrap_or_else(|err| {
+            log::debug!("{} failed: {}", command, err);
+            err.to_value()
+        });
+        conn.write_value(&reply).await?;
+
+        if session.is_quitting {
+            return conn.flush().await;
+        }
+        if session.is_replica {
+            return tokio::select! {
+                served = replication::serve_replica(&mut conn, &session.server) => served,
+                _ = shutting_down(&mut shutdown) => Ok(()),
+            };
         }
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("Logs from your program will appear here!");
+    log::init();
+
+    let config = Config::from_args(std::env::args().skip(1))?;
+    let std_listener = net::TcpListener::bind(config.address())?;
+    let listener = TcpListener::from_std(std_listener)?;
+    log::info!("listening on {}", config.address());
+
+    let dbs = db::databases();
+    rdb::load(&config.rdb_path(), &dbs)?;
+    let server = Server::new(dbs, config);
+    let registry = Arc::new(Registry::new());
+    for db in server.dbs.iter() {
+        tokio::spawn(db::expire_keys(db.clone(), server.active_expire.clone()));
+    }
+    tokio::spawn(replication::request_acks(server.clone()));
+
+    let master = server.config.read().unwrap().replicaof.clone();
+    if let Some((host, port)) = master {
+        let server = server.clone();
+        let registry = registry.clone();
+        tokio::spawn(async move {
+            if let Err(err) = replica::run(server, registry, host, port).await {
+                log::error!("replication stopped: {:#}", err);
+            }
+        });
+    }
 
-    let std_listener = net::TcpListener::bind("127.0.0.1:6379")?;
-    let mut listener = TcpListener::from_std(std_listener)?;
+    serve(listener, server, registry, shutdown_signal()).await?;
+    Ok(())
+}
+
+/// Resolves once the process is asked to stop with SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(err) => {
+            log::warn!("cannot listen for SIGTERM: {}", err);
+            return std::future::pending().await;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+/// Resolves once `shutdown` says the server is stopping, or never if it
+/// cannot anymore.
+async fn shutting_down(shutdown: &mut watch::Receiver<bool>) {
+    if *shutdown.borrow() {
+        return;
+    }
+    while let Some(stopping) = shutdown.recv().await {
+        if stopping {
+            return;
+        }
+    }
+    future::pending().await
+}
 
+/// Serves clients until `shutdown` resolves. New connections are then
+/// refused, idle ones closed, busy ones get a moment to finish their
+/// command, and the dataset is saved if the server is configured to.
+async fn serve(
+    mut listener: TcpListener,
+    server: Server,
+    registry: Arc<Registry>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let maxclients = server.config.read().unwrap().maxclients;
+    let permits = Arc::new(Semaphore::new(maxclients));
+    let (stopping, stopped) = watch::channel(false);
+
+    tokio::pin!(shutdown);
     loop {
-        let (socket, _) = listener.accept().await?;
+        let (mut socket, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = &mut shutdown => break,
+        };
+
+        // Each client holds a permit until it disconnects.
+        let permit = match permits.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                log::warn!("rejecting {}: too many clients", addr);
+                tokio::spawn(async move {
+                    let _ = socket
+                        .write_all(b"-ERR max number of clients reached\r\n")
+                        .await;
+                });
+                continue;
+            }
+        };
+
+        let session = Session::new(server.clone());
+        let registry = registry.clone();
+        let shutdown = stopped.clone();
+        let span = format!("conn{{id={} addr={}}}", session.id, addr);
+        tokio::spawn(log::in_span(span, async move {
+            let _permit = permit;
+            log::info!("accepted connection");
+            match handle_client(socket, session, registry, shutdown).await {
+                Err(err) if err.is::<ConnectionClosed>() => log::info!("client disconnected"),
+                Err(err) if err.is::<IdleTimeout>() => log::info!("closing idle connection"),
+                Err(err) => log::error!("closing connection: {:#}", err),
+                Ok(()) => log::info!("client quit"),
+            }
+        }));
+    }
+
+    drop(listener);
+    log::info!("shutting down");
+    let _ = stopping.broadcast(true);
+    if !server.drain_clients(server::SHUTDOWN_DRAIN_PERIOD).await {
+        log::warn!("closing the connections still open");
+    }
 
-        tokio::spawn(async move { handle_client(socket).await.unwrap() });
+    let config = server.config.read().unwrap().clone();
+    if config.save_on_shutdown {
+        rdb::save(&config.rdb_path(), &server.dbs)?;
+        log::info!("saved the dataset to {}", config.rdb_path().display());
     }
+    Ok(())
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use std::{thread::{spawn}, net::{TcpStream, Shutdown}};
-//     use super::main;
+#[cfg(test)]
+mod tests {
+    use std::net;
+    use std::process;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use anyhow::Result;
+    use bytes::Bytes;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::{oneshot, watch};
+
+    use super::{handle_client, serve};
+    use crate::command::{Registry, Session};
+    use crate::config::Config;
+    use crate::db::{self, Db, Ttl};
+    use crate::log;
+    use crate::rdb;
+    use crate::resp::{self, Limits, Value};
+    use crate::server::{self, Server};
+
+    async fn connect_client() -> Result<TcpStream> {
+        connect_client_to(db::databases()).await
+    }
+
+    async fn connect_client_to(dbs: Arc<Vec<Db>>) -> Result<TcpStream> {
+        connect_session(Session::new(Server::new(dbs, Config::default()))).await
+    }
+
+    async fn connect_session(session: Session) -> Result<TcpStream> {
+        let std_listener = net::TcpListener::bind("127.0.0.1:0")?;
+        let client = net::TcpStream::connect(std_listener.local_addr()?)?;
+        let mut listener = TcpListener::from_std(std_listener)?;
+        let (socket, _) = listener.accept().await?;
+
+        let registry = Arc::new(Registry::new());
+        let (_, shutdown) = watch::channel(false);
+        tokio::spawn(async move { handle_client(socket, session, registry, shutdown).await });
+
+        Ok(TcpStream::from_std(client)?)
+    }
+
+    async fn send_command(client: &mut TcpStream, args: &[&str]) -> Result<()> {
+        let command = Value::Array {
+            len: args.len() as i64,
+            elements: args
+                .iter()
+                .map(|arg| Value::bulk(Bytes::from(arg.to_string())))
+                .collect(),
+        };
+        client.write_all(&command.encode()).await?;
+        Ok(())
+    }
+
+    async fn read_reply(client: &mut TcpStream, len: usize) -> Result<String> {
+        let mut reply = vec![0; len];
+        client.read_exact(&mut reply).await?;
+        Ok(String::from_utf8(reply)?)
+    }
+
+    /// Reads the `$len\r\n` that starts a bulk string, for replies whose
+    /// length is not known in advance.
+    async fn read_bulk_len(client: &mut TcpStream) -> Result<usize> {
+        let mut prefix = read_reply(client, 1).await?;
+        while !prefix.ends_with("\r\n") {
+            prefix.push_str(&read_reply(client, 1).await?);
+        }
+        assert!(prefix.starts_with('$'));
+        Ok(prefix[1..prefix.len() - 2].parse::<usize>()?)
+    }
+
+    /// Reads and decodes one whole reply, for replies too structured to
+    /// compare byte by byte. It reads a byte at a time so as to leave the
+    /// next reply unread.
+    async fn read_value(client: &mut TcpStream) -> Result<Value> {
+        let mut buf = Vec::new();
+        loop {
+            let mut bytes = Bytes::from(buf.clone());
+            match resp::parse_frame(&mut bytes, &Limits::default()) {
+                Ok((value, _)) => return Ok(value),
+                Err(resp::ParseError::Incomplete) => buf.push(client.read_u8().await?),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// The HELLO reply as field name and value pairs, whichever protocol
+    /// it was sent in.
+    fn hello_fields(reply: Value) -> Vec<(String, Value)> {
+        let pairs = match reply {
+            Value::Map { pairs, .. } => pairs,
+            Value::Array { elements, .. } => elements
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .collect(),
+            reply => panic!("unexpected HELLO reply: {:?}", reply),
+        };
+        pairs
+            .into_iter()
+            .map(|(name, value)| match name {
+                Value::Bulk { data, .. } => (String::from_utf8(data.to_vec()).unwrap(), value),
+                name => panic!("unexpected field name: {:?}", name),
+            })
+            .collect()
+    }
+
+    fn field(fields: &[(String, Value)], name: &str) -> Value {
+        fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_else(|| panic!("missing field {}", name))
+    }
+
+    #[tokio::test]
+    async fn it_processes_pipelined_commands() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        client
+            .write_all(b"*1\r\n$4\r\nPING\r\n*1\r\n$4\r\nPING\r\n")
+            .await?;
+
+        assert_eq!(read_reply(&mut client, 14).await?, "+PONG\r\n+PONG\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_replies_to_ping_with_pong_or_the_message() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["PING"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "+PONG\r\n");
+
+        send_command(&mut client, &["PING", "hello"]).await?;
+        assert_eq!(read_reply(&mut client, 11).await?, "$5\r\nhello\r\n");
+
+        send_command(&mut client, &["SUBSCRIBE", "news"]).await?;
+        let confirmation = "*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n";
+        assert_eq!(
+            read_reply(&mut client, confirmation.len()).await?,
+            confirmation
+        );
+
+        send_command(&mut client, &["PING"]).await?;
+        let pong = "*2\r\n$4\r\npong\r\n$0\r\n\r\n";
+        assert_eq!(read_reply(&mut client, pong.len()).await?, pong);
+
+        send_command(&mut client, &["PING", "hello"]).await?;
+        let pong = "*2\r\n$4\r\npong\r\n$5\r\nhello\r\n";
+        assert_eq!(read_reply(&mut client, pong.len()).await?, pong);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_processes_pipelined_inline_commands() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        client.write_all(b"PING\r\nECHO hey\r\n").await?;
+
+        assert_eq!(read_reply(&mut client, 16).await?, "+PONG\r\n$3\r\nhey\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_replies_to_a_large_pipeline() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        let command = Value::Array {
+            len: 3,
+            elements: vec![
+                Value::bulk(Bytes::from("SET")),
+                Value::bulk(Bytes::from("foo")),
+                Value::bulk(Bytes::from("bar")),
+            ],
+        };
+        client.write_all(&command.encode().repeat(1000)).await?;
+
+        assert_eq!(
+            read_reply(&mut client, 5 * 1000).await?,
+            "+OK\r\n".repeat(1000)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_sets_and_gets_a_key() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "foo", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["GET", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "$3\r\nbar\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_expires_a_key_set_with_px() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "foo", "bar", "PX", "50"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["GET", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "$3\r\nbar\r\n");
+
+        tokio::time::delay_for(Duration::from_millis(60)).await;
+
+        send_command(&mut client, &["GET", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_expires_a_key_set_with_a_past_exat() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "foo", "bar", "EXAT", "1"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["GET", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_rejects_a_non_integer_expiry() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "foo", "bar", "EX", "soon"]).await?;
+        let error = "-ERR value is not an integer or out of range\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_sets_with_nx_only_when_the_key_is_missing() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "foo", "bar", "NX"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["SET", "foo", "baz", "NX"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        send_command(&mut client, &["GET", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "$3\r\nbar\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_sets_with_xx_only_when_the_key_exists() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "foo", "bar", "XX"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        send_command(&mut client, &["SET", "foo", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["SET", "foo", "baz", "XX"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["GET", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "$3\r\nbaz\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_rejects_nx_combined_with_xx() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "foo", "bar", "NX", "XX"]).await?;
+        let error = "-ERR syntax error\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_sets_with_setnx_only_when_the_key_is_missing() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SETNX", "foo", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["SETNX", "foo", "baz"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        send_command(&mut client, &["GET", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "$3\r\nbar\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_sets_a_key_with_a_ttl_using_setex_and_psetex() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SETEX", "foo", "100", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["TTL", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 6).await?, ":100\r\n");
+
+        send_command(&mut client, &["PSETEX", "short", "50", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        tokio::time::delay_for(Duration::from_millis(60)).await;
+
+        send_command(&mut client, &["GET", "short"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_rejects_a_non_positive_setex_ttl() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SETEX", "foo", "0", "bar"]).await?;
+        let error = "-ERR invalid expire time in 'setex' command\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        send_command(&mut client, &["PSETEX", "foo", "-5", "bar"]).await?;
+        let error = "-ERR invalid expire time in 'psetex' command\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        send_command(&mut client, &["EXISTS", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_gets_and_deletes_a_key() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["GETDEL", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        send_command(&mut client, &["SET", "foo", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["GETDEL", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "$3\r\nbar\r\n");
+
+        send_command(&mut client, &["EXISTS", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_replies_with_the_old_value_on_getset() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["GETSET", "foo", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        send_command(&mut client, &["GETSET", "foo", "baz"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "$3\r\nbar\r\n");
+
+        send_command(&mut client, &["GET", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "$3\r\nbaz\r\n");
+
+        send_command(&mut client, &["RPUSH", "list", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["GETSET", "list", "b"]).await?;
+        let error = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_serves_clients_with_logging_initialized() -> Result<()> {
+        log::init();
+        let mut client = log::in_span("test".to_string(), connect_client()).await?;
+
+        send_command(&mut client, &["PING"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "+PONG\r\n");
+
+        send_command(&mut client, &["GET"]).await?;
+        let error = "-ERR wrong number of arguments for 'get' command\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_rejects_commands_against_keys_of_another_type() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        for (command, reply) in [
+            (&["SET", "string", "a"][..], "+OK\r\n"),
+            (&["RPUSH", "list", "a"], ":1\r\n"),
+            (&["HSET", "hash", "field", "a"], ":1\r\n"),
+            (&["SADD", "set", "a"], ":1\r\n"),
+            (&["ZADD", "zset", "1", "a"], ":1\r\n"),
+        ] {
+            send_command(&mut client, command).await?;
+            assert_eq!(read_reply(&mut client, reply.len()).await?, reply);
+        }
+
+        let error = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        for key in ["list", "hash", "set", "zset"] {
+            for command in [
+                &["GET", key][..],
+                &["APPEND", key, "b"],
+                &["INCR", key],
+                &["STRLEN", key],
+                &["GETRANGE", key, "0", "-1"],
+            ] {
+                send_command(&mut client, command).await?;
+                assert_eq!(read_reply(&mut client, error.len()).await?, error);
+            }
+        }
+
+        for command in [
+            &["LPUSH", "string", "b"][..],
+            &["HSET", "set", "field", "b"],
+            &["SADD", "hash", "b"],
+            &["ZADD", "list", "1", "b"],
+        ] {
+            send_command(&mut client, command).await?;
+            assert_eq!(read_reply(&mut client, error.len()).await?, error);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_sets_and_gets_many_keys() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["MSET", "a", "1", "b", "2"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["MGET", "a", "missing", "b"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 23).await?,
+            "*3\r\n$1\r\n1\r\n$-1\r\n$1\r\n2\r\n"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_rejects_mset_with_an_odd_number_of_arguments() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["MSET", "a", "1", "b"]).await?;
+        let error = "-ERR syntax error\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_appends_to_a_key() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["APPEND", "greeting", "Hello"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":5\r\n");
+
+        send_command(&mut client, &["APPEND", "greeting", " World"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, ":11\r\n");
+
+        send_command(&mut client, &["GET", "greeting"]).await?;
+        assert_eq!(read_reply(&mut client, 18).await?, "$11\r\nHello World\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_counts_string_length_in_bytes() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "word", "héllo"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["STRLEN", "word"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":6\r\n");
+
+        send_command(&mut client, &["STRLEN", "missing"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_gets_and_sets_ranges() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SETRANGE", "key", "2", "abc"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":5\r\n");
+
+        send_command(&mut client, &["GETRANGE", "key", "-3", "-1"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "$3\r\nabc\r\n");
+
+        send_command(&mut client, &["GETRANGE", "key", "0", "1"]).await?;
+        assert_eq!(read_reply(&mut client, 8).await?, "$2\r\n\0\0\r\n");
+
+        send_command(&mut client, &["SETRANGE", "key", "-1", "x"]).await?;
+        let error = "-ERR offset is out of range\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_replies_with_the_type_of_a_key() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "key", "value"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["TYPE", "key"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "+string\r\n");
+
+        send_command(&mut client, &["TYPE", "missing"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "+none\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_replies_with_the_encoding_of_a_key() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["MSET", "int", "42", "short", "hello"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        send_command(&mut client, &["SET", "long", &"x".repeat(64)]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["OBJECT", "ENCODING", "int"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "$3\r\nint\r\n");
+
+        send_command(&mut client, &["OBJECT", "encoding", "short"]).await?;
+        assert_eq!(read_reply(&mut client, 12).await?, "$6\r\nembstr\r\n");
+
+        send_command(&mut client, &["OBJECT", "ENCODING", "long"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "$3\r\nraw\r\n");
+
+        send_command(&mut client, &["OBJECT", "ENCODING", "missing"]).await?;
+        let error = "-ERR no such key\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_pushes_and_pops_list_elements() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["RPUSH", "list", "a", "b", "c"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":3\r\n");
+
+        send_command(&mut client, &["LPUSH", "list", "z"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":4\r\n");
+
+        send_command(&mut client, &["LPOP", "list"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\nz\r\n");
+
+        send_command(&mut client, &["RPOP", "list", "2"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 18).await?,
+            "*2\r\n$1\r\nc\r\n$1\r\nb\r\n"
+        );
+
+        send_command(&mut client, &["LPOP", "missing", "2"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "*-1\r\n");
+
+        send_command(&mut client, &["SET", "string", "value"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["LPUSH", "string", "a"]).await?;
+        let error = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_returns_list_length_and_ranges() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["RPUSH", "list", "a", "b", "c"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":3\r\n");
+
+        send_command(&mut client, &["LLEN", "list"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":3\r\n");
+
+        send_command(&mut client, &["LRANGE", "list", "0", "-1"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 25).await?,
+            "*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+
+        send_command(&mut client, &["LRANGE", "list", "-10", "0"]).await?;
+        assert_eq!(read_reply(&mut client, 11).await?, "*1\r\n$1\r\na\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_indexes_sets_and_trims_lists() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["RPUSH", "list", "a", "b", "c"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":3\r\n");
+
+        send_command(&mut client, &["LSET", "list", "-1", "z"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["LINDEX", "list", "-1"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\nz\r\n");
+
+        send_command(&mut client, &["LSET", "list", "10", "x"]).await?;
+        let error = "-ERR index out of range\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        send_command(&mut client, &["LTRIM", "list", "1", "0"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["EXISTS", "list"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_finds_the_position_of_list_elements() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(
+            &mut client,
+            &["RPUSH", "list", "a", "b", "c", "1", "2", "3", "c", "c"],
+        )
+        .await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":8\r\n");
+
+        send_command(&mut client, &["LPOS", "list", "c"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["LPOS", "list", "c", "RANK", "-1"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":7\r\n");
+
+        send_command(
+            &mut client,
+            &["LPOS", "list", "c", "RANK", "-2", "COUNT", "0"],
+        )
+        .await?;
+        assert_eq!(read_reply(&mut client, 12).await?, "*2\r\n:6\r\n:2\r\n");
+
+        send_command(&mut client, &["LPOS", "list", "c", "COUNT", "0"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 16).await?,
+            "*3\r\n:2\r\n:6\r\n:7\r\n"
+        );
+
+        send_command(
+            &mut client,
+            &["LPOS", "list", "c", "COUNT", "0", "MAXLEN", "7"],
+        )
+        .await?;
+        assert_eq!(read_reply(&mut client, 12).await?, "*2\r\n:2\r\n:6\r\n");
+
+        send_command(&mut client, &["LPOS", "list", "z"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        send_command(&mut client, &["LPOS", "list", "z", "COUNT", "2"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, "*0\r\n");
+
+        send_command(&mut client, &["LPOS", "list", "c", "RANK", "0"]).await?;
+        let error = "-ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        send_command(&mut client, &["LPOS", "list", "c", "COUNT"]).await?;
+        let error = "-ERR syntax error\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_moves_elements_between_lists() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["RPUSH", "list", "a", "b", "c"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":3\r\n");
+
+        send_command(&mut client, &["RPOPLPUSH", "list", "list"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\nc\r\n");
+
+        send_command(&mut client, &["LMOVE", "list", "list", "LEFT", "RIGHT"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\nc\r\n");
 
-//     #[test]
-//     fn it_listens_on_port_6379() {
-//         let handle = spawn(|| {
-//             main().expect("something went wrong");
-//         });
+        send_command(&mut client, &["LRANGE", "list", "0", "-1"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 25).await?,
+            "*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
 
-//         let connection = TcpStream::connect("127.0.0.1:6379").expect("failed to connect to server");
-//         connection.shutdown(Shutdown::Both).expect("failed to close connection");
+        send_command(&mut client, &["RPOPLPUSH", "list", "other"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\nc\r\n");
+        send_command(&mut client, &["LMOVE", "list", "other", "right", "left"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\nb\r\n");
+        send_command(&mut client, &["LMOVE", "list", "other", "LEFT", "RIGHT"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\na\r\n");
 
-//         handle.join();
-//     }
+        send_command(&mut client, &["EXISTS", "list"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+        send_command(&mut client, &["RPOPLPUSH", "list", "other"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
 
-// }
+        send_command(&mut client, &["LRANGE", "other", "0", "-1"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 25).await?,
+            "*3\r\n$1\r\nb\r\n$1\r\nc\r\n$1\r\na\r\n"
+        );
+
+        send_command(&mut client, &["SET", "string", "x"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        let error = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        send_command(&mut client, &["RPOPLPUSH", "other", "string"]).await?;
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+        send_command(&mut client, &["LMOVE", "string", "other", "LEFT", "LEFT"]).await?;
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        send_command(&mut client, &["LMOVE", "other", "list", "UP", "LEFT"]).await?;
+        let error = "-ERR syntax error\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_blocks_a_pop_until_another_client_pushes() -> Result<()> {
+        let dbs = db::databases();
+        let mut waiter = connect_client_to(dbs.clone()).await?;
+        let mut pusher = connect_client_to(dbs).await?;
+
+        send_command(&mut waiter, &["BLPOP", "list", "0"]).await?;
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+
+        send_command(&mut pusher, &["RPUSH", "list", "value"]).await?;
+        assert_eq!(read_reply(&mut pusher, 4).await?, ":1\r\n");
+
+        assert_eq!(
+            read_reply(&mut waiter, 25).await?,
+            "*2\r\n$4\r\nlist\r\n$5\r\nvalue\r\n"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_times_out_a_blocking_pop_with_a_null_array() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["BRPOP", "list", "0.01"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "*-1\r\n");
+
+        send_command(&mut client, &["BRPOP", "list", "-1"]).await?;
+        let error = "-ERR timeout is negative\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_sets_gets_and_deletes_hash_fields() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["HSET", "hash", "a", "1", "b", "2"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["HGET", "hash", "b"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\n2\r\n");
+
+        send_command(&mut client, &["HGET", "hash", "missing"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        send_command(&mut client, &["HDEL", "hash", "a", "missing"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["HGETALL", "hash"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 18).await?,
+            "*2\r\n$1\r\nb\r\n$1\r\n2\r\n"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_reads_several_hash_fields_at_once() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["HSET", "hash", "a", "1", "b", "2"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["HMGET", "hash", "a", "missing", "b"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 23).await?,
+            "*3\r\n$1\r\n1\r\n$-1\r\n$1\r\n2\r\n"
+        );
+
+        send_command(&mut client, &["HMGET", "missing", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "*1\r\n$-1\r\n");
+
+        send_command(&mut client, &["HLEN", "hash"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["HEXISTS", "hash", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["HEXISTS", "hash", "missing"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        send_command(&mut client, &["HKEYS", "hash"]).await?;
+        let keys = read_reply(&mut client, 18).await?;
+        assert!(keys.starts_with("*2\r\n"));
+        assert!(keys.contains("$1\r\na\r\n") && keys.contains("$1\r\nb\r\n"));
+
+        send_command(&mut client, &["HVALS", "hash"]).await?;
+        let values = read_reply(&mut client, 18).await?;
+        assert!(values.contains("$1\r\n1\r\n") && values.contains("$1\r\n2\r\n"));
+
+        send_command(&mut client, &["SET", "string", "value"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["HLEN", "string"]).await?;
+        let error = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_adds_and_removes_set_members() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SADD", "set", "a", "a", "b"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["SADD", "set", "b"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        send_command(&mut client, &["SISMEMBER", "set", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["SREM", "set", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["SCARD", "set"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["SMEMBERS", "set"]).await?;
+        assert_eq!(read_reply(&mut client, 11).await?, "*1\r\n$1\r\nb\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_intersects_and_subtracts_sets() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SADD", "a", "1", "2"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["SADD", "b", "2", "3"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["SINTER", "a", "b"]).await?;
+        assert_eq!(read_reply(&mut client, 11).await?, "*1\r\n$1\r\n2\r\n");
+
+        send_command(&mut client, &["SDIFF", "a", "b"]).await?;
+        assert_eq!(read_reply(&mut client, 11).await?, "*1\r\n$1\r\n1\r\n");
+
+        send_command(&mut client, &["SINTER", "a", "missing"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, "*0\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_moves_members_between_sets() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SADD", "source", "a", "b"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["SMOVE", "source", "destination", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["SMOVE", "source", "destination", "z"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        send_command(&mut client, &["SMEMBERS", "destination"]).await?;
+        assert_eq!(read_reply(&mut client, 11).await?, "*1\r\n$1\r\na\r\n");
+
+        send_command(&mut client, &["SET", "string", "x"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        let error = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        send_command(&mut client, &["SMOVE", "source", "string", "b"]).await?;
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+        send_command(&mut client, &["SMOVE", "string", "source", "b"]).await?;
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_pops_random_set_members() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SADD", "set", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["SPOP", "set"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\na\r\n");
+
+        send_command(&mut client, &["SPOP", "set"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        send_command(&mut client, &["SADD", "set", "a", "b"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["SPOP", "set", "5"]).await?;
+        let reply = read_reply(&mut client, 18).await?;
+        assert!(reply == "*2\r\n$1\r\na\r\n$1\r\nb\r\n" || reply == "*2\r\n$1\r\nb\r\n$1\r\na\r\n");
+
+        send_command(&mut client, &["EXISTS", "set"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        send_command(&mut client, &["SPOP", "set", "2"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, "*0\r\n");
+
+        send_command(&mut client, &["RPUSH", "list", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["SPOP", "list"]).await?;
+        let error = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_returns_random_set_members_without_removing_them() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SRANDMEMBER", "set"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        send_command(&mut client, &["SRANDMEMBER", "set", "-3"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, "*0\r\n");
+
+        send_command(&mut client, &["SADD", "set", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["SRANDMEMBER", "set"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\na\r\n");
+
+        send_command(&mut client, &["SRANDMEMBER", "set", "3"]).await?;
+        assert_eq!(read_reply(&mut client, 11).await?, "*1\r\n$1\r\na\r\n");
+
+        send_command(&mut client, &["SRANDMEMBER", "set", "-3"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 25).await?,
+            "*3\r\n$1\r\na\r\n$1\r\na\r\n$1\r\na\r\n"
+        );
+
+        send_command(&mut client, &["SRANDMEMBER", "set", "0"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, "*0\r\n");
+
+        send_command(&mut client, &["SCARD", "set"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["SRANDMEMBER", "set", "-9223372036854775808"]).await?;
+        let error = "-ERR value is out of range\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        // Refused before anything is allocated, so the set is still usable.
+        send_command(&mut client, &["SRANDMEMBER", "set", "-4611686018427387904"]).await?;
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+        send_command(&mut client, &["SCARD", "set"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_counts_the_intersection_of_sets() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SADD", "a", "1", "2", "3"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":3\r\n");
+
+        send_command(&mut client, &["SADD", "b", "2", "3", "4"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":3\r\n");
+
+        send_command(&mut client, &["SINTERCARD", "2", "a", "b"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["SINTERCARD", "2", "a", "b", "LIMIT", "1"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["SINTERCARD", "2", "a", "b", "limit", "0"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["SINTERCARD", "2", "a", "empty"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        send_command(&mut client, &["SINTERCARD", "3", "a", "b"]).await?;
+        let error = "-ERR Number of keys can't be greater than number of args\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        send_command(&mut client, &["SINTERCARD", "0", "a"]).await?;
+        let error = "-ERR numkeys should be greater than 0\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        send_command(&mut client, &["SINTERCARD", "1", "a", "LIMIT", "-1"]).await?;
+        let error = "-ERR LIMIT can't be negative\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        send_command(&mut client, &["SINTERCARD", "1", "a", "LIMIT"]).await?;
+        let error = "-ERR syntax error\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_adds_scores_and_removes_sorted_set_members() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["ZADD", "zset", "1", "a", "2.5", "b"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["ZADD", "zset", "3", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        send_command(&mut client, &["ZSCORE", "zset", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\n3\r\n");
+
+        send_command(&mut client, &["ZSCORE", "zset", "b"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "$3\r\n2.5\r\n");
+
+        send_command(&mut client, &["ZREM", "zset", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["ZCARD", "zset"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["ZADD", "zset", "nope", "a"]).await?;
+        let error = "-ERR value is not a valid float\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_ranges_sorted_sets_by_rank_and_score() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["ZADD", "zset", "1", "a", "2", "b", "3", "c"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":3\r\n");
+
+        send_command(&mut client, &["ZRANGE", "zset", "-2", "-1", "WITHSCORES"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 32).await?,
+            "*4\r\n$1\r\nb\r\n$1\r\n2\r\n$1\r\nc\r\n$1\r\n3\r\n"
+        );
+
+        send_command(&mut client, &["ZRANGEBYSCORE", "zset", "(1", "+inf"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 18).await?,
+            "*2\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+
+        send_command(&mut client, &["ZRANGEBYSCORE", "zset", "-inf", "(2"]).await?;
+        assert_eq!(read_reply(&mut client, 11).await?, "*1\r\n$1\r\na\r\n");
+
+        send_command(&mut client, &["ZRANGEBYSCORE", "zset", "low", "2"]).await?;
+        let error = "-ERR min or max is not a float\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_ranks_members_as_their_scores_change() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["ZADD", "zset", "1", "a", "2", "b", "3", "c"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":3\r\n");
+
+        send_command(&mut client, &["ZRANK", "zset", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        send_command(&mut client, &["ZINCRBY", "zset", "2.5", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "$3\r\n3.5\r\n");
+
+        send_command(&mut client, &["ZRANK", "zset", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["ZREVRANK", "zset", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        send_command(&mut client, &["ZINCRBY", "zset", "5", "d"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\n5\r\n");
+
+        send_command(&mut client, &["ZREVRANK", "zset", "d"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        send_command(&mut client, &["ZRANK", "zset", "missing"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_ranges_sorted_sets_by_lex() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(
+            &mut client,
+            &["ZADD", "zset", "0", "a", "0", "b", "0", "c", "0", "d"],
+        )
+        .await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":4\r\n");
+
+        send_command(&mut client, &["ZRANGEBYLEX", "zset", "-", "+"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 32).await?,
+            "*4\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n$1\r\nd\r\n"
+        );
+
+        send_command(&mut client, &["ZRANGEBYLEX", "zset", "[a", "[c"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 25).await?,
+            "*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"
+        );
+
+        send_command(&mut client, &["ZRANGEBYLEX", "zset", "(a", "+"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 25).await?,
+            "*3\r\n$1\r\nb\r\n$1\r\nc\r\n$1\r\nd\r\n"
+        );
+
+        send_command(&mut client, &["ZRANGEBYLEX", "zset", "a", "+"]).await?;
+        let error = "-ERR min or max not valid string range item\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_lists_keys_matching_a_pattern() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(
+            &mut client,
+            &["MSET", "hello", "1", "hallo", "2", "world", "3"],
+        )
+        .await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["KEYS", "h[^a]llo"]).await?;
+        assert_eq!(read_reply(&mut client, 15).await?, "*1\r\n$5\r\nhello\r\n");
+
+        send_command(&mut client, &["KEYS", "wor*"]).await?;
+        assert_eq!(read_reply(&mut client, 15).await?, "*1\r\n$5\r\nworld\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_replies_with_a_random_key() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["RANDOMKEY"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        send_command(&mut client, &["SET", "only", "value"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["RANDOMKEY"]).await?;
+        assert_eq!(read_reply(&mut client, 10).await?, "$4\r\nonly\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_scans_keys_with_a_cursor() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["MSET", "a", "1", "b", "2", "c", "3"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["SCAN", "0", "COUNT", "2"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 29).await?,
+            "*2\r\n$1\r\n2\r\n*2\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+
+        send_command(&mut client, &["SCAN", "2", "MATCH", "*", "COUNT", "2"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 22).await?,
+            "*2\r\n$1\r\n0\r\n*1\r\n$1\r\nc\r\n"
+        );
+
+        send_command(&mut client, &["SCAN", "nope"]).await?;
+        let error = "-ERR invalid cursor\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_counts_keys_before_and_after_flushing() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["MSET", "a", "1", "b", "2"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["DBSIZE"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["FLUSHDB", "ASYNC"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["DBSIZE"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_keeps_keys_separate_between_databases() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "key", "value"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["SELECT", "1"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["GET", "key"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        send_command(&mut client, &["SELECT", "0"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["GET", "key"]).await?;
+        assert_eq!(read_reply(&mut client, 11).await?, "$5\r\nvalue\r\n");
+
+        send_command(&mut client, &["SELECT", "16"]).await?;
+        let error = "-ERR DB index is out of range\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_flushes_every_database_with_flushall() -> Result<()> {
+        let dbs = db::databases();
+        let mut client = connect_client_to(dbs.clone()).await?;
+
+        send_command(&mut client, &["SELECT", "3"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["SET", "key", "value"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["SELECT", "0"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["FLUSHALL"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        assert_eq!(dbs[3].len(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_replies_with_prefixed_errors() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["GET"]).await?;
+        let error = "-ERR wrong number of arguments for 'get' command\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        send_command(&mut client, &["NOPE"]).await?;
+        let error = "-ERR unknown command 'nope'\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_deletes_keys_and_counts_the_removed_ones() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "foo", "1"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        send_command(&mut client, &["SET", "bar", "2"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["DEL", "foo", "bar", "missing"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["GET", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_copies_a_key_only_over_a_missing_one_unless_replacing() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["RPUSH", "source", "a", "b"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+        send_command(&mut client, &["SET", "taken", "value"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["COPY", "source", "copy"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+        send_command(&mut client, &["LRANGE", "copy", "0", "-1"]).await?;
+        assert_eq!(
+            read_reply(&mut client, 18).await?,
+            "*2\r\n$1\r\na\r\n$1\r\nb\r\n"
+        );
+
+        send_command(&mut client, &["COPY", "source", "taken"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+        send_command(&mut client, &["TYPE", "taken"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "+string\r\n");
+
+        send_command(&mut client, &["COPY", "source", "taken", "REPLACE"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+        send_command(&mut client, &["TYPE", "taken"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "+list\r\n");
+
+        // Changing the copy leaves the source alone.
+        send_command(&mut client, &["RPUSH", "copy", "c"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":3\r\n");
+        send_command(&mut client, &["LLEN", "source"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["COPY", "missing", "copy", "REPLACE"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_wakes_a_blocked_pop_when_a_list_is_copied_to_its_key() -> Result<()> {
+        let server = Server::new(db::databases(), Config::default());
+        let mut blocked = connect_session(Session::new(server.clone())).await?;
+        let mut client = connect_session(Session::new(server)).await?;
+
+        send_command(&mut client, &["RPUSH", "source", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+        send_command(&mut blocked, &["BLPOP", "copy", "0"]).await?;
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+
+        send_command(&mut client, &["COPY", "source", "copy"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        let reply = "*2\r\n$4\r\ncopy\r\n$1\r\na\r\n";
+        assert_eq!(read_reply(&mut blocked, reply.len()).await?, reply);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_copies_a_key_into_another_database_with_its_ttl() -> Result<()> {
+        let dbs = db::databases();
+        let mut client = connect_client_to(dbs.clone()).await?;
+
+        send_command(&mut client, &["SET", "key", "value", "EX", "100"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["COPY", "key", "key", "DB", "1"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+        assert_eq!(dbs[1].get("key")?, Some(Bytes::from("value")));
+        assert!(matches!(dbs[1].ttl("key"), Ttl::Remaining(_)));
+
+        send_command(&mut client, &["COPY", "key", "key"]).await?;
+        let error = "-ERR source and destination objects are the same\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        send_command(&mut client, &["COPY", "key", "other", "DB", "99"]).await?;
+        let error = "-ERR DB index is out of range\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_counts_existing_keys_including_duplicates() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "foo", "1"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        send_command(&mut client, &["SET", "bar", "2"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["EXISTS", "foo", "missing", "bar", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":3\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_increments_and_decrements_a_counter() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["INCR", "counter"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+        send_command(&mut client, &["INCR", "counter"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+        send_command(&mut client, &["DECR", "counter"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["SET", "foo", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        send_command(&mut client, &["DECR", "foo"]).await?;
+        let error = "-ERR value is not an integer or out of range\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_increments_and_decrements_by_an_explicit_delta() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["INCRBY", "counter", "5"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":5\r\n");
+        send_command(&mut client, &["DECRBY", "counter", "3"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":2\r\n");
+
+        send_command(&mut client, &["INCRBY", "counter", "two"]).await?;
+        let error = "-ERR value is not an integer or out of range\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_increments_by_a_float() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "foo", "2.5"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        send_command(&mut client, &["INCRBYFLOAT", "foo", "0.5"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\n3\r\n");
+
+        send_command(&mut client, &["SET", "foo", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        send_command(&mut client, &["INCRBYFLOAT", "foo", "1"]).await?;
+        let error = "-ERR value is not a valid float\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_reports_ttl_for_missing_persistent_and_volatile_keys() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["TTL", "missing"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, ":-2\r\n");
+
+        send_command(&mut client, &["SET", "persistent", "value"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        send_command(&mut client, &["PTTL", "persistent"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, ":-1\r\n");
+
+        send_command(&mut client, &["SET", "volatile", "value", "EX", "100"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        send_command(&mut client, &["TTL", "volatile"]).await?;
+        assert_eq!(read_reply(&mut client, 6).await?, ":100\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_expires_then_persists_a_key() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["EXPIRE", "foo", "10"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        send_command(&mut client, &["SET", "foo", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        send_command(&mut client, &["EXPIRE", "foo", "10"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+        send_command(&mut client, &["TTL", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, ":10\r\n");
+
+        send_command(&mut client, &["PERSIST", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+        send_command(&mut client, &["TTL", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, ":-1\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_expires_a_key_with_pexpire() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SET", "foo", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        send_command(&mut client, &["PEXPIRE", "foo", "0"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+
+        send_command(&mut client, &["GET", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_expires_a_key_at_a_unix_time() -> Result<()> {
+        let mut client = connect_client().await?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+
+        send_command(&mut client, &["SET", "foo", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        let at = (now.as_secs() + 20).to_string();
+        send_command(&mut client, &["EXPIREAT", "foo", &at]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+        send_command(&mut client, &["TTL", "foo"]).await?;
+        let ttl = read_reply(&mut client, 5).await?;
+        assert!(ttl == ":20\r\n" || ttl == ":19\r\n", "{:?}", ttl);
+
+        let at = (now.as_millis() - 1000).to_string();
+        send_command(&mut client, &["PEXPIREAT", "foo", &at]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+        send_command(&mut client, &["GET", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+        send_command(&mut client, &["PEXPIREAT", "foo", &at]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_answers_the_command_introspection_subcommands() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["COMMAND"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, "*0\r\n");
+        send_command(&mut client, &["COMMAND", "DOCS"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, "*0\r\n");
+        send_command(&mut client, &["COMMAND", "COUNT"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":0\r\n");
+        send_command(&mut client, &["COMMAND", "INFO", "get"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "*1\r\n$-1\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_negotiates_resp3_with_hello() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["HELLO", "3"]).await?;
+        let reply = read_value(&mut client).await?;
+        assert!(matches!(reply, Value::Map { len: 7, .. }));
+
+        let fields = hello_fields(reply);
+        assert_eq!(field(&fields, "proto"), Value::Number(3));
+        assert_eq!(field(&fields, "id"), Value::Number(1));
+        assert_eq!(field(&fields, "role"), Value::bulk(Bytes::from("master")));
+        assert_eq!(
+            field(&fields, "version"),
+            Value::bulk(Bytes::from(env!("CARGO_PKG_VERSION")))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_reports_the_replica_role_in_hello() -> Result<()> {
+        let config = Config {
+            replicaof: Some(("localhost".to_string(), 6379)),
+            ..Config::default()
+        };
+        let server = Server::new(db::databases(), config);
+
+        for (version, proto) in [("2", 2), ("3", 3)] {
+            let mut client = connect_session(Session::new(server.clone())).await?;
+            send_command(&mut client, &["HELLO", version]).await?;
+
+            let fields = hello_fields(read_value(&mut client).await?);
+            assert_eq!(field(&fields, "proto"), Value::Number(proto));
+            assert_eq!(field(&fields, "role"), Value::bulk(Bytes::from("slave")));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_names_a_client() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["CLIENT", "GETNAME"]).await?;
+        assert_eq!(read_reply(&mut client, 6).await?, "$0\r\n\r\n");
+
+        send_command(&mut client, &["CLIENT", "SETNAME", "worker"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["CLIENT", "GETNAME"]).await?;
+        assert_eq!(read_reply(&mut client, 12).await?, "$6\r\nworker\r\n");
+
+        send_command(&mut client, &["CLIENT", "SETNAME", "two words"]).await?;
+        let error = "-ERR Client names cannot contain spaces, newlines or special characters.\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_gives_each_client_its_own_id() -> Result<()> {
+        let server = Server::new(db::databases(), Config::default());
+        let mut first = connect_session(Session::new(server.clone())).await?;
+        let mut second = connect_session(Session::new(server)).await?;
+
+        send_command(&mut first, &["CLIENT", "ID"]).await?;
+        assert_eq!(read_reply(&mut first, 4).await?, ":1\r\n");
+
+        send_command(&mut second, &["CLIENT", "ID"]).await?;
+        assert_eq!(read_reply(&mut second, 4).await?, ":2\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_lists_the_connected_clients() -> Result<()> {
+        let server = Server::new(db::databases(), Config::default());
+        let mut first = connect_session(Session::new(server.clone())).await?;
+        let second = connect_session(Session::new(server)).await?;
+
+        send_command(&mut first, &["CLIENT", "SETNAME", "first"]).await?;
+        assert_eq!(read_reply(&mut first, 5).await?, "+OK\r\n");
+
+        send_command(&mut first, &["CLIENT", "LIST"]).await?;
+        let len = read_bulk_len(&mut first).await?;
+        let list = read_reply(&mut first, len + 2).await?;
+        let lines = list.trim_end().lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("id=1 addr=127.0.0.1:"));
+        assert!(lines[0].ends_with(" name=first age=0"));
+        assert!(lines[1].starts_with("id=2 addr=127.0.0.1:"));
+
+        drop(second);
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+
+        send_command(&mut first, &["CLIENT", "LIST"]).await?;
+        let len = read_bulk_len(&mut first).await?;
+        let list = read_reply(&mut first, len + 2).await?;
+        assert_eq!(list.trim_end().lines().count(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_closes_the_connection_on_quit() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["QUIT"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await?;
+        assert!(rest.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_stays_on_resp2_with_hello_2() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["HELLO", "2"]).await?;
+        let reply = read_value(&mut client).await?;
+        assert!(matches!(reply, Value::Array { len: 14, .. }));
+
+        let fields = hello_fields(reply);
+        assert_eq!(field(&fields, "proto"), Value::Number(2));
+        assert_eq!(field(&fields, "id"), Value::Number(1));
+        assert_eq!(field(&fields, "role"), Value::bulk(Bytes::from("master")));
+        assert_eq!(
+            field(&fields, "version"),
+            Value::bulk(Bytes::from(env!("CARGO_PKG_VERSION")))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_rejects_an_unsupported_protocol_version() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["HELLO", "4"]).await?;
+        let error = "-NOPROTO unsupported protocol version\r\n";
+        assert_eq!(read_reply(&mut client, error.len()).await?, error);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_replies_nil_when_getting_a_missing_key() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["GET", "missing"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_delivers_published_messages_to_subscribers() -> Result<()> {
+        let server = Server::new(db::databases(), Config::default());
+        let mut subscriber = connect_session(Session::new(server.clone())).await?;
+        let mut publisher = connect_session(Session::new(server)).await?;
+
+        send_command(&mut subscriber, &["SUBSCRIBE", "news"]).await?;
+        let confirmation = "*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n";
+        assert_eq!(
+            read_reply(&mut subscriber, confirmation.len()).await?,
+            confirmation
+        );
+
+        let published = tokio::spawn(async move {
+            send_command(&mut publisher, &["PUBLISH", "news", "hello"]).await?;
+            read_reply(&mut publisher, 4).await
+        });
+        let message = "*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n";
+        assert_eq!(read_reply(&mut subscriber, message.len()).await?, message);
+        assert_eq!(published.await??, ":1\r\n");
+
+        send_command(&mut subscriber, &["GET", "key"]).await?;
+        let error = "-ERR Can't execute 'get': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n";
+        assert_eq!(read_reply(&mut subscriber, error.len()).await?, error);
+
+        send_command(&mut subscriber, &["UNSUBSCRIBE"]).await?;
+        let confirmation = "*3\r\n$11\r\nunsubscribe\r\n$4\r\nnews\r\n:0\r\n";
+        assert_eq!(
+            read_reply(&mut subscriber, confirmation.len()).await?,
+            confirmation
+        );
+
+        send_command(&mut subscriber, &["GET", "key"]).await?;
+        assert_eq!(read_reply(&mut subscriber, 5).await?, "$-1\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_confirms_each_channel_with_a_running_count() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        send_command(&mut client, &["SUBSCRIBE", "a", "b", "c"]).await?;
+        let confirmations = [
+            "*3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:1\r\n",
+            "*3\r\n$9\r\nsubscribe\r\n$1\r\nb\r\n:2\r\n",
+            "*3\r\n$9\r\nsubscribe\r\n$1\r\nc\r\n:3\r\n",
+        ];
+        for confirmation in &confirmations {
+            assert_eq!(
+                read_reply(&mut client, confirmation.len()).await?,
+                *confirmation
+            );
+        }
+
+        send_command(&mut client, &["UNSUBSCRIBE"]).await?;
+        let confirmations = [
+            "*3\r\n$11\r\nunsubscribe\r\n$1\r\na\r\n:2\r\n",
+            "*3\r\n$11\r\nunsubscribe\r\n$1\r\nb\r\n:1\r\n",
+            "*3\r\n$11\r\nunsubscribe\r\n$1\r\nc\r\n:0\r\n",
+        ];
+        for confirmation in &confirmations {
+            assert_eq!(
+                read_reply(&mut client, confirmation.len()).await?,
+                *confirmation
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_delivers_messages_to_pattern_subscribers() -> Result<()> {
+        let server = Server::new(db::databases(), Config::default());
+        let mut subscriber = connect_session(Session::new(server.clone())).await?;
+        let mut publisher = connect_session(Session::new(server)).await?;
+
+        send_command(&mut subscriber, &["PSUBSCRIBE", "news.*"]).await?;
+        let confirmation = "*3\r\n$10\r\npsubscribe\r\n$6\r\nnews.*\r\n:1\r\n";
+        assert_eq!(
+            read_reply(&mut subscriber, confirmation.len()).await?,
+            confirmation
+        );
+
+        send_command(&mut publisher, &["PUBLISH", "news.tech", "hello"]).await?;
+        assert_eq!(read_reply(&mut publisher, 4).await?, ":1\r\n");
+
+        let message = "*4\r\n$8\r\npmessage\r\n$6\r\nnews.*\r\n$9\r\nnews.tech\r\n$5\r\nhello\r\n";
+        assert_eq!(read_reply(&mut subscriber, message.len()).await?, message);
+
+        send_command(&mut publisher, &["PUBLISH", "sport.tennis", "hello"]).await?;
+        assert_eq!(read_reply(&mut publisher, 4).await?, ":0\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_aborts_a_transaction_when_another_client_changes_a_watched_key() -> Result<()> {
+        let dbs = db::databases();
+        let mut client = connect_client_to(dbs.clone()).await?;
+        let mut other = connect_client_to(dbs).await?;
+
+        send_command(&mut client, &["WATCH", "key"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        send_command(&mut client, &["MULTI"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        send_command(&mut client, &["SET", "key", "mine"]).await?;
+        assert_eq!(read_reply(&mut client, 9).await?, "+QUEUED\r\n");
+
+        send_command(&mut other, &["SET", "key", "theirs"]).await?;
+        assert_eq!(read_reply(&mut other, 5).await?, "+OK\r\n");
+
+        send_command(&mut client, &["EXEC"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "*-1\r\n");
+        send_command(&mut client, &["GET", "key"]).await?;
+        assert_eq!(read_reply(&mut client, 12).await?, "$6\r\ntheirs\r\n");
+
+        Ok(())
+    }
+
+    /// Asks for the replication stream with PSYNC, returning the RDB payload
+    /// sent ahead of it.
+    async fn start_replication(client: &mut TcpStream) -> Result<Vec<u8>> {
+        send_command(client, &["PSYNC", "?", "-1"]).await?;
+        let resync = read_reply(client, 56).await?;
+        assert!(resync.starts_with("+FULLRESYNC "));
+        assert!(resync.ends_with(" 0\r\n"));
+
+        let len = read_bulk_len(client).await?;
+        let mut payload = vec![0; len];
+        client.read_exact(&mut payload).await?;
+        Ok(payload)
+    }
+
+    #[tokio::test]
+    async fn it_sends_a_full_resync_and_an_rdb_payload_on_psync() -> Result<()> {
+        let mut client = connect_client().await?;
+
+        let payload = start_replication(&mut client).await?;
+
+        assert!(payload.starts_with(b"REDIS"));
+        assert_eq!(payload[payload.len() - 9], 0xff);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_propagates_writes_to_replicas() -> Result<()> {
+        let server = Server::new(db::databases(), Config::default());
+        let mut replica = connect_session(Session::new(server.clone())).await?;
+        let mut client = connect_session(Session::new(server)).await?;
+        start_replication(&mut replica).await?;
+
+        send_command(&mut client, &["GET", "foo"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+        send_command(&mut client, &["SET", "foo", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+
+        let stream =
+            "*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n";
+        assert_eq!(read_reply(&mut replica, stream.len()).await?, stream);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_propagates_expiries_as_unix_times_and_skips_unchanged_writes() -> Result<()> {
+        let server = Server::new(db::databases(), Config::default());
+        let mut replica = connect_session(Session::new(server.clone())).await?;
+        let mut client = connect_session(Session::new(server)).await?;
+        start_replication(&mut replica).await?;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        for (command, reply) in [
+            (&["SET", "a", "1", "NX"][..], "+OK\r\n"),
+            (&["SET", "a", "2", "NX"], "$-1\r\n"),
+            (&["DEL", "missing"], ":0\r\n"),
+            (&["SETEX", "b", "100", "v"], "+OK\r\n"),
+            (&["SET", "a", "3", "px", "100000", "XX"], "+OK\r\n"),
+            (&["EXPIRE", "missing", "10"], ":0\r\n"),
+            (&["EXPIRE", "a", "50"], ":1\r\n"),
+            (&["PEXPIRE", "a", "0"], ":1\r\n"),
+        ] {
+            send_command(&mut client, command).await?;
+            assert_eq!(read_reply(&mut client, reply.len()).await?, reply);
+        }
+
+        let mut stream = Vec::new();
+        for _ in 0..6 {
+            match read_value(&mut replica).await? {
+                Value::Array { elements, .. } => stream.push(
+                    elements
+                        .iter()
+                        .map(|element| element.as_string())
+                        .collect::<std::result::Result<Vec<_>, _>>()?,
+                ),
+                value => panic!("unexpected command: {:?}", value),
+            }
+        }
+        let expires_in = |at: &str, ttl: u128| {
+            let at = at.parse::<u128>().unwrap();
+            assert!(
+                (now + ttl..now + ttl + 1000).contains(&at),
+                "{} from {}",
+                at,
+                now
+            );
+        };
+
+        assert_eq!(stream[0], ["SELECT", "0"]);
+        assert_eq!(stream[1], ["SET", "a", "1", "NX"]);
+        assert_eq!(stream[2][..4], ["SET", "b", "v", "PXAT"]);
+        expires_in(&stream[2][4], 100_000);
+        assert_eq!(stream[3][..4], ["SET", "a", "3", "PXAT"]);
+        expires_in(&stream[3][4], 100_000);
+        assert_eq!(stream[3][5..], ["XX"]);
+        assert_eq!(stream[4][..2], ["PEXPIREAT", "a"]);
+        expires_in(&stream[4][2], 50_000);
+        assert_eq!(stream[5], ["DEL", "a"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_propagates_spop_as_the_removal_of_the_popped_members() -> Result<()> {
+        let server = Server::new(db::databases(), Config::default());
+        let mut replica = connect_session(Session::new(server.clone())).await?;
+        let mut client = connect_session(Session::new(server)).await?;
+        start_replication(&mut replica).await?;
+
+        send_command(&mut client, &["SPOP", "set"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "$-1\r\n");
+        send_command(&mut client, &["SADD", "set", "a"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+        send_command(&mut client, &["SPOP", "set"]).await?;
+        assert_eq!(read_reply(&mut client, 7).await?, "$1\r\na\r\n");
+
+        let stream = "*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n*3\r\n$4\r\nSADD\r\n$3\r\nset\r\n$1\r\na\r\n*3\r\n$4\r\nSREM\r\n$3\r\nset\r\n$1\r\na\r\n";
+        assert_eq!(read_reply(&mut replica, stream.len()).await?, stream);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_propagates_blocking_pops_as_pops_of_the_served_list() -> Result<()> {
+        let server = Server::new(db::databases(), Config::default());
+        let mut replica = connect_session(Session::new(server.clone())).await?;
+        let mut blocked = connect_session(Session::new(server.clone())).await?;
+        let mut client = connect_session(Session::new(server)).await?;
+        start_replication(&mut replica).await?;
+
+        send_command(&mut client, &["RPUSH", "b", "x"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+        send_command(&mut blocked, &["BRPOP", "a", "b", "0"]).await?;
+        let reply = "*2\r\n$1\r\nb\r\n$1\r\nx\r\n";
+        assert_eq!(read_reply(&mut blocked, reply.len()).await?, reply);
+
+        send_command(&mut blocked, &["BLPOP", "a", "b", "0"]).await?;
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+        send_command(&mut client, &["LPUSH", "a", "y"]).await?;
+        assert_eq!(read_reply(&mut client, 4).await?, ":1\r\n");
+        let reply = "*2\r\n$1\r\na\r\n$1\r\ny\r\n";
+        assert_eq!(read_reply(&mut blocked, reply.len()).await?, reply);
+
+        let stream = [
+            "*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n",
+            "*3\r\n$5\r\nRPUSH\r\n$1\r\nb\r\n$1\r\nx\r\n",
+            "*2\r\n$4\r\nRPOP\r\n$1\r\nb\r\n",
+            "*3\r\n$5\r\nLPUSH\r\n$1\r\na\r\n$1\r\ny\r\n",
+            "*2\r\n$4\r\nLPOP\r\n$1\r\na\r\n",
+        ]
+        .concat();
+        assert_eq!(read_reply(&mut replica, stream.len()).await?, stream);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_stops_accepting_and_saves_on_shutdown() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("redis-shutdown-{}", process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let config = Config {
+            dir: dir.to_string_lossy().into_owned(),
+            save_on_shutdown: true,
+            ..Config::default()
+        };
+        let server = Server::new(db::databases(), config.clone());
+
+        let std_listener = net::TcpListener::bind("127.0.0.1:0")?;
+        let addr = std_listener.local_addr()?;
+        let listener = TcpListener::from_std(std_listener)?;
+        let (stop, stopped) = oneshot::channel::<()>();
+        let registry = Arc::new(Registry::new());
+        let serving = tokio::spawn(serve(listener, server, registry, async {
+            let _ = stopped.await;
+        }));
+
+        let mut client = TcpStream::from_std(net::TcpStream::connect(addr)?)?;
+        send_command(&mut client, &["SET", "foo", "bar"]).await?;
+        assert_eq!(read_reply(&mut client, 5).await?, "+OK\r\n");
+        drop(client);
+
+        let _ = stop.send(());
+        serving.await??;
+        assert!(net::TcpStream::connect(addr).is_err());
+
+        let dbs = db::databases();
+        rdb::load(&config.rdb_path(), &dbs)?;
+        assert_eq!(dbs[0].get("foo")?, Some(Bytes::from("bar")));
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_closes_idle_connections_and_finishes_running_commands_on_shutdown() -> Result<()> {
+        let server = Server::new(db::databases(), Config::default());
+
+        let std_listener = net::TcpListener::bind("127.0.0.1:0")?;
+        let addr = std_listener.local_addr()?;
+        let listener = TcpListener::from_std(std_listener)?;
+        let (stop, stopped) = oneshot::channel::<()>();
+        let registry = Arc::new(Registry::new());
+        let serving = tokio::spawn(serve(listener, server, registry, async {
+            let _ = stopped.await;
+        }));
+
+        let mut idle = TcpStream::from_std(net::TcpStream::connect(addr)?)?;
+        send_command(&mut idle, &["PING"]).await?;
+        assert_eq!(read_reply(&mut idle, 7).await?, "+PONG\r\n");
+        let mut busy = TcpStream::from_std(net::TcpStream::connect(addr)?)?;
+        send_command(&mut busy, &["BLPOP", "list", "0.2"]).await?;
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+
+        let started = std::time::Instant::now();
+        let _ = stop.send(());
+
+        let mut rest = String::new();
+        idle.read_to_string(&mut rest).await?;
+        assert_eq!(rest, "");
+        assert!(started.elapsed() < Duration::from_millis(150));
+
+        busy.read_to_string(&mut rest).await?;
+        assert_eq!(rest, "*-1\r\n");
+
+        serving.await??;
+        assert!(started.elapsed() < server::SHUTDOWN_DRAIN_PERIOD);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_turns_away_clients_past_maxclients() -> Result<()> {
+        let config = Config {
+            maxclients: 1,
+            ..Config::default()
+        };
+        let server = Server::new(db::databases(), config);
+
+        let std_listener = net::TcpListener::bind("127.0.0.1:0")?;
+        let addr = std_listener.local_addr()?;
+        let listener = TcpListener::from_std(std_listener)?;
+        let (stop, stopped) = oneshot::channel::<()>();
+        let registry = Arc::new(Registry::new());
+        tokio::spawn(serve(listener, server, registry, async {
+            let _ = stopped.await;
+        }));
+
+        let mut first = TcpStream::from_std(net::TcpStream::connect(addr)?)?;
+        send_command(&mut first, &["PING"]).await?;
+        assert_eq!(read_reply(&mut first, 7).await?, "+PONG\r\n");
+
+        let mut second = TcpStream::from_std(net::TcpStream::connect(addr)?)?;
+        let mut reply = String::new();
+        second.read_to_string(&mut reply).await?;
+        assert_eq!(reply, "-ERR max number of clients reached\r\n");
+
+        // The permit is given back once the first client leaves.
+        drop(first);
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+        let mut third = TcpStream::from_std(net::TcpStream::connect(addr)?)?;
+        send_command(&mut third, &["PING"]).await?;
+        assert_eq!(read_reply(&mut third, 7).await?, "+PONG\r\n");
+
+        let _ = stop.send(());
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use tokio::sync::broadcast;
+
+use crate::glob;
+
+/// How many messages a subscriber can fall behind before it starts missing
+/// some.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// A message sent to a pattern subscriber, along with the channel it was
+/// published to.
+pub type PatternMessage = (String, Bytes);
+
+#[derive(Default)]
+struct Subscriptions {
+    channels: HashMap<String, broadcast::Sender<Bytes>>,
+    patterns: HashMap<String, broadcast::Sender<PatternMessage>>,
+}
+
+/// The channels and patterns clients subscribed to, shared by every
+/// connection.
+#[derive(Clone, Default)]
+pub struct PubSub {
+    subscriptions: Arc<Mutex<Subscriptions>>,
+}
+
+impl PubSub {
+    /// Returns a receiver for the messages published to `channel` from now
+    /// on.
+    pub fn subscribe(&self, channel: &str) -> broadcast::Receiver<Bytes> {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        receiver(&mut subscriptions.channels, channel)
+    }
+
+    /// Returns a receiver for the messages published from now on to the
+    /// channels matching the glob `pattern`.
+    pub fn psubscribe(&self, pattern: &str) -> broadcast::Receiver<PatternMessage> {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        receiver(&mut subscriptions.patterns, pattern)
+    }
+
+    /// Sends `message` to the subscribers of `channel` and of the patterns
+    /// matching it, returning how many of them received it.
+    pub fn publish(&self, channel: &str, message: Bytes) -> usize {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+
+        let mut received = subscriptions
+            .channels
+            .get(channel)
+            .and_then(|sender| sender.send(message.clone()).ok())
+            .unwrap_or(0);
+        if received == 0 {
+            // Every subscriber left, so the channel can go.
+            subscriptions.channels.remove(channel);
+        }
+
+        subscriptions.patterns.retain(|pattern, sender| {
+            if !glob::matches(pattern.as_bytes(), channel.as_bytes()) {
+                return true;
+            }
+            match sender.send((channel.to_string(), message.clone())) {
+                Ok(count) => {
+                    received += count;
+                    true
+                }
+                Err(_) => false,
+            }
+        });
+
+        received
+    }
+}
+
+/// Subscribes to the sender kept under `name`, creating it on first use.
+fn receiver<T: Clone>(
+    senders: &mut HashMap<String, broadcast::Sender<T>>,
+    name: &str,
+) -> broadcast::Receiver<T> {
+    match senders.get(name) {
+        Some(sender) => sender.subscribe(),
+        None => {
+            let (sender, receiver) = broadcast::channel(CHANNEL_CAPACITY);
+            senders.insert(name.to_string(), sender);
+            receiver
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::PubSub;
+
+    #[tokio::test]
+    async fn it_delivers_messages_to_every_subscriber() {
+        let pubsub = PubSub::default();
+        let mut first = pubsub.subscribe("news");
+        let mut second = pubsub.subscribe("news");
+
+        assert_eq!(pubsub.publish("news", Bytes::from("hello")), 2);
+        assert_eq!(first.recv().await.unwrap(), Bytes::from("hello"));
+        assert_eq!(second.recv().await.unwrap(), Bytes::from("hello"));
+    }
+
+    #[test]
+    fn it_forgets_channels_without_subscribers() {
+        let pubsub = PubSub::default();
+        assert_eq!(pubsub.publish("news", Bytes::from("hello")), 0);
+
+        drop(pubsub.subscribe("news"));
+
+        assert_eq!(pubsub.publish("news", Bytes::from("hello")), 0);
+        assert!(pubsub.subscriptions.lock().unwrap().channels.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_delivers_messages_to_matching_patterns() {
+        let pubsub = PubSub::default();
+        let mut exact = pubsub.subscribe("news.tech");
+        let mut pattern = pubsub.psubscribe("news.*");
+        let _other = pubsub.psubscribe("sport.*");
+
+        assert_eq!(pubsub.publish("news.tech", Bytes::from("hello")), 2);
+        assert_eq!(exact.recv().await.unwrap(), Bytes::from("hello"));
+        assert_eq!(
+            pattern.recv().await.unwrap(),
+            ("news.tech".to_string(), Bytes::from("hello"))
+        );
+        assert_eq!(pubsub.publish("news.sport", Bytes::from("hi")), 1);
+    }
+}
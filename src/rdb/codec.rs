@@ -0,0 +1,241 @@
+use anyhow::{bail, format_err, Result};
+use bytes::Bytes;
+
+/// A length prefix, which may instead announce a specially encoded string.
+enum Length {
+    Plain(u64),
+    Encoded(u8),
+}
+
+const ENCODING_INT8: u8 = 0;
+const ENCODING_INT16: u8 = 1;
+const ENCODING_INT32: u8 = 2;
+const ENCODING_LZF: u8 = 3;
+
+/// Consumes the next `len` bytes of `data`.
+pub fn read_bytes<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if data.len() < len {
+        bail!("unexpected end of RDB file");
+    }
+
+    let (taken, rest) = data.split_at(len);
+    *data = rest;
+    Ok(taken)
+}
+
+pub fn read_array<const N: usize>(data: &mut &[u8]) -> Result<[u8; N]> {
+    Ok(read_bytes(data, N)?.try_into()?)
+}
+
+pub fn read_u8(data: &mut &[u8]) -> Result<u8> {
+    Ok(read_bytes(data, 1)?[0])
+}
+
+/// Reads a length, whose two most significant bits tell how it is encoded:
+/// in the remaining 6 bits, in 14 bits spanning the next byte, or in the 32
+/// or 64 bits that follow.
+fn read_length_or_encoding(data: &mut &[u8]) -> Result<Length> {
+    let first = read_u8(data)?;
+    match first >> 6 {
+        0b00 => Ok(Length::Plain((first & 0x3F) as u64)),
+        0b01 => {
+            let second = read_u8(data)?;
+            Ok(Length::Plain(((first & 0x3F) as u64) << 8 | second as u64))
+        }
+        0b10 => match first {
+            0x80 => Ok(Length::Plain(u32::from_be_bytes(read_array(data)?) as u64)),
+            0x81 => Ok(Length::Plain(u64::from_be_bytes(read_array(data)?))),
+            _ => bail!("invalid length encoding {:#x}", first),
+        },
+        _ => Ok(Length::Encoded(first & 0x3F)),
+    }
+}
+
+pub fn read_length(data: &mut &[u8]) -> Result<usize> {
+    match read_length_or_encoding(data)? {
+        Length::Plain(len) => {
+            usize::try_from(len).map_err(|_| format_err!("length {} is too large", len))
+        }
+        Length::Encoded(_) => bail!("expected a length, found a string encoding"),
+    }
+}
+
+/// Reads a string, which is either prefixed by its length or stored as an
+/// integer.
+pub fn read_string(data: &mut &[u8]) -> Result<Bytes> {
+    let integer = match read_length_or_encoding(data)? {
+        Length::Plain(len) => {
+            let len = usize::try_from(len)?;
+            return Ok(Bytes::copy_from_slice(read_bytes(data, len)?));
+        }
+        Length::Encoded(ENCODING_INT8) => i8::from_le_bytes(read_array(data)?) as i32,
+        Length::Encoded(ENCODING_INT16) => i16::from_le_bytes(read_array(data)?) as i32,
+        Length::Encoded(ENCODING_INT32) => i32::from_le_bytes(read_array(data)?),
+        Length::Encoded(ENCODING_LZF) => bail!("compressed strings are not supported"),
+        Length::Encoded(encoding) => bail!("unknown string encoding {}", encoding),
+    };
+
+    Ok(Bytes::from(integer.to_string()))
+}
+
+/// Writes a length using the smallest of its encodings.
+pub fn write_length(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.push(0x40 | (len >> 8) as u8);
+        out.push(len as u8);
+    } else if len <= u32::MAX as u64 {
+        out.push(0x80);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+/// Writes a string, as an integer when it is the exact representation of
+/// one that fits in 32 bits.
+pub fn write_string(out: &mut Vec<u8>, data: &[u8]) {
+    if let Some(integer) = as_integer(data) {
+        if let Ok(integer) = i8::try_from(integer) {
+            out.push(0xC0 | ENCODING_INT8);
+            out.extend_from_slice(&integer.to_le_bytes());
+        } else if let Ok(integer) = i16::try_from(integer) {
+            out.push(0xC0 | ENCODING_INT16);
+            out.extend_from_slice(&integer.to_le_bytes());
+        } else {
+            out.push(0xC0 | ENCODING_INT32);
+            out.extend_from_slice(&integer.to_le_bytes());
+        }
+        return;
+    }
+
+    write_length(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+/// Parses `data` as an integer, as long as formatting it back gives the
+/// same bytes, so that "007" or "+1" are kept as strings.
+fn as_integer(data: &[u8]) -> Option<i32> {
+    let integer: i32 = std::str::from_utf8(data).ok()?.parse().ok()?;
+    if integer.to_string().as_bytes() == data {
+        Some(integer)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_length, read_string, write_length, write_string};
+
+    use bytes::Bytes;
+
+    fn encode_length(len: u64) -> Vec<u8> {
+        let mut out = vec![];
+        write_length(&mut out, len);
+        out
+    }
+
+    fn encode_string(data: &str) -> Vec<u8> {
+        let mut out = vec![];
+        write_string(&mut out, data.as_bytes());
+        out
+    }
+
+    #[test]
+    fn it_encodes_lengths_at_each_boundary() {
+        assert_eq!(encode_length(0), vec![0x00]);
+        assert_eq!(encode_length(63), vec![0x3F]);
+        assert_eq!(encode_length(64), vec![0x40, 0x40]);
+        assert_eq!(encode_length(16383), vec![0x7F, 0xFF]);
+        assert_eq!(encode_length(16384), vec![0x80, 0x00, 0x00, 0x40, 0x00]);
+        assert_eq!(
+            encode_length(u32::MAX as u64),
+            vec![0x80, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            encode_length(u32::MAX as u64 + 1),
+            vec![0x81, 0, 0, 0, 1, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn it_reads_back_lengths_at_each_boundary() {
+        for len in [
+            0,
+            63,
+            64,
+            16383,
+            16384,
+            u32::MAX as u64,
+            u32::MAX as u64 + 1,
+        ] {
+            let encoded = encode_length(len);
+            let mut data = &encoded[..];
+
+            assert_eq!(read_length(&mut data).unwrap() as u64, len);
+            assert!(data.is_empty());
+        }
+    }
+
+    #[test]
+    fn it_encodes_integer_strings_in_the_smallest_form() {
+        assert_eq!(encode_string("0"), vec![0xC0, 0x00]);
+        assert_eq!(encode_string("-128"), vec![0xC0, 0x80]);
+        assert_eq!(encode_string("127"), vec![0xC0, 0x7F]);
+        assert_eq!(encode_string("128"), vec![0xC1, 0x80, 0x00]);
+        assert_eq!(encode_string("-32768"), vec![0xC1, 0x00, 0x80]);
+        assert_eq!(encode_string("32768"), vec![0xC2, 0x00, 0x80, 0x00, 0x00]);
+        assert_eq!(
+            encode_string("2147483647"),
+            vec![0xC2, 0xFF, 0xFF, 0xFF, 0x7F]
+        );
+    }
+
+    #[test]
+    fn it_keeps_strings_that_only_look_like_integers() {
+        assert_eq!(encode_string("2147483648"), b"\x0A2147483648".to_vec());
+        assert_eq!(encode_string("007"), b"\x03007".to_vec());
+        assert_eq!(encode_string("+1"), b"\x02+1".to_vec());
+        assert_eq!(encode_string("-0"), b"\x02-0".to_vec());
+        assert_eq!(encode_string(""), vec![0x00]);
+    }
+
+    #[test]
+    fn it_reads_back_every_string_encoding() {
+        let samples = [
+            "",
+            "foo",
+            "0",
+            "-128",
+            "127",
+            "128",
+            "-32768",
+            "32768",
+            "2147483647",
+            "2147483648",
+            "007",
+        ];
+        for sample in samples {
+            let encoded = encode_string(sample);
+            let mut data = &encoded[..];
+
+            assert_eq!(read_string(&mut data).unwrap(), Bytes::from(sample));
+            assert!(data.is_empty());
+        }
+
+        let long = "x".repeat(16384);
+        let encoded = encode_string(&long);
+        assert_eq!(read_string(&mut &encoded[..]).unwrap(), Bytes::from(long));
+    }
+
+    #[test]
+    fn it_rejects_truncated_and_unsupported_strings() {
+        assert!(read_string(&mut &b"\x05abc"[..]).is_err());
+        assert!(read_string(&mut &b"\xC1\x01"[..]).is_err());
+        assert!(read_string(&mut &b"\xC3\x01\x01"[..]).is_err());
+        assert!(read_length(&mut &b"\xC0\x01"[..]).is_err());
+    }
+}
@@ -0,0 +1,352 @@
+mod codec;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+
+use crate::db::{instant_from_unix, unix_from_instant, Db, SetCondition, StoredValue};
+use codec::{
+    read_array, read_bytes, read_length, read_string, read_u8, write_length, write_string,
+};
+
+/// Every RDB file starts with this, followed by a four digit version.
+const MAGIC: &[u8] = b"REDIS";
+
+/// Version written in the header of saved files.
+const VERSION: &[u8] = b"0011";
+
+/// The reflected form of the polynomial used by Redis for its CRC-64
+/// checksums (Jones).
+const CRC64_POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+
+/// Loads the RDB file at `path` into `dbs`. A missing file leaves them
+/// untouched.
+pub fn load(path: &Path, dbs: &[Db]) -> Result<()> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    parse(&data, dbs)
+}
+
+/// Saves the string keys of `dbs` to an RDB file at `path`. The file is
+/// written under a temporary name first, so an existing one is only
+/// replaced once the new one is complete.
+pub fn save(path: &Path, dbs: &[Db]) -> Result<()> {
+    let temp_path = path.with_file_name(format!("temp-{}.rdb", process::id()));
+    fs::write(&temp_path, serialize(dbs))?;
+    fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
+
+/// Encodes the keys of `dbs` in the RDB format.
+pub fn serialize(dbs: &[Db]) -> Vec<u8> {
+    let mut out = vec![];
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(VERSION);
+
+    for (index, db) in dbs.iter().enumerate() {
+        // Only strings can be read back, so other types are left out.
+        let entries: Vec<_> = db
+            .snapshot()
+            .into_iter()
+            .filter_map(|(key, value, expires_at)| match value {
+                StoredValue::String(value) => Some((key, value, expires_at)),
+                _ => None,
+            })
+            .collect();
+        if entries.is_empty() {
+            continue;
+        }
+
+        out.push(OPCODE_SELECTDB);
+        write_length(&mut out, index as u64);
+        out.push(OPCODE_RESIZEDB);
+        write_length(&mut out, entries.len() as u64);
+        let expiring = entries.iter().filter(|entry| entry.2.is_some()).count();
+        write_length(&mut out, expiring as u64);
+
+        for (key, value, expires_at) in entries {
+            if let Some(expires_at) = expires_at {
+                out.push(OPCODE_EXPIRETIME_MS);
+                let millis = unix_from_instant(expires_at).as_millis() as u64;
+                out.extend_from_slice(&millis.to_le_bytes());
+            }
+            out.push(TYPE_STRING);
+            write_string(&mut out, key.as_bytes());
+            write_string(&mut out, &value);
+        }
+    }
+
+    out.push(OPCODE_EOF);
+    let checksum = crc64(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+fn unix_now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Computes the checksum found at the end of RDB files.
+fn crc64(data: &[u8]) -> u64 {
+    let mut crc = 0;
+    for &byte in data {
+        crc ^= byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC64_POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    crc
+}
+
+/// Reads the keys stored in the content of an RDB file into `dbs`. Only
+/// string values are supported.
+pub fn parse(data: &[u8], dbs: &[Db]) -> Result<()> {
+    let mut data = data;
+    if read_bytes(&mut data, MAGIC.len())? != MAGIC {
+        bail!("not an RDB file");
+    }
+    let version = read_bytes(&mut data, 4)?;
+    if !version.iter().all(u8::is_ascii_digit) {
+        bail!("invalid RDB version");
+    }
+
+    let mut db_index = 0;
+    let mut expires_at = None;
+    loop {
+        match read_u8(&mut data)? {
+            // The checksum that follows is not verified.
+            OPCODE_EOF => return Ok(()),
+            OPCODE_AUX => {
+                read_string(&mut data)?;
+                read_string(&mut data)?;
+            }
+            OPCODE_SELECTDB => {
+                db_index = read_length(&mut data)?;
+                if db_index >= dbs.len() {
+                    bail!("database {} is out of range", db_index);
+                }
+            }
+            OPCODE_RESIZEDB => {
+                read_length(&mut data)?;
+                read_length(&mut data)?;
+            }
+            OPCODE_EXPIRETIME => {
+                let seconds = u32::from_le_bytes(read_array(&mut data)?);
+                expires_at = Some(Duration::from_secs(seconds as u64));
+            }
+            OPCODE_EXPIRETIME_MS => {
+                let millis = u64::from_le_bytes(read_array(&mut data)?);
+                expires_at = Some(Duration::from_millis(millis));
+            }
+            TYPE_STRING => {
+                let key = String::from_utf8(read_string(&mut data)?.to_vec())?;
+                let value = read_string(&mut data)?;
+                let expires_at = match expires_at.take() {
+                    // Keys that expired while the server was down are
+                    // dropped rather than loaded.
+                    Some(timestamp) if timestamp <= unix_now() => continue,
+                    Some(timestamp) => instant_from_unix(timestamp),
+                    None => None,
+                };
+                dbs[db_index].set_if(key, value, expires_at, SetCondition::Always);
+            }
+            value_type => bail!("unsupported value type {}", value_type),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc64, load, parse, save, serialize};
+    use crate::db::{self, Ttl};
+
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use std::time::{Duration, Instant};
+
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    /// A file with an auxiliary field and four keys in database 0: `foo` and
+    /// `num`, the latter stored as an integer, `later` expiring in 2100 in
+    /// milliseconds and `soon` expiring in 2100 in seconds.
+    const FIXTURE: &[u8] = b"REDIS0011\
+        \xFA\x09redis-ver\x057.2.0\
+        \xFE\x00\xFB\x04\x02\
+        \x00\x03foo\x03bar\
+        \x00\x03num\xC0\x2A\
+        \xFC\x00\xD8\xC3\x2C\xBB\x03\x00\x00\x00\x05later\x05value\
+        \xFD\x00\x57\x86\xF4\x00\x04soon\x05value\
+        \xFF\x00\x00\x00\x00\x00\x00\x00\x00";
+
+    /// Keys spread over databases 0 and 1, where `stale` expired in 2000 and
+    /// `fresh` expires in 2100.
+    const MULTI_KEY_FIXTURE: &[u8] = b"REDIS0011\
+        \xFE\x00\xFB\x04\x02\
+        \x00\x05apple\x01a\
+        \x00\x06banana\x01b\
+        \xFC\x00\xAC\xCF\x6A\xDC\x00\x00\x00\x00\x05stale\x01s\
+        \xFC\x00\xD8\xC3\x2C\xBB\x03\x00\x00\x00\x05fresh\x01f\
+        \xFE\x01\xFB\x01\x00\
+        \x00\x05other\x01o\
+        \xFF\x00\x00\x00\x00\x00\x00\x00\x00";
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("redis-rdb-{}-{}", process::id(), name))
+    }
+
+    #[test]
+    fn it_loads_string_keys_from_a_file() -> Result<()> {
+        let path = temp_path("strings.rdb");
+        fs::write(&path, FIXTURE)?;
+        let dbs = db::databases();
+
+        load(&path, &dbs)?;
+        fs::remove_file(&path)?;
+
+        assert_eq!(dbs[0].get("foo")?, Some(Bytes::from("bar")));
+        assert_eq!(dbs[0].get("num")?, Some(Bytes::from("42")));
+        assert_eq!(dbs[0].len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_loads_expiry_times() -> Result<()> {
+        let dbs = db::databases();
+
+        parse(FIXTURE, &dbs)?;
+
+        assert_eq!(dbs[0].ttl("foo"), Ttl::Persistent);
+        assert!(matches!(dbs[0].ttl("later"), Ttl::Remaining(_)));
+        assert!(matches!(dbs[0].ttl("soon"), Ttl::Remaining(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_loads_keys_into_the_selected_database() -> Result<()> {
+        let dbs = db::databases();
+
+        parse(b"REDIS0011\xFE\x02\x00\x01k\x01v\xFF", &dbs)?;
+
+        assert_eq!(dbs[0].len(), 0);
+        assert_eq!(dbs[2].get("k")?, Some(Bytes::from("v")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_saves_keys_that_load_back() -> Result<()> {
+        let dbs = db::databases();
+        dbs[0].set_many(vec![
+            ("foo".to_string(), Bytes::from("bar")),
+            ("empty".to_string(), Bytes::new()),
+            ("large".to_string(), Bytes::from("x".repeat(20_000))),
+        ]);
+        let expires_at = Instant::now() + Duration::from_secs(3600);
+        dbs[0].expire("foo", expires_at);
+        dbs[3].set_many(vec![("other".to_string(), Bytes::from("db"))]);
+        let path = temp_path("saved.rdb");
+
+        save(&path, &dbs)?;
+        let loaded = db::databases();
+        load(&path, &loaded)?;
+        fs::remove_file(&path)?;
+
+        assert_eq!(loaded[0].len(), 3);
+        assert_eq!(loaded[0].get("foo")?, Some(Bytes::from("bar")));
+        assert_eq!(loaded[0].get("empty")?, Some(Bytes::new()));
+        assert_eq!(loaded[0].get("large")?, dbs[0].get("large")?);
+        assert_eq!(loaded[3].get("other")?, Some(Bytes::from("db")));
+        match loaded[0].ttl("foo") {
+            Ttl::Remaining(remaining) => {
+                assert!(remaining > Duration::from_secs(3590));
+                assert!(remaining <= Duration::from_secs(3600));
+            }
+            ttl => panic!("unexpected ttl {:?}", ttl),
+        }
+        assert_eq!(loaded[0].ttl("empty"), Ttl::Persistent);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_ends_saved_files_with_their_checksum() {
+        let dbs = db::databases();
+        dbs[0].set_many(vec![("foo".to_string(), Bytes::from("bar"))]);
+
+        let data = serialize(&dbs);
+        let (content, checksum) = data.split_at(data.len() - 8);
+
+        assert!(content.starts_with(b"REDIS0011"));
+        assert_eq!(content.last(), Some(&0xFF));
+        assert_eq!(checksum, crc64(content).to_le_bytes());
+    }
+
+    #[test]
+    fn it_computes_the_redis_crc64() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+    }
+
+    #[test]
+    fn it_lists_the_loaded_keys_without_the_expired_ones() -> Result<()> {
+        let dbs = db::databases();
+
+        parse(MULTI_KEY_FIXTURE, &dbs)?;
+
+        let mut keys = dbs[0].keys("*");
+        keys.sort();
+        assert_eq!(keys, vec!["apple", "banana", "fresh"]);
+        assert_eq!(dbs[0].snapshot().len(), 3);
+        assert_eq!(dbs[1].keys("*"), vec!["other"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_starts_empty_without_a_file() -> Result<()> {
+        let dbs = db::databases();
+
+        load(&temp_path("missing.rdb"), &dbs)?;
+
+        assert_eq!(dbs[0].len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_invalid_files() {
+        let dbs = db::databases();
+
+        assert!(parse(b"RESP0011\xFF", &dbs).is_err());
+        assert!(parse(b"REDIS0011\x00\x03foo", &dbs).is_err());
+        assert!(parse(b"REDIS0011\x04\x03foo\xFF", &dbs).is_err());
+    }
+}
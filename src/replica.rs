@@ -0,0 +1,243 @@
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Result};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use crate::command::{Registry, Session};
+use crate::connection::Connection;
+use crate::log;
+use crate::rdb;
+use crate::resp::Value;
+use crate::server::Server;
+
+/// Connects to the master at `host:port` and replicates it, starting with
+/// the handshake.
+pub async fn run(server: Server, registry: Arc<Registry>, host: String, port: u16) -> Result<()> {
+    let listening_port = server.config.read().unwrap().port;
+    let mut conn = Connection::new(TcpStream::connect((host.as_str(), port)).await?);
+
+    let (replid, offset) = handshake(&mut conn, listening_port).await?;
+    log::info!("replicating {}:{} from offset {}", host, port, offset);
+    server.replication.write().unwrap().replid = replid;
+
+    replicate(&mut conn, server, &registry, offset).await
+}
+
+/// Loads the dataset the master sends once the handshake is done, then
+/// applies the commands it streams from `offset` on. The offset counts the
+/// bytes of every command processed, requests for acknowledgement included.
+pub async fn replicate<S>(
+    conn: &mut Connection<S>,
+    server: Server,
+    registry: &Registry,
+    mut offset: u64,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let snapshot = conn.read_rdb_payload().await?;
+    rdb::parse(&snapshot, &server.dbs)?;
+
+    let mut session = Session::new(server.clone());
+    loop {
+        let consumed = conn.bytes_consumed();
+        let (command, args) = conn.read_command().await?;
+
+        if is_ack_request(&command, &args) {
+            // The request itself is only counted once acknowledged.
+            let ack = Value::command(&["REPLCONF", "ACK", &offset.to_string()]);
+            conn.write_value(&ack).await?;
+            conn.flush().await?;
+        } else if let Err(err) = registry.execute(&command, &args, &mut session).await {
+            // The master does not expect replies, so errors are only logged.
+            log::warn!("replicating {} failed: {}", command, err);
+        }
+
+        offset += conn.bytes_consumed() - consumed;
+        server.replication.write().unwrap().offset = offset;
+    }
+}
+
+/// Whether the master sent `REPLCONF GETACK *`.
+fn is_ack_request(command: &str, args: &[Value]) -> bool {
+    command == "REPLCONF"
+        && matches!(args.first().map(|arg| arg.as_str()), Some(Ok(option)) if option.eq_ignore_ascii_case("GETACK"))
+}
+
+/// Introduces this server to the master as a replica listening on
+/// `listening_port`, and asks it for a full copy of its dataset. Returns the
+/// replication id and offset the master resynchronizes from.
+pub async fn handshake<S>(conn: &mut Connection<S>, listening_port: u16) -> Result<(String, u64)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    expect(conn, &["PING"], "PONG").await?;
+    let listening_port = listening_port.to_string();
+    expect(conn, &["REPLCONF", "listening-port", &listening_port], "OK").await?;
+    expect(conn, &["REPLCONF", "capa", "psync2"], "OK").await?;
+
+    let reply = request(conn, &["PSYNC", "?", "-1"]).await?;
+    let resync = match &reply {
+        Value::String(line) => line.strip_prefix("FULLRESYNC "),
+        _ => None,
+    };
+    let (replid, offset) = resync
+        .and_then(|resync| resync.split_once(' '))
+        .ok_or_else(|| format_err!("unexpected reply {:?} to PSYNC from the master", reply))?;
+    Ok((replid.to_string(), offset.parse()?))
+}
+
+async fn request<S>(conn: &mut Connection<S>, args: &[&str]) -> Result<Value>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    conn.write_value(&Value::command(args)).await?;
+    conn.flush().await?;
+    conn.read_value().await
+}
+
+/// Sends a command and checks that the master replied with the simple
+/// string `expected`.
+async fn expect<S>(conn: &mut Connection<S>, args: &[&str], expected: &str) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match request(conn, args).await? {
+        Value::String(reply) if reply.eq_ignore_ascii_case(expected) => Ok(()),
+        reply => bail!(
+            "unexpected reply {:?} to {} from the master",
+            reply,
+            args[0]
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{handshake, replicate};
+    use crate::command::Registry;
+    use crate::config::Config;
+    use crate::connection::Connection;
+    use crate::db;
+    use crate::rdb;
+    use crate::resp::Value;
+    use crate::server::Server;
+
+    use anyhow::Result;
+    use bytes::Bytes;
+    use tokio::io::{self, DuplexStream};
+    use tokio::task::JoinHandle;
+
+    /// Plays the master's side of the handshake, answering each command with
+    /// the next of `replies` and collecting the commands it received.
+    fn mock_master(
+        stream: DuplexStream,
+        replies: &'static [&'static str],
+    ) -> JoinHandle<Result<Vec<String>>> {
+        tokio::spawn(async move {
+            let mut master = Connection::new(stream);
+            let mut received = Vec::new();
+            for reply in replies {
+                let (command, args) = master.read_command().await?;
+                let mut words = vec![command];
+                for arg in args {
+                    words.push(arg.as_string()?);
+                }
+                received.push(words.join(" "));
+
+                master.write_all(reply.as_bytes()).await?;
+                master.flush().await?;
+            }
+            Ok(received)
+        })
+    }
+
+    #[tokio::test]
+    async fn it_sends_the_handshake_commands_in_order() -> Result<()> {
+        let (replica, master) = io::duplex(64 * 1024);
+        let master = mock_master(
+            master,
+            &[
+                "+PONG\r\n",
+                "+OK\r\n",
+                "+OK\r\n",
+                "+FULLRESYNC 8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb 0\r\n",
+            ],
+        );
+
+        let (replid, offset) = handshake(&mut Connection::new(replica), 6380).await?;
+
+        assert_eq!(
+            master.await??,
+            vec![
+                "PING",
+                "REPLCONF listening-port 6380",
+                "REPLCONF capa psync2",
+                "PSYNC ? -1"
+            ]
+        );
+        assert_eq!(replid, "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb");
+        assert_eq!(offset, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_stops_at_an_unexpected_reply() -> Result<()> {
+        let (replica, master) = io::duplex(64 * 1024);
+        let master = mock_master(master, &["-ERR not now\r\n"]);
+
+        let error = handshake(&mut Connection::new(replica), 6380)
+            .await
+            .unwrap_err();
+
+        assert_eq!(master.await??, vec!["PING"]);
+        assert!(error.to_string().contains("unexpected reply"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_acknowledges_the_offset_of_the_commands_it_processed() -> Result<()> {
+        let (replica, master) = io::duplex(64 * 1024);
+        let server = Server::new(db::databases(), Config::default());
+        let replicating = server.clone();
+        tokio::spawn(async move {
+            let registry = Registry::new();
+            replicate(&mut Connection::new(replica), replicating, &registry, 0).await
+        });
+
+        let mut master = Connection::new(master);
+        let snapshot = rdb::serialize(&[]);
+        master
+            .write_all(format!("${}\r\n", snapshot.len()).as_bytes())
+            .await?;
+        master.write_all(&snapshot).await?;
+        // 29 bytes, then 37 for each request for acknowledgement.
+        master
+            .write_value(&Value::command(&["SET", "foo", "1"]))
+            .await?;
+        master
+            .write_value(&Value::command(&["REPLCONF", "GETACK", "*"]))
+            .await?;
+        master.flush().await?;
+
+        assert_eq!(
+            master.read_value().await?,
+            Value::command(&["REPLCONF", "ACK", "29"])
+        );
+        assert_eq!(server.dbs[0].get("foo")?, Some(Bytes::from("1")));
+
+        master
+            .write_value(&Value::command(&["REPLCONF", "GETACK", "*"]))
+            .await?;
+        master.flush().await?;
+        assert_eq!(
+            master.read_value().await?,
+            Value::command(&["REPLCONF", "ACK", "66"])
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::connection::Connection;
+use crate::rdb;
+use crate::resp::Value;
+use crate::server::Server;
+
+/// Length of a replication id, in hex digits.
+const REPLID_LEN: usize = 40;
+
+/// How often replicas are asked to acknowledge their offset.
+pub const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The replication state of the server, as reported by INFO.
+pub struct Replication {
+    /// Identifies the history of the dataset, so that replicas can tell
+    /// whether they can resume from it.
+    pub replid: String,
+    /// Bytes of the replication stream produced so far.
+    pub offset: u64,
+    /// The connected replicas.
+    pub replicas: Vec<ReplicaLink>,
+    /// Database the replicas last saw selected, if the stream selected one.
+    selected_db: Option<usize>,
+    /// Notified whenever a replica acknowledges an offset.
+    acks: broadcast::Sender<()>,
+}
+
+/// The master's end of the stream to one replica.
+pub struct ReplicaLink {
+    stream: mpsc::UnboundedSender<Bytes>,
+    /// Offset of the stream the replica last acknowledged.
+    acked: Arc<AtomicU64>,
+}
+
+impl ReplicaLink {
+    /// Offset of the stream the replica last acknowledged.
+    pub fn acked(&self) -> u64 {
+        self.acked.load(Ordering::SeqCst)
+    }
+}
+
+impl Replication {
+    pub fn new() -> Self {
+        Replication {
+            replid: random_id(),
+            offset: 0,
+            replicas: Vec::new(),
+            selected_db: None,
+            acks: broadcast::channel(1).0,
+        }
+    }
+
+    /// Adds a replica to the stream, returning where its share of the stream
+    /// arrives and where to record the offsets it acknowledges.
+    pub fn add_replica(&mut self) -> (mpsc::UnboundedReceiver<Bytes>, Arc<AtomicU64>) {
+        let (stream, receiver) = mpsc::unbounded_channel();
+        let acked = Arc::new(AtomicU64::new(0));
+        self.replicas.push(ReplicaLink {
+            stream,
+            acked: acked.clone(),
+        });
+        // The new replica knows nothing of what the stream selected.
+        self.selected_db = None;
+        (receiver, acked)
+    }
+
+    /// Asks every replica to acknowledge the offset it processed.
+    pub fn request_acks(&mut self) {
+        if !self.replicas.is_empty() {
+            self.send(Value::command(&["REPLCONF", "GETACK", "*"]).encode());
+        }
+    }
+
+    /// Counts the replicas that acknowledged the stream up to `offset`.
+    pub fn acknowledged(&self, offset: u64) -> usize {
+        self.replicas
+            .iter()
+            .filter(|replica| replica.acked() >= offset)
+            .count()
+    }
+
+    /// Forwards a write command run against database `db_index` to every
+    /// replica, selecting the database first if the stream is elsewhere.
+    pub fn propagate(&mut self, db_index: usize, command: Value) {
+        if self.replicas.is_empty() {
+            return;
+        }
+
+        if self.selected_db != Some(db_index) {
+            self.send(Value::command(&["SELECT", &db_index.to_string()]).encode());
+            self.selected_db = Some(db_index);
+        }
+        self.send(command.encode());
+    }
+
+    fn send(&mut self, bytes: Bytes) {
+        self.offset += bytes.len() as u64;
+        // Replicas that went away are dropped from the stream.
+        self.replicas
+            .retain(|replica| replica.stream.send(bytes.clone()).is_ok());
+    }
+}
+
+/// Streams the dataset to a replica that asked for a full resynchronization
+/// with PSYNC, followed by the write commands run from then on. Replicas
+/// start from an empty dataset for now.
+pub async fn serve_replica<S>(conn: &mut Connection<S>, server: &Server) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut stream, acked) = server.replication.write().unwrap().add_replica();
+
+    // Unlike a bulk string, the payload is not followed by CRLF.
+    let snapshot = rdb::serialize(&[]);
+    conn.write_all(format!("${}\r\n", snapshot.len()).as_bytes())
+        .await?;
+    conn.write_all(&snapshot).await?;
+    conn.flush().await?;
+
+    loop {
+        tokio::select! {
+            command = conn.read_command() => {
+                // Replicas only ever send acknowledgements.
+                let (command, args) = command?;
+                if let Some(offset) = parse_ack(&command, &args) {
+                    acked.store(offset, Ordering::SeqCst);
+                    // Nobody may be waiting for acknowledgements.
+                    let _ = server.replication.read().unwrap().acks.send(());
+                }
+            }
+            Some(bytes) = stream.recv() => {
+                conn.write_all(&bytes).await?;
+                conn.flush().await?;
+            }
+        }
+    }
+}
+
+/// Waits until `needed` replicas acknowledged the stream produced so far, or
+/// until `timeout` elapses, returning how many did. Without a timeout it
+/// waits for as long as it takes.
+pub async fn wait_for_replicas(server: &Server, needed: usize, timeout: Option<Duration>) -> usize {
+    // Subscribe before asking so that no acknowledgement slips through.
+    let (offset, mut acks) = {
+        let replication = server.replication.read().unwrap();
+        (replication.offset, replication.acks.subscribe())
+    };
+    let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+
+    let mut requested = false;
+    loop {
+        let acknowledged = server.replication.read().unwrap().acknowledged(offset);
+        if acknowledged >= needed {
+            return acknowledged;
+        }
+        if !requested {
+            server.replication.write().unwrap().request_acks();
+            requested = true;
+        }
+
+        // Lagging behind only means several replicas acknowledged at once.
+        let acked = acks.recv();
+        match deadline {
+            Some(deadline) => {
+                if tokio::time::timeout_at(deadline, acked).await.is_err() {
+                    return server.replication.read().unwrap().acknowledged(offset);
+                }
+            }
+            None => {
+                let _ = acked.await;
+            }
+        }
+    }
+}
+
+/// Periodically asks the replicas to acknowledge their offset, so the
+/// master knows how far behind each of them is.
+pub async fn request_acks(server: Server) {
+    let mut interval = tokio::time::interval(ACK_INTERVAL);
+    loop {
+        interval.tick().await;
+        server.replication.write().unwrap().request_acks();
+    }
+}
+
+/// Reads the offset out of a `REPLCONF ACK <offset>` command.
+fn parse_ack(command: &str, args: &[Value]) -> Option<u64> {
+    match args {
+        [option, offset] if command == "REPLCONF" => {
+            if !option.as_str().ok()?.eq_ignore_ascii_case("ACK") {
+                return None;
+            }
+            offset.as_str().ok()?.parse().ok()
+        }
+        _ => None,
+    }
+}
+
+/// Generates a random id of hex digits. `RandomState` is seeded randomly for
+/// each process, which is enough for ids that only need to differ between
+/// runs.
+fn random_id() -> String {
+    let state = RandomState::new();
+    let mut id = String::with_capacity(REPLID_LEN + 16);
+    let mut counter = 0;
+    while id.len() < REPLID_LEN {
+        let mut hasher = state.build_hasher();
+        hasher.write_u64(counter);
+        id.push_str(&format!("{:016x}", hasher.finish()));
+        counter += 1;
+    }
+    id.truncate(REPLID_LEN);
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{random_id, serve_replica, wait_for_replicas, Replication, REPLID_LEN};
+    use crate::config::Config;
+    use crate::connection::Connection;
+    use crate::db;
+    use crate::resp::Value;
+    use crate::server::Server;
+
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use tokio::io;
+
+    #[test]
+    fn it_generates_random_hex_ids() {
+        let id = random_id();
+
+        assert_eq!(id.len(), REPLID_LEN);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(id, random_id());
+    }
+
+    #[test]
+    fn it_selects_the_database_before_propagating_commands() {
+        let mut replication = Replication::new();
+        let (mut stream, _) = replication.add_replica();
+
+        replication.propagate(0, Value::command(&["SET", "a", "1"]));
+        replication.propagate(0, Value::command(&["DEL", "a"]));
+        replication.propagate(2, Value::command(&["DEL", "a"]));
+
+        let mut sent = Vec::new();
+        while let Ok(bytes) = stream.try_recv() {
+            sent.extend_from_slice(&bytes);
+        }
+        let expected = [
+            "*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n",
+            "*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n",
+            "*2\r\n$3\r\nDEL\r\n$1\r\na\r\n",
+            "*2\r\n$6\r\nSELECT\r\n$1\r\n2\r\n",
+            "*2\r\n$3\r\nDEL\r\n$1\r\na\r\n",
+        ]
+        .concat();
+        assert_eq!(String::from_utf8(sent).unwrap(), expected);
+        assert_eq!(replication.offset, expected.len() as u64);
+    }
+
+    #[test]
+    fn it_only_counts_the_stream_while_replicas_are_connected() {
+        let mut replication = Replication::new();
+        replication.propagate(0, Value::command(&["SET", "a", "1"]));
+        assert_eq!(replication.offset, 0);
+
+        drop(replication.add_replica());
+        replication.propagate(0, Value::command(&["SET", "a", "1"]));
+
+        assert!(replication.replicas.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_records_the_offsets_replicas_acknowledge() -> Result<()> {
+        let server = Server::new(db::databases(), Config::default());
+        let (master, replica) = io::duplex(64 * 1024);
+        let serving = server.clone();
+        tokio::spawn(async move { serve_replica(&mut Connection::new(master), &serving).await });
+
+        let mut replica = Connection::new(replica);
+        replica.read_rdb_payload().await?;
+        server.replication.write().unwrap().request_acks();
+        assert_eq!(
+            replica.read_value().await?,
+            Value::command(&["REPLCONF", "GETACK", "*"])
+        );
+
+        replica
+            .write_value(&Value::command(&["REPLCONF", "ACK", "37"]))
+            .await?;
+        replica.flush().await?;
+        let acked = || server.replication.read().unwrap().replicas[0].acked();
+        for _ in 0..100 {
+            if acked() == 37 {
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+        assert_eq!(acked(), 37);
+        assert_eq!(server.replication.read().unwrap().offset, 37);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_waits_for_replicas_to_acknowledge_the_stream() -> Result<()> {
+        let server = Server::new(db::databases(), Config::default());
+        let (master, replica) = io::duplex(64 * 1024);
+        let serving = server.clone();
+        tokio::spawn(async move { serve_replica(&mut Connection::new(master), &serving).await });
+
+        let mut replica = Connection::new(replica);
+        replica.read_rdb_payload().await?;
+        server
+            .replication
+            .write()
+            .unwrap()
+            .propagate(0, Value::command(&["SET", "a", "1"]));
+        let offset = server.replication.read().unwrap().offset;
+
+        let waiting = server.clone();
+        let waited = tokio::spawn(async move {
+            wait_for_replicas(&waiting, 1, Some(Duration::from_secs(5))).await
+        });
+        replica.read_value().await?;
+        replica.read_value().await?;
+        assert_eq!(
+            replica.read_value().await?,
+            Value::command(&["REPLCONF", "GETACK", "*"])
+        );
+        replica
+            .write_value(&Value::command(&["REPLCONF", "ACK", &offset.to_string()]))
+            .await?;
+        replica.flush().await?;
+
+        assert_eq!(waited.await?, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_stops_waiting_for_replicas_after_the_timeout() -> Result<()> {
+        let server = Server::new(db::databases(), Config::default());
+        let (master, replica) = io::duplex(64 * 1024);
+        let serving = server.clone();
+        tokio::spawn(async move { serve_replica(&mut Connection::new(master), &serving).await });
+        let mut replica = Connection::new(replica);
+        replica.read_rdb_payload().await?;
+
+        // Nothing was written yet, so the replica is already up to date.
+        assert_eq!(wait_for_replicas(&server, 0, None).await, 1);
+        server
+            .replication
+            .write()
+            .unwrap()
+            .propagate(0, Value::command(&["SET", "a", "1"]));
+        assert_eq!(
+            wait_for_replicas(&server, 1, Some(Duration::from_millis(50))).await,
+            0
+        );
+
+        Ok(())
+    }
+}
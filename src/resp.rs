@@ -1,16 +1,83 @@
 use anyhow::{bail, Result};
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 
-#[derive(PartialEq, Debug)]
+/// RESP protocol version negotiated with a client.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub enum Value {
     String(String),
     Number(i64),
-    Bulk { size: i64, data: Bytes },
+    Bulk {
+        size: i64,
+        data: Bytes,
+    },
     Error(String),
-    Array { len: i64, elements: Vec<Value> },
+    Array {
+        len: i64,
+        elements: Vec<Value>,
+    },
+    Null,
+    NullArray,
+    Map {
+        len: i64,
+        pairs: Vec<(Value, Value)>,
+    },
+    Double(f64),
+    Boolean(bool),
+    BigNumber(String),
+    Verbatim {
+        format: [u8; 3],
+        data: Bytes,
+    },
 }
 
 impl Value {
+    pub fn array(elements: Vec<Value>) -> Self {
+        Value::Array {
+            len: elements.len() as i64,
+            elements,
+        }
+    }
+
+    /// Builds a map reply, which RESP2 clients receive as a flat array of
+    /// alternating keys and values.
+    pub fn map(pairs: Vec<(Value, Value)>, protocol: Protocol) -> Self {
+        match protocol {
+            Protocol::Resp2 => Value::array(
+                pairs
+                    .into_iter()
+                    .flat_map(|(key, value)| vec![key, value])
+                    .collect(),
+            ),
+            Protocol::Resp3 => Value::Map {
+                len: pairs.len() as i64,
+                pairs,
+            },
+        }
+    }
+
+    /// A command as clients send it: an array of bulk strings.
+    pub fn command(args: &[&str]) -> Self {
+        Value::array(
+            args.iter()
+                .map(|arg| Value::bulk(Bytes::from(arg.to_string())))
+                .collect(),
+        )
+    }
+
+    pub fn bulk(data: Bytes) -> Self {
+        Value::Bulk {
+            size: data.len() as i64,
+            data,
+        }
+    }
+
     pub fn as_string(&self) -> Result<String> {
         match self {
             Value::String(value) => Ok(value.clone()),
@@ -21,119 +88,725 @@ impl Value {
             unexpected_value => bail!("value {:?} cannot be converted to string", unexpected_value),
         }
     }
+
+    /// Borrows a string value as `&str`, validating UTF-8 in place rather
+    /// than copying the payload like `as_string` does.
+    pub fn as_str(&self) -> Result<&str> {
+        match self {
+            Value::String(value) => Ok(value),
+            Value::Bulk { data, .. } => Ok(std::str::from_utf8(data)?),
+            unexpected_value => bail!("value {:?} cannot be converted to string", unexpected_value),
+        }
+    }
+
+    pub fn as_i64(&self) -> Result<i64> {
+        match self {
+            Value::Number(value) => Ok(*value),
+            Value::String(_) | Value::Bulk { .. } => match self.as_str()?.parse::<i64>() {
+                Ok(value) => Ok(value),
+                Err(_) => bail!("value {:?} is not a valid integer", self),
+            },
+            unexpected_value => bail!(
+                "value {:?} cannot be converted to integer",
+                unexpected_value
+            ),
+        }
+    }
+
+    /// Returns the raw bytes of a string value without validating UTF-8.
+    pub fn as_bytes(&self) -> Result<Bytes> {
+        match self {
+            Value::String(value) => Ok(Bytes::from(value.clone())),
+            Value::Bulk { data, .. } => Ok(data.clone()),
+            unexpected_value => bail!("value {:?} cannot be converted to bytes", unexpected_value),
+        }
+    }
+
+    /// Serializes the value to its RESP wire format.
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::new();
+        self.encode_into(&mut buf);
+        buf.freeze()
+    }
+
+    fn encode_into(&self, buf: &mut BytesMut) {
+        match self {
+            Value::String(value) => {
+                buf.extend_from_slice(format!("+{}\r\n", value).as_bytes());
+            }
+            Value::Number(value) => {
+                buf.extend_from_slice(format!(":{}\r\n", value).as_bytes());
+            }
+            Value::Bulk { data, .. } => {
+                buf.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
+                buf.extend_from_slice(data);
+                buf.extend_from_slice(b"\r\n");
+            }
+            Value::Error(message) => {
+                buf.extend_from_slice(format!("-{}\r\n", message).as_bytes());
+            }
+            Value::Array { elements, .. } => {
+                buf.extend_from_slice(format!("*{}\r\n", elements.len()).as_bytes());
+                for element in elements {
+                    element.encode_into(buf);
+                }
+            }
+            Value::Map { pairs, .. } => {
+                buf.extend_from_slice(format!("%{}\r\n", pairs.len()).as_bytes());
+                for (key, value) in pairs {
+                    key.encode_into(buf);
+                    value.encode_into(buf);
+                }
+            }
+            Value::Double(value) if value.is_nan() => buf.extend_from_slice(b",nan\r\n"),
+            Value::Double(value) => {
+                buf.extend_from_slice(format!(",{}\r\n", value).as_bytes());
+            }
+            Value::Boolean(true) => buf.extend_from_slice(b"#t\r\n"),
+            Value::Boolean(false) => buf.extend_from_slice(b"#f\r\n"),
+            Value::BigNumber(digits) => {
+                buf.extend_from_slice(format!("({}\r\n", digits).as_bytes());
+            }
+            Value::Verbatim { format, data } => {
+                buf.extend_from_slice(format!("={}\r\n", data.len() + 4).as_bytes());
+                buf.extend_from_slice(format);
+                buf.extend_from_slice(b":");
+                buf.extend_from_slice(data);
+                buf.extend_from_slice(b"\r\n");
+            }
+            Value::Null => buf.extend_from_slice(b"$-1\r\n"),
+            Value::NullArray => buf.extend_from_slice(b"*-1\r\n"),
+        }
+    }
 }
 
 fn find_crlf(buf: &Bytes) -> Option<usize> {
-    return buf.windows(2).position(|window| window == b"\r\n");
+    buf.windows(2).position(|window| window == b"\r\n")
 }
 
 type ParserState = (Value, Bytes);
 
-fn parse_string(buf: &mut Bytes) -> Result<ParserState> {
-    match find_crlf(&buf) {
+type ParseResult = std::result::Result<ParserState, ParseError>;
+
+/// Why a frame could not be parsed.
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum ParseError {
+    /// The input ends before the frame does, so reading more of it may still
+    /// complete the frame.
+    #[error("incomplete frame")]
+    Incomplete,
+    /// The input can never form a valid frame.
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+}
+
+/// Returns early with a `ParseError::Protocol` built from a format string.
+macro_rules! malformed {
+    ($($arg:tt)*) => {
+        return Err(ParseError::Protocol(format!($($arg)*)))
+    };
+}
+
+/// Default cap on the declared size of a bulk string, matching Redis's
+/// `proto-max-bulk-len`.
+pub const DEFAULT_MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+
+/// Default cap on the declared number of elements of an array or map.
+pub const DEFAULT_MAX_ARRAY_LEN: i64 = 1024 * 1024;
+
+/// Default cap on how deeply arrays and maps may be nested.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// Bounds enforced while parsing untrusted input.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    pub max_bulk_len: i64,
+    pub max_array_len: i64,
+    pub max_depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_bulk_len: DEFAULT_MAX_BULK_LEN,
+            max_array_len: DEFAULT_MAX_ARRAY_LEN,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+/// Checks the declared length and nesting depth of an aggregate before any
+/// of its elements get parsed.
+fn check_aggregate(len: i64, limits: &Limits, depth: usize) -> std::result::Result<(), ParseError> {
+    if len > limits.max_array_len {
+        malformed!("invalid multibulk length");
+    }
+    if depth >= limits.max_depth {
+        malformed!("maximum nesting depth exceeded");
+    }
+    Ok(())
+}
+
+fn parse_string(buf: &mut Bytes) -> ParseResult {
+    match find_crlf(buf) {
         Some(pos) => {
-            let string_value = String::from_utf8(Bytes::split_to(buf, pos).to_vec())?;
+            let string_value = match String::from_utf8(Bytes::split_to(buf, pos).to_vec()) {
+                Ok(string_value) => string_value,
+                Err(_) => malformed!("string parsing failed, invalid UTF-8"),
+            };
             Ok((Value::String(string_value), Bytes::split_off(buf, 2)))
         }
-        None => bail!("string parsing failed, could not find '\\r\\n' ending"),
+        None => Err(ParseError::Incomplete),
+    }
+}
+
+fn parse_error(buf: &mut Bytes) -> ParseResult {
+    match parse_string(buf)? {
+        (Value::String(message), rest) => Ok((Value::Error(message), rest)),
+        _ => malformed!("error parsing failed, unexpected value type"),
+    }
+}
+
+fn parse_number(buf: &mut Bytes) -> ParseResult {
+    match parse_string(buf)? {
+        (Value::String(value), rest) => match value.parse::<i64>() {
+            Ok(number) => Ok((Value::Number(number), rest)),
+            Err(_) => malformed!("number parsing failed, invalid integer: '{}'", value),
+        },
+        _ => malformed!("number parsing failed, unexpected value type"),
+    }
+}
+
+fn parse_double(buf: &mut Bytes) -> ParseResult {
+    match parse_string(buf)? {
+        (Value::String(value), rest) => {
+            let double = match value.as_str() {
+                "inf" => f64::INFINITY,
+                "-inf" => f64::NEG_INFINITY,
+                "nan" => f64::NAN,
+                _ => match value.parse::<f64>() {
+                    Ok(double) if double.is_finite() => double,
+                    _ => malformed!("double parsing failed, invalid double: '{}'", value),
+                },
+            };
+            Ok((Value::Double(double), rest))
+        }
+        _ => malformed!("double parsing failed, unexpected value type"),
+    }
+}
+
+fn parse_boolean(buf: &mut Bytes) -> ParseResult {
+    if buf.len() < 3 {
+        return Err(ParseError::Incomplete);
     }
+    if &buf[1..3] != b"\r\n" {
+        malformed!("boolean parsing failed, could not find '\\r\\n' ending");
+    }
+
+    let value = match buf[0] {
+        b't' => true,
+        b'f' => false,
+        byte => malformed!(
+            "boolean parsing failed, invalid value: '{}'",
+            char::from(byte)
+        ),
+    };
+    Ok((Value::Boolean(value), Bytes::split_off(buf, 3)))
 }
 
-fn parse_number(buf: &mut Bytes) -> Result<ParserState> {
+fn parse_big_number(buf: &mut Bytes) -> ParseResult {
     match parse_string(buf)? {
-        (Value::String(value), rest) => Ok((
-            Value::Number(i64::from_str_radix(&value, 10).unwrap()),
-            rest,
-        )),
-        _ => bail!("number parsing failed, unexpected value type"),
+        (Value::String(value), rest) => {
+            let digits = value.strip_prefix('-').unwrap_or(&value);
+            if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+                malformed!("big number parsing failed, invalid number: '{}'", value);
+            }
+            Ok((Value::BigNumber(value), rest))
+        }
+        _ => malformed!("big number parsing failed, unexpected value type"),
     }
 }
 
-fn parse_array(buf: &mut Bytes) -> Result<ParserState> {
-    if buf.len() < 1 {
-        bail!("array parsing failed, missing 'len'");
+fn parse_array(buf: &mut Bytes, limits: &Limits, depth: usize) -> ParseResult {
+    if buf.is_empty() {
+        return Err(ParseError::Incomplete);
     }
 
     match parse_number(buf)? {
+        (Value::Number(len), rest) if len < 0 => Ok((Value::NullArray, rest)),
         (Value::Number(len), rest) => {
-            let mut leftover_data = rest;
+            check_aggregate(len, limits, depth)?;
+            *buf = rest;
             let mut elements: Vec<Value> = vec![];
 
             for _ in 0..len {
-                let (element, element_leftover_data) = parse_resp(&mut leftover_data)?;
-                leftover_data = element_leftover_data;
+                let (element, rest) = parse_value(buf, limits, depth + 1)?;
+                *buf = rest;
                 elements.push(element);
             }
 
-            Ok((Value::Array { len, elements }, leftover_data))
+            Ok((Value::Array { len, elements }, Bytes::split_off(buf, 0)))
         }
-        _ => bail!("array parsing failed, could not parse 'len' as a number"),
+        _ => malformed!("array parsing failed, could not parse 'len' as a number"),
     }
 }
 
-fn parse_bulk_string(buf: &mut Bytes) -> Result<ParserState> {
-    if buf.len() < 1 {
-        bail!("bulk string parsing failed, missing 'size'");
+fn parse_map(buf: &mut Bytes, limits: &Limits, depth: usize) -> ParseResult {
+    if buf.is_empty() {
+        return Err(ParseError::Incomplete);
     }
 
     match parse_number(buf)? {
+        (Value::Number(len), _) if len < 0 => {
+            malformed!("map parsing failed, invalid 'len': {}", len)
+        }
+        (Value::Number(len), rest) => {
+            check_aggregate(len, limits, depth)?;
+            *buf = rest;
+            let mut pairs: Vec<(Value, Value)> = vec![];
+
+            for _ in 0..len {
+                let (key, rest) = parse_value(buf, limits, depth + 1)?;
+                *buf = rest;
+                let (value, rest) = parse_value(buf, limits, depth + 1)?;
+                *buf = rest;
+                pairs.push((key, value));
+            }
+
+            Ok((Value::Map { len, pairs }, Bytes::split_off(buf, 0)))
+        }
+        _ => malformed!("map parsing failed, could not parse 'len' as a number"),
+    }
+}
+
+fn parse_bulk_string(buf: &mut Bytes, limits: &Limits) -> ParseResult {
+    if buf.is_empty() {
+        return Err(ParseError::Incomplete);
+    }
+
+    match parse_number(buf)? {
+        (Value::Number(size), rest) if size < 0 => Ok((Value::Null, rest)),
+        (Value::Number(size), _) if size > limits.max_bulk_len => {
+            malformed!("invalid bulk length")
+        }
         (Value::Number(size), mut rest) => {
-            let buffer_size = rest.len() as i64;
-            if size > buffer_size as i64 - 2 {
-                bail!("bulk string parsing failed, cannot read {} bytes from buffer of size {} accounting for '\\r\\n' ending", size, buffer_size);
+            if size > rest.len() as i64 - 2 {
+                return Err(ParseError::Incomplete);
+            }
+
+            let data = Bytes::split_to(&mut rest, size as usize);
+            if !rest.starts_with(b"\r\n") {
+                malformed!("bulk string parsing failed, could not find '\\r\\n' ending");
             }
 
-            let data = Bytes::split_to(&mut rest, size.try_into()?);
-            let end_pos = find_crlf(&rest).ok_or(anyhow::format_err!(
-                "bulk string failed, could not find '\\r\\n' ending"
-            ))?;
+            Ok((Value::Bulk { size, data }, Bytes::split_off(&mut rest, 2)))
+        }
+        _ => malformed!("bulk string parsing failed, could not parse 'size' as a number"),
+    }
+}
+
+/// Parses a verbatim string, which is framed like a bulk string whose
+/// payload starts with a three byte format hint and a colon.
+fn parse_verbatim_string(buf: &mut Bytes, limits: &Limits) -> ParseResult {
+    match parse_bulk_string(buf, limits)? {
+        (Value::Bulk { data, .. }, rest) => {
+            if data.len() < 4 || data[3] != b':' {
+                malformed!("verbatim string parsing failed, missing format prefix");
+            }
 
+            let mut format = [0; 3];
+            format.copy_from_slice(&data[..3]);
             Ok((
-                Value::Bulk { size, data },
-                Bytes::split_off(&mut rest, end_pos + 2),
+                Value::Verbatim {
+                    format,
+                    data: data.slice(4..),
+                },
+                rest,
             ))
         }
-        _ => bail!("bulk string parsing failed, could not parse 'size' as a number"),
+        _ => malformed!("verbatim string parsing failed, invalid size"),
+    }
+}
+
+/// Longest inline command accepted, matching Redis's
+/// `PROTO_INLINE_MAX_SIZE`, so that text sent without a CRLF cannot grow the
+/// read buffer forever.
+pub const MAX_INLINE_LEN: usize = 64 * 1024;
+
+/// Whether `byte` starts a RESP frame. Anything else starts an inline
+/// command.
+fn is_type_prefix(byte: u8) -> bool {
+    b"+-*:$%,#(=_>~!|".contains(&byte)
+}
+
+/// Length of the inline command line at the start of `buf`, including its
+/// CRLF.
+fn inline_len(buf: &[u8]) -> std::result::Result<usize, ParseError> {
+    let window = &buf[..buf.len().min(MAX_INLINE_LEN + 2)];
+    match line_len(window) {
+        Err(ParseError::Incomplete) if buf.len() >= MAX_INLINE_LEN + 2 => {
+            malformed!("too big inline request")
+        }
+        len => len,
+    }
+}
+
+/// How many bytes of blank inline lines, such as a bare CRLF typed into
+/// telnet, `buf` starts with. They are skipped rather than run.
+fn blank_lines_len(buf: &[u8]) -> usize {
+    let mut skipped = 0;
+    while let Some(&first) = buf.get(skipped) {
+        if is_type_prefix(first) {
+            break;
+        }
+        match inline_len(&buf[skipped..]) {
+            Ok(len)
+                if buf[skipped..skipped + len]
+                    .iter()
+                    .all(u8::is_ascii_whitespace) =>
+            {
+                skipped += len
+            }
+            _ => break,
+        }
     }
+    skipped
 }
 
-fn parsing_error(buf: &mut Bytes, message: &str) -> Result<ParserState> {
-    Ok((Value::Error(message.to_string()), Bytes::split_off(buf, 0)))
+/// Parses an inline command, a line of space-separated arguments sent
+/// without any RESP framing, into an array of bulk strings.
+fn parse_inline(buf: &mut Bytes) -> ParseResult {
+    let len = inline_len(buf)?;
+    let line = Bytes::split_to(buf, len - 2);
+    let elements: Vec<Value> = line
+        .split(|byte| byte.is_ascii_whitespace())
+        .filter(|argument| !argument.is_empty())
+        .map(|argument| Value::bulk(Bytes::copy_from_slice(argument)))
+        .collect();
+
+    Ok((
+        Value::Array {
+            len: elements.len() as i64,
+            elements,
+        },
+        Bytes::split_off(buf, 2),
+    ))
 }
 
-pub fn parse_resp(buf: &mut Bytes) -> Result<ParserState> {
-    if buf.len() < 1 {
-        bail!("empty buffer");
+fn parse_value(buf: &mut Bytes, limits: &Limits, depth: usize) -> ParseResult {
+    if buf.is_empty() {
+        return Err(ParseError::Incomplete);
     }
 
     match Bytes::split_to(buf, 1)[0] {
         b'+' => parse_string(buf),
-        b'*' => parse_array(buf),
+        b'-' => parse_error(buf),
+        b'*' => parse_array(buf, limits, depth),
         b':' => parse_number(buf),
-        b'$' => parse_bulk_string(buf),
-        kind => bail!("parsing failed, unknown kind: '{}'", char::from(kind)),
+        b'$' => parse_bulk_string(buf, limits),
+        b'%' => parse_map(buf, limits, depth),
+        b',' => parse_double(buf),
+        b'#' => parse_boolean(buf),
+        b'(' => parse_big_number(buf),
+        b'=' => parse_verbatim_string(buf, limits),
+        kind => malformed!("parsing failed, unknown kind: '{}'", char::from(kind)),
+    }
+}
+
+/// Parses a single frame from `buf`, returning it along with the bytes that
+/// follow it, which `buf` is also advanced to. Fails with `Incomplete` when
+/// the frame is truncated, or `Protocol` when it is malformed, leaving `buf`
+/// untouched either way.
+pub fn parse_frame(buf: &mut Bytes, limits: &Limits) -> ParseResult {
+    // Parsing splits the bytes it reads off, so it works on a cheap copy
+    // that only replaces `buf` once a whole frame was read.
+    let mut data = buf.slice(blank_lines_len(buf)..);
+    let (value, rest) = if !data.is_empty() && !is_type_prefix(data[0]) {
+        parse_inline(&mut data)?
+    } else {
+        parse_value(&mut data, limits, 0)?
+    };
+    *buf = rest.clone();
+
+    Ok((value, rest))
+}
+
+/// Length of the line at the start of `buf`, including its CRLF.
+fn line_len(buf: &[u8]) -> std::result::Result<usize, ParseError> {
+    buf.windows(2)
+        .position(|window| window == b"\r\n")
+        .map(|pos| pos + 2)
+        .ok_or(ParseError::Incomplete)
+}
+
+/// Reads the length line of a bulk string or aggregate, returning the
+/// declared length and how many bytes the line took up.
+fn declared_len(buf: &[u8]) -> std::result::Result<(i64, usize), ParseError> {
+    let line = line_len(buf)?;
+    match parse_number(&mut Bytes::copy_from_slice(&buf[..line]))? {
+        (Value::Number(len), _) => Ok((len, line)),
+        _ => malformed!("length parsing failed, unexpected value type"),
+    }
+}
+
+fn value_len(buf: &[u8], limits: &Limits, depth: usize) -> std::result::Result<usize, ParseError> {
+    let kind = *buf.first().ok_or(ParseError::Incomplete)?;
+    match kind {
+        b'$' | b'=' => {
+            let (size, header) = declared_len(&buf[1..])?;
+            if size > limits.max_bulk_len {
+                malformed!("invalid bulk length");
+            }
+
+            let len = 1 + header + if size < 0 { 0 } else { size as usize + 2 };
+            if buf.len() < len {
+                return Err(ParseError::Incomplete);
+            }
+            Ok(len)
+        }
+        b'*' | b'%' => {
+            let (count, header) = declared_len(&buf[1..])?;
+            let mut len = 1 + header;
+            if count < 0 {
+                if kind == b'%' {
+                    malformed!("map parsing failed, invalid 'len': {}", count);
+                }
+                return Ok(len);
+            }
+
+            check_aggregate(count, limits, depth)?;
+            let elements = if kind == b'%' { count * 2 } else { count };
+            for _ in 0..elements {
+                len += value_len(&buf[len..], limits, depth + 1)?;
+            }
+            Ok(len)
+        }
+        _ => {
+            // Simple values are a single short line, which the parser
+            // checks so that a malformed one is reported right away.
+            let len = 1 + line_len(&buf[1..])?;
+            parse_value(&mut Bytes::copy_from_slice(&buf[..len]), limits, depth)?;
+            Ok(len)
+        }
     }
 }
 
+/// Measures the frame at the start of `buf` without copying it, so that a
+/// reader only splits off and parses whole frames. Fails the way
+/// `parse_frame` does, except that the payload of bulk strings is only
+/// checked once parsed.
+pub fn frame_len(buf: &[u8], limits: &Limits) -> std::result::Result<usize, ParseError> {
+    let skipped = blank_lines_len(buf);
+    let buf = &buf[skipped..];
+    let len = if !buf.is_empty() && !is_type_prefix(buf[0]) {
+        inline_len(buf)?
+    } else {
+        value_len(buf, limits, 0)?
+    };
+    Ok(skipped + len)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{parse_resp, Value};
+    use super::{
+        frame_len, parse_frame, Limits, ParseError, Protocol, Value, DEFAULT_MAX_DEPTH,
+        MAX_INLINE_LEN,
+    };
 
     use anyhow::Result;
     use bytes::Bytes;
 
+    fn assert_round_trip(value: Value, wire: &str) -> Result<()> {
+        let encoded = value.encode();
+        assert_eq!(encoded, Bytes::from(wire.to_string()));
+
+        let (parsed, rest) = parse_frame(&mut encoded.clone(), &Limits::default())?;
+        assert_eq!(parsed, value);
+        assert_eq!(rest, Bytes::from(""));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_borrows_a_large_bulk_string_without_copying() -> Result<()> {
+        let data = Bytes::from("x".repeat(8 * 1024 * 1024));
+        let value = Value::bulk(data.clone());
+
+        let borrowed = value.as_str()?;
+        assert_eq!(borrowed.len(), data.len());
+        assert_eq!(borrowed.as_ptr(), data.as_ptr());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_returns_an_error_borrowing_invalid_utf8() {
+        let value = Value::bulk(Bytes::from(vec![0xff, 0xfe]));
+
+        assert!(value.as_str().is_err());
+    }
+
+    #[test]
+    fn it_converts_values_to_integers() -> Result<()> {
+        assert_eq!(Value::Number(-7).as_i64()?, -7);
+        assert_eq!(Value::String("42".to_string()).as_i64()?, 42);
+        assert_eq!(Value::bulk(Bytes::from("-12")).as_i64()?, -12);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_returns_an_error_converting_an_invalid_integer() {
+        let err = Value::bulk(Bytes::from("12abc")).as_i64().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "value Bulk { size: 5, data: b\"12abc\" } is not a valid integer"
+        );
+        assert!(Value::Null.as_i64().is_err());
+    }
+
+    #[test]
+    fn it_converts_values_to_bytes() -> Result<()> {
+        let binary = Bytes::from(vec![0xff, 0x00, 0xfe]);
+        assert_eq!(Value::bulk(binary.clone()).as_bytes()?, binary);
+        assert_eq!(
+            Value::String("OK".to_string()).as_bytes()?,
+            Bytes::from("OK")
+        );
+        assert!(Value::Number(1).as_bytes().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_encodes_a_string() -> Result<()> {
+        assert_round_trip(Value::String("OK".to_string()), "+OK\r\n")
+    }
+
+    #[test]
+    fn it_encodes_a_number() -> Result<()> {
+        assert_round_trip(Value::Number(-123), ":-123\r\n")
+    }
+
+    #[test]
+    fn it_encodes_a_bulk_string() -> Result<()> {
+        assert_round_trip(
+            Value::Bulk {
+                size: 5,
+                data: Bytes::from("hello"),
+            },
+            "$5\r\nhello\r\n",
+        )
+    }
+
+    #[test]
+    fn it_encodes_a_null_bulk_string() -> Result<()> {
+        assert_round_trip(Value::Null, "$-1\r\n")
+    }
+
+    #[test]
+    fn it_encodes_a_null_array() -> Result<()> {
+        assert_round_trip(Value::NullArray, "*-1\r\n")
+    }
+
+    #[test]
+    fn it_encodes_a_double() -> Result<()> {
+        assert_round_trip(Value::Double(1.5), ",1.5\r\n")?;
+        assert_round_trip(Value::Double(f64::NEG_INFINITY), ",-inf\r\n")?;
+        assert_eq!(Value::Double(f64::NAN).encode(), Bytes::from(",nan\r\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_encodes_a_boolean() -> Result<()> {
+        assert_round_trip(Value::Boolean(true), "#t\r\n")?;
+        assert_round_trip(Value::Boolean(false), "#f\r\n")
+    }
+
+    #[test]
+    fn it_encodes_a_big_number() -> Result<()> {
+        assert_round_trip(
+            Value::BigNumber("-12345678901234567890".to_string()),
+            "(-12345678901234567890\r\n",
+        )
+    }
+
+    #[test]
+    fn it_encodes_a_verbatim_string() -> Result<()> {
+        assert_round_trip(
+            Value::Verbatim {
+                format: *b"txt",
+                data: Bytes::from("Some string"),
+            },
+            "=15\r\ntxt:Some string\r\n",
+        )
+    }
+
+    #[test]
+    fn it_encodes_an_error() -> Result<()> {
+        assert_round_trip(
+            Value::Error("ERR unknown command".to_string()),
+            "-ERR unknown command\r\n",
+        )
+    }
+
+    #[test]
+    fn it_encodes_a_nested_array() -> Result<()> {
+        assert_round_trip(
+            Value::Array {
+                len: 3,
+                elements: vec![
+                    Value::Number(1),
+                    Value::Bulk {
+                        size: 3,
+                        data: Bytes::from("foo"),
+                    },
+                    Value::Array {
+                        len: 1,
+                        elements: vec![Value::String("bar".to_string())],
+                    },
+                ],
+            },
+            "*3\r\n:1\r\n$3\r\nfoo\r\n*1\r\n+bar\r\n",
+        )
+    }
+
+    #[test]
+    fn it_encodes_a_map_for_resp3() -> Result<()> {
+        let map = Value::map(
+            vec![(
+                Value::String("key".to_string()),
+                Value::String("val".to_string()),
+            )],
+            Protocol::Resp3,
+        );
+
+        assert_round_trip(map, "%1\r\n+key\r\n+val\r\n")
+    }
+
+    #[test]
+    fn it_flattens_a_map_for_resp2() {
+        let map = Value::map(
+            vec![(
+                Value::String("key".to_string()),
+                Value::String("val".to_string()),
+            )],
+            Protocol::Resp2,
+        );
+
+        assert_eq!(map.encode(), Bytes::from("*2\r\n+key\r\n+val\r\n"));
+    }
+
     #[test]
     fn it_parses_a_string() -> Result<()> {
         let mut buffer = Bytes::from("+Test\r\n+Foo\r\n");
 
-        match parse_resp(&mut buffer)? {
+        match parse_frame(&mut buffer, &Limits::default())? {
             (Value::String(value), rest) => {
                 assert_eq!(value, "Test".to_string());
                 assert_eq!(rest, Bytes::from("+Foo\r\n"))
             }
-            (Value::Error(err), _) => {
-                panic!("parsing error: {}", err);
-            }
             (kind, rest) => {
                 panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
             }
@@ -145,7 +818,7 @@ mod tests {
     #[test]
     fn it_parses_an_empty_array() -> Result<()> {
         let mut buffer = Bytes::from("*0\r\n");
-        match parse_resp(&mut buffer)? {
+        match parse_frame(&mut buffer, &Limits::default())? {
             (Value::Array { len, elements }, rest) => {
                 assert_eq!(len, 0);
                 assert_eq!(elements.len(), 0);
@@ -158,10 +831,24 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn it_parses_a_null_array() -> Result<()> {
+        let mut buffer = Bytes::from("*-1\r\n:1\r\n");
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::NullArray, rest) => {
+                assert_eq!(rest, Bytes::from(":1\r\n"))
+            }
+            (kind, rest) => {
+                panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
+            }
+        };
+        Ok(())
+    }
+
     #[test]
     fn it_parses_a_string_array() -> Result<()> {
         let mut buffer = Bytes::from("*2\r\n+hello\r\n+world\r\n");
-        match parse_resp(&mut buffer)? {
+        match parse_frame(&mut buffer, &Limits::default())? {
             (Value::Array { len, elements }, rest) => {
                 assert_eq!(len, 2);
                 assert_eq!(elements.len(), 2);
@@ -185,7 +872,7 @@ mod tests {
     #[test]
     fn it_parses_a_number_array() -> Result<()> {
         let mut buffer = Bytes::from("*3\r\n:1\r\n:2\r\n:3\r\n");
-        match parse_resp(&mut buffer)? {
+        match parse_frame(&mut buffer, &Limits::default())? {
             (Value::Array { len, elements }, rest) => {
                 assert_eq!(len, 3);
                 assert_eq!(elements.len(), 3);
@@ -206,7 +893,7 @@ mod tests {
     #[test]
     fn it_parses_a_mixed_array() -> Result<()> {
         let mut buffer = Bytes::from("*2\r\n:1\r\n+hello\r\n");
-        match parse_resp(&mut buffer)? {
+        match parse_frame(&mut buffer, &Limits::default())? {
             (Value::Array { len, elements }, rest) => {
                 assert_eq!(len, 2);
                 assert_eq!(elements.len(), 2);
@@ -225,18 +912,20 @@ mod tests {
     }
 
     #[test]
-    fn it_parses_a_bulk_string() -> Result<()> {
-        let mut buffer = Bytes::from("$5\r\nhello\r\n");
-
-        match parse_resp(&mut buffer)? {
-            (Value::Bulk { size, data }, rest) => {
-                assert_eq!(size, 5);
-                assert_eq!(data, Bytes::from("hello"));
+    fn it_parses_a_map() -> Result<()> {
+        let mut buffer = Bytes::from("%1\r\n+key\r\n+val\r\n");
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Map { len, pairs }, rest) => {
+                assert_eq!(len, 1);
+                assert_eq!(
+                    pairs,
+                    vec![(
+                        Value::String("key".to_string()),
+                        Value::String("val".to_string())
+                    )]
+                );
                 assert_eq!(rest, Bytes::from(""))
             }
-            (Value::Error(err), _) => {
-                panic!("parsing error: {}", err);
-            }
             (kind, rest) => {
                 panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
             }
@@ -246,18 +935,14 @@ mod tests {
     }
 
     #[test]
-    fn it_parses_a_bulk_string_ignoring_extra_data() -> Result<()> {
-        let mut buffer = Bytes::from("$5\r\nhello world\r\n");
-
-        match parse_resp(&mut buffer)? {
-            (Value::Bulk { size, data }, rest) => {
-                assert_eq!(size, 5);
-                assert_eq!(data, Bytes::from("hello"));
+    fn it_parses_an_empty_map() -> Result<()> {
+        let mut buffer = Bytes::from("%0\r\n");
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Map { len, pairs }, rest) => {
+                assert_eq!(len, 0);
+                assert_eq!(pairs.len(), 0);
                 assert_eq!(rest, Bytes::from(""))
             }
-            (Value::Error(err), _) => {
-                panic!("parsing error: {}", err);
-            }
             (kind, rest) => {
                 panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
             }
@@ -267,35 +952,22 @@ mod tests {
     }
 
     #[test]
-    fn it_returns_an_error_if_reading_a_bulk_string_goes_out_of_bound() -> Result<()> {
-        let mut buffer = Bytes::from("$5\r\nh");
-
-        match parse_resp(&mut buffer)? {
-            (Value::Error(err), _) => {
-                assert_eq!(
-                    err,
-                    "bulk string parsing failed, cannot read 5 bytes from buffer of size 1 accounting for '\\r\\n' ending"
-                );
-            }
-            (kind, rest) => {
-                panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
-            }
-        };
+    fn it_returns_an_error_on_a_map_missing_its_value() {
+        let mut buffer = Bytes::from("%1\r\n+key\r\n");
 
-        Ok(())
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert_eq!(err, ParseError::Incomplete);
+        assert_eq!(buffer, Bytes::from("%1\r\n+key\r\n"));
     }
 
     #[test]
-    fn it_returns_an_error_if_reading_a_bulk_string_goes_out_of_bound_accounting_for_ending(
-    ) -> Result<()> {
-        let mut buffer = Bytes::from("$5\r\nh\r\n");
+    fn it_parses_a_double() -> Result<()> {
+        let mut buffer = Bytes::from(",2.5\r\n");
 
-        match parse_resp(&mut buffer)? {
-            (Value::Error(err), _) => {
-                assert_eq!(
-                    err,
-                    "bulk string parsing failed, cannot read 5 bytes from buffer of size 3 accounting for '\\r\\n' ending"
-                );
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Double(value), rest) => {
+                assert_eq!(value, 2.5);
+                assert_eq!(rest, Bytes::from(""))
             }
             (kind, rest) => {
                 panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
@@ -306,11 +978,18 @@ mod tests {
     }
 
     #[test]
-    fn it_returns_an_error_on_invalid_length_array() -> Result<()> {
-        let mut buffer = Bytes::from("*2\r\n+hello\r\n");
-        match parse_resp(&mut buffer)? {
-            (Value::Error(err), rest) => {
-                assert_eq!(err, "empty buffer");
+    fn it_parses_the_special_doubles() -> Result<()> {
+        let mut buffer = Bytes::from(",inf\r\n,-inf\r\n,nan\r\n");
+
+        let (value, mut rest) = parse_frame(&mut buffer, &Limits::default())?;
+        assert_eq!(value, Value::Double(f64::INFINITY));
+
+        let (value, mut rest) = parse_frame(&mut rest, &Limits::default())?;
+        assert_eq!(value, Value::Double(f64::NEG_INFINITY));
+
+        match parse_frame(&mut rest, &Limits::default())? {
+            (Value::Double(value), rest) => {
+                assert!(value.is_nan());
                 assert_eq!(rest, Bytes::from(""))
             }
             (kind, rest) => {
@@ -322,16 +1001,136 @@ mod tests {
     }
 
     #[test]
-    fn it_parses_a_number() -> Result<()> {
-        let mut buffer = Bytes::from(":1000\r\n");
+    fn it_returns_an_error_on_an_invalid_double() {
+        let mut buffer = Bytes::from(",pi\r\n");
 
-        match parse_resp(&mut buffer)? {
-            (Value::Number(value), rest) => {
-                assert_eq!(value, 1000);
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::Protocol("double parsing failed, invalid double: 'pi'".to_string())
+        );
+    }
+
+    #[test]
+    fn it_parses_booleans() -> Result<()> {
+        let mut buffer = Bytes::from("#t\r\n#f\r\n");
+
+        let (value, mut rest) = parse_frame(&mut buffer, &Limits::default())?;
+        assert_eq!(value, Value::Boolean(true));
+
+        let (value, rest) = parse_frame(&mut rest, &Limits::default())?;
+        assert_eq!(value, Value::Boolean(false));
+        assert_eq!(rest, Bytes::from(""));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_returns_an_error_on_an_invalid_boolean() {
+        let mut buffer = Bytes::from("#x\r\n");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::Protocol("boolean parsing failed, invalid value: 'x'".to_string())
+        );
+    }
+
+    #[test]
+    fn it_parses_a_big_number() -> Result<()> {
+        let mut buffer = Bytes::from("(3492890328409238509324850943850943825024385\r\n");
+
+        let (value, rest) = parse_frame(&mut buffer, &Limits::default())?;
+        assert_eq!(
+            value,
+            Value::BigNumber("3492890328409238509324850943850943825024385".to_string())
+        );
+        assert_eq!(rest, Bytes::from(""));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_a_negative_big_number() -> Result<()> {
+        let mut buffer = Bytes::from("(-3492890328409238509324850943850943825024385\r\n");
+
+        let (value, _) = parse_frame(&mut buffer, &Limits::default())?;
+        assert_eq!(
+            value,
+            Value::BigNumber("-3492890328409238509324850943850943825024385".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_returns_an_error_on_an_invalid_big_number() {
+        let mut buffer = Bytes::from("(12a\r\n");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::Protocol("big number parsing failed, invalid number: '12a'".to_string())
+        );
+    }
+
+    #[test]
+    fn it_parses_a_text_verbatim_string() -> Result<()> {
+        let mut buffer = Bytes::from("=15\r\ntxt:Some string\r\n");
+
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Verbatim { format, data }, rest) => {
+                assert_eq!(&format, b"txt");
+                assert_eq!(data, Bytes::from("Some string"));
+                assert_eq!(rest, Bytes::from(""))
+            }
+            (kind, rest) => {
+                panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
+            }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_a_markdown_verbatim_string() -> Result<()> {
+        let mut buffer = Bytes::from("=11\r\nmkd:# Title\r\n");
+
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Verbatim { format, data }, rest) => {
+                assert_eq!(&format, b"mkd");
+                assert_eq!(data, Bytes::from("# Title"));
                 assert_eq!(rest, Bytes::from(""))
             }
-            (Value::Error(err), _) => {
-                panic!("parsing error: {}", err);
+            (kind, rest) => {
+                panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
+            }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_returns_an_error_on_a_verbatim_string_without_format() {
+        let mut buffer = Bytes::from("=3\r\ntxt\r\n");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::Protocol(
+                "verbatim string parsing failed, missing format prefix".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn it_parses_an_error() -> Result<()> {
+        let mut buffer = Bytes::from("-ERR something went wrong\r\n+Foo\r\n");
+
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Error(message), rest) => {
+                assert_eq!(message, "ERR something went wrong");
+                assert_eq!(rest, Bytes::from("+Foo\r\n"))
             }
             (kind, rest) => {
                 panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
@@ -342,16 +1141,35 @@ mod tests {
     }
 
     #[test]
-    fn it_parses_a_negative_number() -> Result<()> {
-        let mut buffer = Bytes::from(":-1000\r\n");
+    fn it_parses_an_array_containing_an_error() -> Result<()> {
+        let mut buffer = Bytes::from("*2\r\n-ERR oops\r\n:1\r\n");
 
-        match parse_resp(&mut buffer)? {
-            (Value::Number(value), rest) => {
-                assert_eq!(value, -1000);
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Array { len, elements }, rest) => {
+                assert_eq!(len, 2);
+                assert_eq!(
+                    elements,
+                    vec![Value::Error("ERR oops".to_string()), Value::Number(1)]
+                );
                 assert_eq!(rest, Bytes::from(""))
             }
-            (Value::Error(err), _) => {
-                panic!("parsing error: {}", err);
+            (kind, rest) => {
+                panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
+            }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_a_bulk_string() -> Result<()> {
+        let mut buffer = Bytes::from("$5\r\nhello\r\n");
+
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Bulk { size, data }, rest) => {
+                assert_eq!(size, 5);
+                assert_eq!(data, Bytes::from("hello"));
+                assert_eq!(rest, Bytes::from(""))
             }
             (kind, rest) => {
                 panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
@@ -362,50 +1180,452 @@ mod tests {
     }
 
     #[test]
-    fn it_returns_an_error_on_missing_crlf() -> Result<()> {
-        let mut buffer = Bytes::from("+Test");
-        match parse_resp(&mut buffer)? {
-            (Value::Error(err), rest) => {
-                assert_eq!(err, "string parsing failed, could not find '\\r\\n' ending");
-                assert_eq!(rest, Bytes::from("Test"))
+    fn it_rejects_a_bulk_string_longer_than_its_declared_size() {
+        let mut buffer = Bytes::from("$5\r\nhello world\r\n");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::Protocol(
+                "bulk string parsing failed, could not find '\\r\\n' ending".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn it_rejects_a_bulk_string_not_followed_by_crlf() {
+        // Waiting for a CRLF further on would never end, or swallow the
+        // command pipelined after it.
+        for malformed in ["$3\r\nabcXY", "$3\r\nabcXY*1\r\n$4\r\nPING\r\n"] {
+            let mut buffer = Bytes::from(malformed);
+
+            let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+            assert!(matches!(err, ParseError::Protocol(_)));
+            assert_eq!(buffer, Bytes::from(malformed));
+        }
+
+        for partial in ["$3\r\nabc", "$3\r\nabc\r"] {
+            let mut buffer = Bytes::from(partial);
+
+            let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+            assert_eq!(err, ParseError::Incomplete);
+        }
+    }
+
+    #[test]
+    fn it_parses_a_null_bulk_string() -> Result<()> {
+        let mut buffer = Bytes::from("$-1\r\n+Foo\r\n");
+
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Null, rest) => {
+                assert_eq!(rest, Bytes::from("+Foo\r\n"))
             }
             (kind, rest) => {
                 panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
             }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_a_bulk_string_larger_than_the_limit() {
+        let mut buffer = Bytes::from("$100000000000\r\n");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert!(matches!(err, ParseError::Protocol(_)));
+        assert_eq!(err.to_string(), "Protocol error: invalid bulk length");
+    }
+
+    #[test]
+    fn it_enforces_a_configured_bulk_string_limit() -> Result<()> {
+        let limits = Limits {
+            max_bulk_len: 4,
+            ..Limits::default()
+        };
+
+        let (value, _) = parse_frame(&mut Bytes::from("$4\r\nfour\r\n"), &limits)?;
+        assert_eq!(value, Value::bulk(Bytes::from("four")));
+
+        let err = parse_frame(&mut Bytes::from("$5\r\nfive!\r\n"), &limits).unwrap_err();
+        assert!(matches!(err, ParseError::Protocol(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_an_array_longer_than_the_limit() {
+        let mut buffer = Bytes::from("*2000000000\r\n");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert!(matches!(err, ParseError::Protocol(_)));
+        assert_eq!(err.to_string(), "Protocol error: invalid multibulk length");
+    }
+
+    #[test]
+    fn it_rejects_a_map_longer_than_the_limit() {
+        let limits = Limits {
+            max_array_len: 1,
+            ..Limits::default()
+        };
+        let mut buffer = Bytes::from("%2\r\n");
+
+        let err = parse_frame(&mut buffer, &limits).unwrap_err();
+        assert!(matches!(err, ParseError::Protocol(_)));
+    }
+
+    #[test]
+    fn it_rejects_deeply_nested_arrays() {
+        let mut buffer = Bytes::from("*1\r\n".repeat(DEFAULT_MAX_DEPTH + 1));
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert!(matches!(err, ParseError::Protocol(_)));
+        assert_eq!(
+            err.to_string(),
+            "Protocol error: maximum nesting depth exceeded"
+        );
+    }
+
+    #[test]
+    fn it_parses_arrays_nested_up_to_the_limit() -> Result<()> {
+        let limits = Limits {
+            max_depth: 3,
+            ..Limits::default()
+        };
+        let mut buffer = Bytes::from("*1\r\n*1\r\n*1\r\n:1\r\n");
+
+        let (value, _) = parse_frame(&mut buffer, &limits)?;
+        assert_eq!(
+            value,
+            Value::array(vec![Value::array(vec![Value::array(vec![Value::Number(
+                1
+            )])])])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_returns_an_error_if_reading_a_bulk_string_goes_out_of_bound() {
+        let mut buffer = Bytes::from("$5\r\nh");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert_eq!(err, ParseError::Incomplete);
+    }
+
+    #[test]
+    fn it_returns_an_error_if_reading_a_bulk_string_goes_out_of_bound_accounting_for_ending() {
+        let mut buffer = Bytes::from("$5\r\nh\r\n");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert_eq!(err, ParseError::Incomplete);
+    }
+
+    #[test]
+    fn it_returns_an_error_on_invalid_length_array() {
+        let mut buffer = Bytes::from("*2\r\n+hello\r\n");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert_eq!(err, ParseError::Incomplete);
+        assert_eq!(buffer, Bytes::from("*2\r\n+hello\r\n"));
+    }
+
+    #[test]
+    fn it_leaves_a_partial_array_untouched() {
+        for partial in ["*2\r\n$3\r\nGET\r\n", "*2\r\n$3\r\nGET\r\n$3\r\nke", "*2\r"] {
+            let mut buffer = Bytes::from(partial);
+
+            let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+            assert_eq!(err, ParseError::Incomplete);
+            assert_eq!(buffer, Bytes::from(partial));
         }
+    }
+
+    #[test]
+    fn it_parses_a_partial_array_once_completed() -> Result<()> {
+        let mut buffer = Bytes::from("*2\r\n$3\r\nGET\r\n");
+        assert!(parse_frame(&mut buffer, &Limits::default()).is_err());
+
+        let mut buffer = Bytes::from([&buffer[..], b"$3\r\nkey\r\n+next\r\n"].concat());
+        let (value, rest) = parse_frame(&mut buffer, &Limits::default())?;
+        assert_eq!(
+            value,
+            Value::array(vec![
+                Value::bulk(Bytes::from("GET")),
+                Value::bulk(Bytes::from("key"))
+            ])
+        );
+        assert_eq!(rest, Bytes::from("+next\r\n"));
+        assert_eq!(buffer, rest);
 
         Ok(())
     }
 
     #[test]
-    fn it_returns_an_error_on_empty_input() -> Result<()> {
-        let mut buffer = Bytes::from("");
-        match parse_resp(&mut buffer)? {
-            (Value::Error(err), rest) => {
-                assert_eq!(err, "empty buffer");
+    fn it_reports_every_prefix_of_a_frame_as_incomplete() -> Result<()> {
+        for frame in [
+            "*2\r\n$4\r\nECHO\r\n$5\r\nhello\r\n",
+            "+OK\r\n",
+            ":-42\r\n",
+            "$-1\r\n",
+            "%1\r\n+key\r\n#t\r\n",
+            ",3.14\r\n",
+            "(123456789012345678901234567890\r\n",
+            "=7\r\ntxt:abc\r\n",
+            "PING hello\r\n",
+        ] {
+            for end in 0..frame.len() {
+                let mut buffer = Bytes::from(&frame[..end]);
+                assert_eq!(
+                    parse_frame(&mut buffer, &Limits::default()),
+                    Err(ParseError::Incomplete),
+                    "prefix {:?}",
+                    &frame[..end]
+                );
+            }
+
+            let (_, rest) = parse_frame(&mut Bytes::from(frame), &Limits::default())?;
+            assert!(rest.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_measures_frames_like_it_parses_them() -> Result<()> {
+        for frame in [
+            "*2\r\n$4\r\nECHO\r\n$5\r\nhello\r\n",
+            "+OK\r\n",
+            "$-1\r\n",
+            "*-1\r\n",
+            "%1\r\n+key\r\n#t\r\n",
+            "=7\r\ntxt:abc\r\n",
+            "PING hello\r\n",
+        ] {
+            let buffer = Bytes::from(format!("{}+next\r\n", frame));
+            assert_eq!(frame_len(&buffer, &Limits::default()), Ok(frame.len()));
+
+            for end in 0..frame.len() {
+                assert_eq!(
+                    frame_len(&buffer[..end], &Limits::default()),
+                    parse_frame(&mut buffer.slice(..end), &Limits::default()).map(|_| end),
+                    "prefix {:?}",
+                    &frame[..end]
+                );
+            }
+        }
+
+        for malformed in ["*2\r\n:nope\r\n", "$100000000000\r\n", "*x\r\n", "~Foo\r\n"] {
+            let err = frame_len(malformed.as_bytes(), &Limits::default()).unwrap_err();
+            assert_eq!(
+                Err(err),
+                parse_frame(&mut Bytes::from(malformed), &Limits::default())
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_treats_a_malformed_frame_as_a_hard_error() {
+        let mut buffer = Bytes::from("*2\r\n:nope\r\n");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert!(matches!(err, ParseError::Protocol(_)));
+        assert_eq!(buffer, Bytes::from("*2\r\n:nope\r\n"));
+    }
+
+    #[test]
+    fn it_parses_an_inline_command() -> Result<()> {
+        let mut buffer = Bytes::from("ECHO hello\r\n+Foo\r\n");
+
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Array { len, elements }, rest) => {
+                assert_eq!(len, 2);
+                assert_eq!(
+                    elements,
+                    vec![
+                        Value::bulk(Bytes::from("ECHO")),
+                        Value::bulk(Bytes::from("hello"))
+                    ]
+                );
+                assert_eq!(rest, Bytes::from("+Foo\r\n"))
+            }
+            (kind, rest) => {
+                panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
+            }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_an_inline_command_with_repeated_spaces() -> Result<()> {
+        let mut buffer = Bytes::from("SET  foo   bar\r\n");
+
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Array { len, elements }, rest) => {
+                assert_eq!(len, 3);
+                assert_eq!(
+                    elements,
+                    vec![
+                        Value::bulk(Bytes::from("SET")),
+                        Value::bulk(Bytes::from("foo")),
+                        Value::bulk(Bytes::from("bar"))
+                    ]
+                );
                 assert_eq!(rest, Bytes::from(""))
             }
             (kind, rest) => {
                 panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
             }
-        }
+        };
 
         Ok(())
     }
 
     #[test]
-    fn it_returns_an_error_on_unknown_kind() -> Result<()> {
-        let mut buffer = Bytes::from(")Foo\r\n");
-        match parse_resp(&mut buffer)? {
-            (Value::Error(err), rest) => {
-                assert_eq!(err, "parsing failed, unknown kind: ')'");
-                assert_eq!(rest, Bytes::from("Foo\r\n"))
+    fn it_parses_inline_commands_not_starting_with_a_letter() -> Result<()> {
+        let mut buffer = Bytes::from("  ping\r\n");
+        assert_eq!(frame_len(&buffer, &Limits::default()), Ok(buffer.len()));
+
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Array { elements, .. }, rest) => {
+                assert_eq!(elements, vec![Value::bulk(Bytes::from("ping"))]);
+                assert_eq!(rest, Bytes::from(""))
             }
             (kind, rest) => {
                 panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
             }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_skips_blank_lines_before_a_command() -> Result<()> {
+        for input in ["\r\n \r\nPING\r\n", "\r\n*1\r\n$4\r\nPING\r\n"] {
+            let mut buffer = Bytes::from(input);
+            assert_eq!(frame_len(&buffer, &Limits::default()), Ok(input.len()));
+
+            match parse_frame(&mut buffer, &Limits::default())? {
+                (Value::Array { elements, .. }, rest) => {
+                    assert_eq!(elements, vec![Value::bulk(Bytes::from("PING"))]);
+                    assert_eq!(rest, Bytes::from(""))
+                }
+                (kind, rest) => {
+                    panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
+                }
+            };
         }
 
+        let mut buffer = Bytes::from("\r\n");
+        assert_eq!(
+            frame_len(&buffer, &Limits::default()),
+            Err(ParseError::Incomplete)
+        );
+        assert_eq!(
+            parse_frame(&mut buffer, &Limits::default()).unwrap_err(),
+            ParseError::Incomplete
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_inline_commands_over_the_size_limit() {
+        let within = format!("ECHO {}", "a".repeat(MAX_INLINE_LEN - 5));
+        let mut buffer = Bytes::from(within.clone());
+        assert_eq!(
+            parse_frame(&mut buffer, &Limits::default()).unwrap_err(),
+            ParseError::Incomplete
+        );
+        let mut buffer = Bytes::from(format!("{}\r\n", within));
+        assert!(parse_frame(&mut buffer, &Limits::default()).is_ok());
+
+        let mut buffer = Bytes::from(format!("ECHO {}", "a".repeat(MAX_INLINE_LEN)));
+        let expected = || ParseError::Protocol("too big inline request".to_string());
+        assert_eq!(frame_len(&buffer, &Limits::default()), Err(expected()));
+        assert_eq!(
+            parse_frame(&mut buffer, &Limits::default()).unwrap_err(),
+            expected()
+        );
+    }
+
+    #[test]
+    fn it_parses_a_number() -> Result<()> {
+        let mut buffer = Bytes::from(":1000\r\n");
+
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Number(value), rest) => {
+                assert_eq!(value, 1000);
+                assert_eq!(rest, Bytes::from(""))
+            }
+            (kind, rest) => {
+                panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
+            }
+        };
+
         Ok(())
     }
+
+    #[test]
+    fn it_parses_a_negative_number() -> Result<()> {
+        let mut buffer = Bytes::from(":-1000\r\n");
+
+        match parse_frame(&mut buffer, &Limits::default())? {
+            (Value::Number(value), rest) => {
+                assert_eq!(value, -1000);
+                assert_eq!(rest, Bytes::from(""))
+            }
+            (kind, rest) => {
+                panic!("unexpected kind: {:?} read_bytes: {:?}", kind, rest)
+            }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_returns_an_error_on_invalid_number() {
+        let mut buffer = Bytes::from(":notanumber\r\n");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::Protocol(
+                "number parsing failed, invalid integer: 'notanumber'".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn it_returns_an_error_on_missing_crlf() {
+        let mut buffer = Bytes::from("+Test");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert_eq!(err, ParseError::Incomplete);
+        assert_eq!(buffer, Bytes::from("+Test"));
+    }
+
+    #[test]
+    fn it_returns_an_error_on_empty_input() {
+        let mut buffer = Bytes::from("");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert_eq!(err, ParseError::Incomplete);
+        assert_eq!(buffer, Bytes::from(""));
+    }
+
+    #[test]
+    fn it_returns_an_error_on_unknown_kind() {
+        let mut buffer = Bytes::from("~Foo\r\n");
+
+        let err = parse_frame(&mut buffer, &Limits::default()).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::Protocol("parsing failed, unknown kind: '~'".to_string())
+        );
+        assert_eq!(buffer, Bytes::from("~Foo\r\n"));
+    }
 }
@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::db::Db;
+use crate::pubsub::PubSub;
+use crate::replication::Replication;
+
+/// How long clients still connected get to finish once the server is asked
+/// to stop.
+pub const SHUTDOWN_DRAIN_PERIOD: Duration = Duration::from_secs(1);
+
+/// How often the clients still connected are counted while draining.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What CLIENT LIST reports about a connected client.
+pub struct ClientInfo {
+    /// Address of the client's end of the connection, empty if unknown.
+    pub addr: String,
+    pub name: String,
+    pub connected_at: Instant,
+}
+
+/// The state shared by every client connection.
+#[derive(Clone)]
+pub struct Server {
+    pub dbs: Arc<Vec<Db>>,
+    pub config: Arc<RwLock<Config>>,
+    pub pubsub: PubSub,
+    pub replication: Arc<RwLock<Replication>>,
+    /// When the server started, for the uptime reported by INFO.
+    pub started_at: Instant,
+    /// Whether expired keys are evicted in the background, which DEBUG
+    /// SET-ACTIVE-EXPIRE toggles.
+    pub active_expire: Arc<AtomicBool>,
+    /// Id given to the last client that connected.
+    last_client_id: Arc<AtomicU64>,
+    /// The connected clients, by id.
+    pub clients: Arc<Mutex<BTreeMap<u64, ClientInfo>>>,
+}
+
+impl Server {
+    pub fn new(dbs: Arc<Vec<Db>>, config: Config) -> Self {
+        Server {
+            dbs,
+            config: Arc::new(RwLock::new(config)),
+            pubsub: PubSub::default(),
+            replication: Arc::new(RwLock::new(Replication::new())),
+            started_at: Instant::now(),
+            active_expire: Arc::new(AtomicBool::new(true)),
+            last_client_id: Arc::default(),
+            clients: Arc::default(),
+        }
+    }
+
+    /// Hands out the id of a new client. Ids start at 1 and are never reused.
+    pub fn next_client_id(&self) -> u64 {
+        self.last_client_id.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Waits for every client to disconnect, for up to `timeout`. Returns
+    /// whether they all did.
+    pub async fn drain_clients(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !self.clients.lock().unwrap().is_empty() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::delay_for(DRAIN_POLL_INTERVAL).await;
+        }
+        true
+    }
+}
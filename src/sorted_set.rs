@@ -0,0 +1,305 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+
+use bytes::Bytes;
+
+/// A score that can be ordered. Scores are never NaN, which is rejected when
+/// they are parsed, so the total order matches the numeric one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Score(f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// One end of a score range, as given to ZRANGEBYSCORE.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScoreBound {
+    Inclusive(f64),
+    Exclusive(f64),
+}
+
+impl ScoreBound {
+    fn is_above(&self, score: f64) -> bool {
+        match *self {
+            ScoreBound::Inclusive(min) => score >= min,
+            ScoreBound::Exclusive(min) => score > min,
+        }
+    }
+
+    fn is_below(&self, score: f64) -> bool {
+        match *self {
+            ScoreBound::Inclusive(max) => score <= max,
+            ScoreBound::Exclusive(max) => score < max,
+        }
+    }
+}
+
+/// One end of a lexicographic range, as given to ZRANGEBYLEX.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexBound {
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+    /// `-`, below every member.
+    Min,
+    /// `+`, above every member.
+    Max,
+}
+
+impl LexBound {
+    fn is_above(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Inclusive(min) => member >= &min[..],
+            LexBound::Exclusive(min) => member > &min[..],
+            LexBound::Min => true,
+            LexBound::Max => false,
+        }
+    }
+
+    fn is_below(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Inclusive(max) => member <= &max[..],
+            LexBound::Exclusive(max) => member < &max[..],
+            LexBound::Min => false,
+            LexBound::Max => true,
+        }
+    }
+}
+
+/// Members with a score, kept ordered by score and then by member.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SortedSet {
+    scores: HashMap<Bytes, f64>,
+    ordered: BTreeSet<(Score, Bytes)>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        SortedSet::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &Bytes> {
+        self.scores.keys()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Sets the score of `member`, returning whether it was newly added.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.ordered.remove(&(Score(previous), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+
+        previous.is_none()
+    }
+
+    /// Returns the position of `member` in ascending order, if present.
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let (member, score) = self.scores.get_key_value(member)?;
+        Some(
+            self.ordered
+                .range(..(Score(*score), member.clone()))
+                .count(),
+        )
+    }
+
+    /// Returns the members and scores between the inclusive `start` and
+    /// `stop` ranks, in order.
+    pub fn range(&self, start: usize, stop: usize) -> Vec<(Bytes, f64)> {
+        self.ordered
+            .iter()
+            .skip(start)
+            .take(stop.saturating_sub(start) + 1)
+            .map(|(score, member)| (member.clone(), score.0))
+            .collect()
+    }
+
+    /// Returns the members and scores whose score lies between `min` and
+    /// `max`, in order.
+    pub fn range_by_score(&self, min: ScoreBound, max: ScoreBound) -> Vec<(Bytes, f64)> {
+        let (ScoreBound::Inclusive(lowest) | ScoreBound::Exclusive(lowest)) = min;
+        // The empty member sorts before any other with the same score.
+        let start = Bound::Included((Score(lowest), Bytes::new()));
+        self.ordered
+            .range((start, Bound::Unbounded))
+            .skip_while(|(score, _)| !min.is_above(score.0))
+            .take_while(|(score, _)| max.is_below(score.0))
+            .map(|(score, member)| (member.clone(), score.0))
+            .collect()
+    }
+
+    /// Returns the members between `min` and `max`, in order. Members are
+    /// only ordered lexicographically when they share the same score, as
+    /// ZRANGEBYLEX expects.
+    pub fn range_by_lex(&self, min: &LexBound, max: &LexBound) -> Vec<Bytes> {
+        self.ordered
+            .iter()
+            .skip_while(|(_, member)| !min.is_above(member))
+            .take_while(|(_, member)| max.is_below(member))
+            .map(|(_, member)| member.clone())
+            .collect()
+    }
+
+    /// Removes `member`, returning whether it was present.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove_entry(member) {
+            Some((member, score)) => {
+                self.ordered.remove(&(Score(score), member));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LexBound, ScoreBound, SortedSet};
+
+    use bytes::Bytes;
+
+    #[test]
+    fn it_adds_members_with_scores() {
+        let mut set = SortedSet::new();
+
+        assert!(set.insert(Bytes::from("a"), 1.0));
+        assert!(set.insert(Bytes::from("b"), 2.0));
+
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.score(b"a"), Some(1.0));
+        assert_eq!(set.score(b"missing"), None);
+    }
+
+    #[test]
+    fn it_rescores_an_existing_member() {
+        let mut set = SortedSet::new();
+        set.insert(Bytes::from("a"), 1.0);
+
+        assert!(!set.insert(Bytes::from("a"), 5.0));
+
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.score(b"a"), Some(5.0));
+        assert_eq!(set.ordered.len(), 1);
+    }
+
+    #[test]
+    fn it_removes_members() {
+        let mut set = SortedSet::new();
+        set.insert(Bytes::from("a"), 1.0);
+
+        assert!(set.remove(b"a"));
+        assert!(!set.remove(b"a"));
+        assert!(set.is_empty());
+        assert!(set.ordered.is_empty());
+    }
+
+    #[test]
+    fn it_ranks_members_in_order() {
+        let mut set = SortedSet::new();
+        set.insert(Bytes::from("b"), 2.0);
+        set.insert(Bytes::from("a"), 1.0);
+        set.insert(Bytes::from("c"), 2.0);
+
+        assert_eq!(set.rank(b"a"), Some(0));
+        assert_eq!(set.rank(b"b"), Some(1));
+        assert_eq!(set.rank(b"c"), Some(2));
+        assert_eq!(set.rank(b"missing"), None);
+    }
+
+    #[test]
+    fn it_orders_by_score_then_member() {
+        let mut set = SortedSet::new();
+        set.insert(Bytes::from("c"), 1.0);
+        set.insert(Bytes::from("b"), 1.0);
+        set.insert(Bytes::from("a"), 2.0);
+
+        assert_eq!(
+            set.range(0, 5),
+            vec![
+                (Bytes::from("b"), 1.0),
+                (Bytes::from("c"), 1.0),
+                (Bytes::from("a"), 2.0)
+            ]
+        );
+        assert_eq!(set.range(1, 1), vec![(Bytes::from("c"), 1.0)]);
+    }
+
+    #[test]
+    fn it_ranges_by_score_with_exclusive_and_infinite_bounds() {
+        let mut set = SortedSet::new();
+        set.insert(Bytes::from("a"), 1.0);
+        set.insert(Bytes::from("b"), 2.0);
+        set.insert(Bytes::from("c"), 3.0);
+        let members = |range: Vec<(Bytes, f64)>| -> Vec<Bytes> {
+            range.into_iter().map(|(member, _)| member).collect()
+        };
+
+        assert_eq!(
+            members(set.range_by_score(
+                ScoreBound::Inclusive(f64::NEG_INFINITY),
+                ScoreBound::Inclusive(f64::INFINITY)
+            )),
+            vec![Bytes::from("a"), Bytes::from("b"), Bytes::from("c")]
+        );
+        assert_eq!(
+            members(set.range_by_score(ScoreBound::Exclusive(1.0), ScoreBound::Inclusive(3.0))),
+            vec![Bytes::from("b"), Bytes::from("c")]
+        );
+        assert_eq!(
+            members(set.range_by_score(ScoreBound::Inclusive(1.0), ScoreBound::Exclusive(3.0))),
+            vec![Bytes::from("a"), Bytes::from("b")]
+        );
+        assert!(set
+            .range_by_score(ScoreBound::Exclusive(2.0), ScoreBound::Exclusive(3.0))
+            .is_empty());
+    }
+
+    #[test]
+    fn it_ranges_by_lex_with_exclusive_and_open_bounds() {
+        let mut set = SortedSet::new();
+        for member in &["a", "b", "c", "d"] {
+            set.insert(Bytes::from(*member), 0.0);
+        }
+        let bound = |member: &str| Bytes::from(member.to_string());
+
+        assert_eq!(set.range_by_lex(&LexBound::Min, &LexBound::Max).len(), 4);
+        assert_eq!(
+            set.range_by_lex(
+                &LexBound::Inclusive(bound("a")),
+                &LexBound::Inclusive(bound("c"))
+            ),
+            vec![bound("a"), bound("b"), bound("c")]
+        );
+        assert_eq!(
+            set.range_by_lex(
+                &LexBound::Exclusive(bound("a")),
+                &LexBound::Exclusive(bound("c"))
+            ),
+            vec![bound("b")]
+        );
+        assert!(set.range_by_lex(&LexBound::Max, &LexBound::Min).is_empty());
+    }
+}